@@ -0,0 +1,46 @@
+#!/usr/bin/env bash
+# Compares bench iterations per second across thread counts, between two
+# revisions - e.g. before and after the atomics change:
+#
+#     scripts/bench_threads.sh 49b4385 244847f
+#
+# Only worth running on a machine with at least as many cores as the highest
+# thread count, otherwise the threads just take turns on the same core.
+set -euo pipefail
+
+before=${1:?usage: $0 <before-rev> <after-rev> [game] [iterations] [episodes]}
+after=${2:?usage: $0 <before-rev> <after-rev> [game] [iterations] [episodes]}
+game=${3:-c4}
+iterations=${4:-20000}
+episodes=${5:-5}
+threads="1 2 4 8 16"
+
+root=$(git rev-parse --show-toplevel)
+work=$root/target/bench_threads
+
+build() {
+    local rev=$1
+    local dir=$work/$rev
+    if [ ! -d "$dir" ]; then
+        git -C "$root" worktree add --detach "$dir" "$rev" >/dev/null
+    fi
+    cargo build --release --quiet --bin bench --manifest-path "$dir/Cargo.toml" \
+        --target-dir "$work/target-$rev"
+    echo "$work/target-$rev/release/bench"
+}
+
+ips() {
+    "$1" "$game" -i "$iterations" -e "$episodes" -t "$2" \
+        | sed -n 's/^Average iterations per second: //p'
+}
+
+before_bin=$(build "$before")
+after_bin=$(build "$after")
+
+echo "$(nproc) cores, $game, $iterations iterations x $episodes episodes"
+printf "%-8s %16s %16s %8s\n" threads "$before" "$after" speedup
+for t in $threads; do
+    b=$(ips "$before_bin" "$t")
+    a=$(ips "$after_bin" "$t")
+    awk -v t="$t" -v b="$b" -v a="$a" 'BEGIN { printf "%-8s %16.0f %16.0f %7.2fx\n", t, b, a, a / b }'
+done
@@ -1,8 +1,5 @@
 //! Plays configurations of the MCTS against one another
-mod game;
-mod games;
-mod mon2y;
-mod test;
+use mon2y_rs::{game, games, mon2y};
 
 //use crate::mon2y::action_log::{Action, ActionLogEntry};
 use clap::Parser;
@@ -11,11 +8,15 @@ use game::Game;
 use games::Games;
 use games::{C4, CS, EBR, NT};
 use log::Record;
-use mon2y::game::{Action, Actor, State};
-use mon2y::{calculate_best_turn, BestTurnPolicy};
+use mon2y::dataset::DatasetWriter;
+use mon2y::game::{final_reward, Action, Actor, State};
+use mon2y::rng;
+use mon2y::weighted_random::random_game_action;
+use mon2y::{Search, SearchConfig};
 use rand::Rng;
 use serde::Deserialize;
 use std::io::Write;
+use std::path::PathBuf;
 use std::{fs, thread};
 
 #[derive(Debug, Parser)]
@@ -32,71 +33,116 @@ struct ArenaSettings {
     game: Games,
     episodes: usize,
     players: Vec<PlayerSettings>,
+    /// Seed for the first episode (each one after adds one), so episodes can
+    /// be replayed
+    seed: Option<u64>,
+    /// File to write every decision the MCTS players make to, as JSON Lines
+    dataset: Option<PathBuf>,
+    /// Move every player along a seat each episode, so none of them gets the
+    /// same seat every game - results are then shown for each seat too
+    #[serde(default)]
+    rotate_seats: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "type")]
 enum PlayerSettings {
     Random,
-    Mcts(MctsSettings),
+    Mcts(SearchConfig),
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct MctsSettings {
-    policy: BestTurnPolicy,
-    exploration_constant: Option<f64>,
-    iterations: usize,
-    time_limit: Option<f32>,
-    threads: Option<usize>,
-}
-
-fn run_episode<G: Game>(game: G, players: Vec<PlayerSettings>) -> Vec<f64> {
+///
+/// Play one episode. Everything random in it is drawn from streams derived
+/// from `seed`. The MCTS players' decisions are recorded in `dataset`, if
+/// there is one.
+///
+fn run_episode<G: Game>(
+    game: G,
+    players: Vec<PlayerSettings>,
+    seed: u64,
+    mut dataset: Option<&mut DatasetWriter>,
+) -> Vec<f64> {
+    rng::seed_thread(seed);
     let mut state = game.init_game();
+    let mut searches: Vec<Search<G::StateType, G::ActionType>> = players
+        .iter()
+        .enumerate()
+        .map(|(i, player)| match player {
+            PlayerSettings::Mcts(config) => {
+                Search::new(config.clone()).with_seed(rng::derive_seed(seed, i as u64))
+            }
+            _ => Search::default(),
+        })
+        .collect();
     while !state.terminal() {
         let actor = state.next_actor();
         match actor {
             Actor::Player(player) => {
+                for (i, player_settings) in players.iter().enumerate() {
+                    if let PlayerSettings::Mcts(config) = player_settings {
+                        if i != player as usize && config.ponder {
+                            searches[i].start_pondering(&state);
+                        }
+                    }
+                }
                 let action: G::ActionType = match players.get(player as usize) {
                     Some(PlayerSettings::Random) => {
                         let permitted_actions = state.permitted_actions();
-                        permitted_actions[rand::thread_rng().gen_range(0..permitted_actions.len())]
-                            .clone()
+                        permitted_actions
+                            [rng::with_rng(|rng| rng.gen_range(0..permitted_actions.len()))]
+                        .clone()
+                    }
+                    Some(PlayerSettings::Mcts(_)) => {
+                        let result = searches[player as usize]
+                            .calculate_best_turn(state.clone())
+                            .unwrap_or_else(|e| panic!("Invalid search config: {}", e));
+                        log::debug!("Player {} searched: {}", player, result);
+                        if let Some(dataset) = dataset.as_deref_mut() {
+                            dataset.record(seed, &state, player, &result);
+                        }
+                        result.action
                     }
-                    Some(PlayerSettings::Mcts(mcts_settings)) => calculate_best_turn(
-                        mcts_settings.iterations,
-                        match mcts_settings.time_limit {
-                            None => None,
-                            Some(time_limit) => {
-                                Some(std::time::Duration::from_secs_f32(time_limit))
-                            }
-                        },
-                        match mcts_settings.threads {
-                            None => 4,
-                            Some(thread) => thread,
-                        },
-                        state.clone(),
-                        mcts_settings.policy,
-                        match mcts_settings.exploration_constant {
-                            None => 2.0_f64.sqrt(),
-                            Some(constant) => constant,
-                        },
-                        false,
-                    ),
                     _ => todo!(),
                 };
                 log::debug!("Player {} plays {:?}", player, action);
                 state = action.execute(&state);
+                advance_searches(&mut searches, &players, &action);
             }
-            Actor::GameAction(actions) => {
-                //TODO: Use a weighted random (because the second variable is supposed to be the weight)
-                let action = actions[rand::thread_rng().gen_range(0..actions.len())]
-                    .0
-                    .clone();
+            Actor::GameAction(_) | Actor::SampledGameAction => {
+                let Some(action) = random_game_action(&state) else {
+                    log::warn!("No game action could be sampled - ending the game");
+                    break;
+                };
                 state = action.execute(&state);
+                advance_searches(&mut searches, &players, &action);
             }
         }
     }
-    state.reward()
+    let reward = final_reward(&state);
+    if let Some(dataset) = dataset {
+        dataset
+            .finish_episode(&reward)
+            .expect("Failed to write dataset");
+    }
+    reward
+}
+
+/// Let each search know which action was played, so players that reuse their
+/// tree can keep the subtree
+fn advance_searches<StateType, ActionType>(
+    searches: &mut [Search<StateType, ActionType>],
+    players: &[PlayerSettings],
+    action: &ActionType,
+) where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    for (search, player) in searches.iter_mut().zip(players.iter()) {
+        match player {
+            PlayerSettings::Mcts(config) if config.reuses_tree() => search.advance(action),
+            _ => search.reset(),
+        }
+    }
 }
 
 fn run_config(config_file: String) {
@@ -104,46 +150,83 @@ fn run_config(config_file: String) {
     let arena_settings: ArenaSettings =
         serde_json::from_str(&config_file).expect("Failed to parse config file");
 
-    let mut results = vec![(0.0, 0); arena_settings.players.len()];
+    let mut dataset = arena_settings
+        .dataset
+        .as_ref()
+        .map(|path| DatasetWriter::create(path).expect("Failed to create dataset"));
+    let player_count = arena_settings.players.len();
+    // Reward, wins and games for each player, in each seat
+    let mut results = vec![vec![(0.0, 0, 0); player_count]; player_count];
+    let first_seed = arena_settings.seed.unwrap_or_else(rand::random);
     for episode in 0..arena_settings.episodes {
-        log::info!("Starting episode {}", episode);
+        let seed = first_seed.wrapping_add(episode as u64);
+        log::info!("Starting episode {} (seed {})", episode, seed);
+        // Which player is in each seat
+        let shift = if arena_settings.rotate_seats {
+            episode % player_count
+        } else {
+            0
+        };
+        let seating: Vec<usize> = (0..player_count)
+            .map(|seat| (seat + player_count - shift) % player_count)
+            .collect();
+        let players: Vec<PlayerSettings> = seating
+            .iter()
+            .map(|&player| arena_settings.players[player].clone())
+            .collect();
         let result = match arena_settings.game {
-            Games::C4 => run_episode(C4, arena_settings.players.clone()),
+            Games::C4 => run_episode(C4, players, seed, dataset.as_mut()),
             Games::NT => run_episode(
                 NT {
-                    player_count: arena_settings.players.len() as u8,
+                    player_count: player_count as u8,
                 },
-                arena_settings.players.clone(),
+                players,
+                seed,
+                dataset.as_mut(),
             ),
             Games::CS => run_episode(
                 CS {
-                    player_count: arena_settings.players.len() as u8,
+                    player_count: player_count as u8,
                 },
-                arena_settings.players.clone(),
+                players,
+                seed,
+                dataset.as_mut(),
             ),
             Games::EBR => run_episode(
                 EBR {
-                    player_count: arena_settings.players.len() as u8,
+                    player_count: player_count as u8,
                 },
-                arena_settings.players.clone(),
+                players,
+                seed,
+                dataset.as_mut(),
             ),
         };
         let max_result = result
             .iter()
-            .map(|r| r)
             .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less));
-        for (i, r) in result.iter().enumerate() {
-            results[i].0 += *r;
+        for (seat, r) in result.iter().enumerate() {
+            let seat_result = &mut results[seating[seat]][seat];
+            seat_result.0 += *r;
             if Some(r) == max_result {
-                results[i].1 += 1;
+                seat_result.1 += 1;
             }
+            seat_result.2 += 1;
         }
     }
     println!();
     println!("{:?}", arena_settings);
+    println!("First seed: {}", first_seed);
     println!("Player\tReward\t%\tWins\t%");
-    let total: f64 = results.iter().map(|r| r.0 as f64).sum();
-    for (i, r) in results.iter().enumerate() {
+    let totals: Vec<(f64, usize)> = results
+        .iter()
+        .map(|seats| {
+            seats.iter().fold((0.0, 0), |(reward, wins), seat| {
+                (reward + seat.0, wins + seat.1)
+            })
+        })
+        .collect();
+    let total: f64 = totals.iter().map(|r| r.0).sum();
+    for (i, r) in totals.iter().enumerate() {
         println!(
             "{}\t{:?}\t{:>5.2}%\t{:?}\t{:>5.2}%",
             i + 1,
@@ -153,6 +236,22 @@ fn run_config(config_file: String) {
             (100.0 * r.1 as f64) / arena_settings.episodes as f64
         );
     }
+    if arena_settings.rotate_seats {
+        println!("Player\tSeat\tGames\tReward\tWins\t%");
+        for (i, seats) in results.iter().enumerate() {
+            for (seat, r) in seats.iter().enumerate() {
+                println!(
+                    "{}\t{}\t{}\t{:?}\t{:?}\t{:>5.2}%",
+                    i + 1,
+                    seat + 1,
+                    r.2,
+                    r.0,
+                    r.1,
+                    (100.0 * r.1 as f64) / r.2.max(1) as f64
+                );
+            }
+        }
+    }
 }
 
 fn main() {
@@ -1,52 +1,54 @@
 //! Benchmarks mon2y_rs by just taking the first turn and timing it
-mod game;
-mod games;
-mod mon2y;
-mod test;
+use mon2y_rs::{game, games, mon2y};
 
 use clap::Parser;
 use game::Game;
 use games::Games;
 use games::{C4, CS, EBR, NT};
-use mon2y::{calculate_best_turn, BestTurnPolicy};
-use std::time::Instant;
+use mon2y::rng;
+use mon2y::{Search, SearchConfig};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
+// Benchmarks default to a bigger search than a game turn
+#[command(mut_arg("iterations", |arg| arg.default_value("100000")))]
+#[command(mut_arg("threads", |arg| arg.default_value("8")))]
 struct Args {
     #[arg()]
     game: Games,
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
-    #[arg(short, long, default_value_t = 100000)]
-    iterations: usize,
-    #[arg(short, long, default_value_t = 8)]
-    threads: usize,
+    #[command(flatten)]
+    search: SearchConfig,
     #[arg(short, long, default_value_t = 10)]
     episodes: usize,
     #[arg(short, long, default_value_t = 3)]
     player_count: u8,
+    /// Seed for the first episode's search (each one after adds one)
+    #[arg(long)]
+    seed: Option<u64>,
 }
 
-fn run_benchmark<G: Game>(game: G, iterations: usize, thread_count: usize) -> f64 {
+fn run_benchmark<G: Game>(game: G, config: &SearchConfig, seed: Option<u64>) -> f64 {
     let state = game.init_game();
-    let start = Instant::now();
-    calculate_best_turn(
-        iterations,
-        None,
-        thread_count,
-        state,
-        BestTurnPolicy::MostVisits,
-        2.0_f64.sqrt(),
-        false,
-    );
-    let elapsed = start.elapsed();
-    let iterations_per_second = iterations as f64 / elapsed.as_secs_f64();
+    let search = Search::new(config.clone());
+    let mut search = match seed {
+        Some(seed) => search.with_seed(seed),
+        None => search,
+    };
+    let result = search
+        .calculate_best_turn(state)
+        .unwrap_or_else(|e| panic!("Invalid search config: {}", e));
+    let elapsed = result.elapsed;
+    let iterations_per_second = result.iterations as f64 / elapsed.as_secs_f64();
     println!(
-        "{} iterations in {:.2} seconds ({:.2} iterations per second)",
-        iterations,
+        "{} iterations in {:.2} seconds ({:.2} iterations per second, {} nodes, {} bytes, depth {})",
+        result.iterations,
         &elapsed.as_secs_f64(),
-        iterations_per_second
+        iterations_per_second,
+        result.node_count,
+        result.used_bytes,
+        result.max_depth
     );
     elapsed.as_secs_f64()
 }
@@ -54,8 +56,12 @@ fn run_benchmark<G: Game>(game: G, iterations: usize, thread_count: usize) -> f6
 fn main() {
     let args = Args::parse();
     println!(
-        "===\nIterations: {}, Episodes: {}, Threads: {}",
-        args.iterations, args.episodes, args.threads
+        "===\nIterations: {}, Episodes: {}, Threads: {}, Virtual loss: {}, Parallelism: {}",
+        args.search.iterations,
+        args.episodes,
+        args.search.threads,
+        args.search.virtual_loss,
+        args.search.parallelism
     );
     println!("---");
     env_logger::Builder::new()
@@ -63,29 +69,35 @@ fn main() {
         .init();
 
     let durations: Vec<f64> = (0..args.episodes)
-        .map(|_| match args.game {
-            Games::C4 => run_benchmark(C4, args.iterations, args.threads),
-            Games::NT => run_benchmark(
-                NT {
-                    player_count: args.player_count,
-                },
-                args.iterations,
-                args.threads,
-            ),
-            Games::CS => run_benchmark(
-                CS {
-                    player_count: args.player_count,
-                },
-                args.iterations,
-                args.threads,
-            ),
-            Games::EBR => run_benchmark(
-                EBR {
-                    player_count: args.player_count,
-                },
-                args.iterations,
-                args.threads,
-            ),
+        .map(|episode| {
+            let seed = args.seed.map(|seed| seed.wrapping_add(episode as u64));
+            if let Some(seed) = seed {
+                rng::seed_thread(seed);
+            }
+            match args.game {
+                Games::C4 => run_benchmark(C4, &args.search, seed),
+                Games::NT => run_benchmark(
+                    NT {
+                        player_count: args.player_count,
+                    },
+                    &args.search,
+                    seed,
+                ),
+                Games::CS => run_benchmark(
+                    CS {
+                        player_count: args.player_count,
+                    },
+                    &args.search,
+                    seed,
+                ),
+                Games::EBR => run_benchmark(
+                    EBR {
+                        player_count: args.player_count,
+                    },
+                    &args.search,
+                    seed,
+                ),
+            }
         })
         .collect();
     println!("---");
@@ -95,6 +107,6 @@ fn main() {
     );
     println!(
         "Average iterations per second: {}",
-        (args.episodes * args.iterations) as f64 / durations.iter().sum::<f64>()
+        (args.episodes * args.search.iterations) as f64 / durations.iter().sum::<f64>()
     );
 }
@@ -1,3 +1,5 @@
+use serde::Serialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io;
 
 use crate::game::Game;
@@ -123,13 +125,13 @@ fn check_for_win(board: &Vec<C4Cell>) -> CheckForWinResult {
     CheckForWinResult::Ongoing
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Hash, Serialize)]
 enum C4Cell {
     Empty,
     Filled(u8),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct C4State {
     board: Vec<C4Cell>,
     next_player: u8,
@@ -137,6 +139,56 @@ pub struct C4State {
     reward: Vec<f64>,
 }
 
+impl C4State {
+    /// Whether `player` dropping into `column` would win the game
+    fn wins_with(&self, player: u8, column: u8) -> bool {
+        let state = C4State {
+            next_player: player,
+            ..self.clone()
+        };
+        let next_state = C4Action::Drop(column).execute(&state);
+        next_state.terminal && next_state.reward[player as usize] > 0.0
+    }
+
+    ///
+    /// Lines of four with three of `player`'s pieces and the fourth cell
+    /// empty - each is a win waiting to happen
+    ///
+    fn open_threes(&self, player: u8) -> usize {
+        let cell = |row: isize, column: isize| -> Option<C4Cell> {
+            if (0..BOARD_HEIGHT as isize).contains(&row)
+                && (0..BOARD_WIDTH as isize).contains(&column)
+            {
+                Some(self.board[row as usize * BOARD_WIDTH + column as usize])
+            } else {
+                None
+            }
+        };
+        let mut count = 0;
+        for row in 0..BOARD_HEIGHT as isize {
+            for column in 0..BOARD_WIDTH as isize {
+                for (row_step, column_step) in [(0, 1), (1, 0), (1, 1), (-1, 1)] {
+                    let line: Option<Vec<C4Cell>> = (0..4)
+                        .map(|i| cell(row + i * row_step, column + i * column_step))
+                        .collect();
+                    let Some(line) = line else {
+                        continue;
+                    };
+                    let mine = line
+                        .iter()
+                        .filter(|&&c| c == C4Cell::Filled(player))
+                        .count();
+                    let empty = line.iter().filter(|&&c| c == C4Cell::Empty).count();
+                    if mine == 3 && empty == 1 {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+}
+
 impl State for C4State {
     type ActionType = C4Action;
     fn permitted_actions(&self) -> Vec<Self::ActionType> {
@@ -155,6 +207,95 @@ impl State for C4State {
     fn reward(&self) -> Vec<f64> {
         self.reward.clone()
     }
+
+    fn reward_range(&self) -> Option<(f64, f64)> {
+        Some((-1.0, 1.0))
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.board.capacity() * std::mem::size_of::<C4Cell>()
+            + self.reward.capacity() * std::mem::size_of::<f64>()
+    }
+
+    fn playout_heuristic(&self, action: &C4Action) -> f64 {
+        let C4Action::Drop(column) = action;
+        if self.wins_with(self.next_player, *column) {
+            2.0
+        } else if self.wins_with((self.next_player + 1) % 2, *column) {
+            1.0
+        } else {
+            // Prefer the centre
+            let centre = (BOARD_WIDTH / 2) as f64;
+            (centre - (*column as f64 - centre).abs()) / 10.0
+        }
+    }
+
+    fn action_priors(&self, actions: &[C4Action]) -> Option<Vec<f64>> {
+        // Centre columns are part of more lines
+        let centre = (BOARD_WIDTH / 2) as f64;
+        Some(
+            actions
+                .iter()
+                .map(|C4Action::Drop(column)| centre + 1.0 - (*column as f64 - centre).abs())
+                .collect(),
+        )
+    }
+
+    fn features(&self) -> Option<Vec<f64>> {
+        // A plane for the mover's pieces, then one for their opponent's
+        let plane = |player: u8| {
+            self.board
+                .iter()
+                .map(move |cell| (*cell == C4Cell::Filled(player)) as u8 as f64)
+        };
+        Some(
+            plane(self.next_player)
+                .chain(plane((self.next_player + 1) % 2))
+                .collect(),
+        )
+    }
+
+    fn action_index(&self, action: &C4Action) -> Option<usize> {
+        let C4Action::Drop(column) = action;
+        Some(*column as usize)
+    }
+
+    fn action_slots(&self) -> usize {
+        BOARD_WIDTH
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+
+    fn heavy_playout_action(&self, actions: &[C4Action]) -> Option<C4Action> {
+        // Take a win, otherwise block a loss
+        let opponent = (self.next_player + 1) % 2;
+        actions
+            .iter()
+            .find(|C4Action::Drop(column)| self.wins_with(self.next_player, *column))
+            .or_else(|| {
+                actions
+                    .iter()
+                    .find(|C4Action::Drop(column)| self.wins_with(opponent, *column))
+            })
+            .copied()
+    }
+
+    fn evaluate(&self) -> Option<Vec<f64>> {
+        // Whoever has more open threes is ahead - squashed to stay short of
+        // the reward for a win
+        let lead = self.open_threes(0) as f64 - self.open_threes(1) as f64;
+        let value = (lead / 2.0).tanh();
+        Some(vec![value, -value])
+    }
+
+    fn state_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.board.hash(&mut hasher);
+        self.next_player.hash(&mut hasher);
+        Some(hasher.finish())
+    }
 }
 
 pub struct C4;
@@ -1,12 +1,13 @@
 // src/games/cs.rs
 use linked_hash_set::LinkedHashSet;
+use serde::Serialize;
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
-use std::sync::LazyLock;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, LazyLock};
 
 use crate::game::Game;
-use crate::mon2y::game::{Action, Actor, State};
+use crate::mon2y::game::{hash_map_bytes, hash_set_bytes, Action, Actor, State};
 
 /// Column lengths in the game
 static COLUMNS: LazyLock<HashMap<u8, u8>> = LazyLock::new(|| {
@@ -35,7 +36,8 @@ const COLUMN_COUNT: usize = 11;
 
 /// List of all dice actions from 4 d6s with weights
 /// (so - 1,1,1,1 is weighted 1 - because there's only 1 way to get that combo )
-static DICE_ACTIONS: LazyLock<Vec<(CSAction, u32)>> = LazyLock::new(|| {
+/// Shared, so every roll hands out the same list rather than a copy
+static DICE_ACTIONS: LazyLock<Arc<[(CSAction, u32)]>> = LazyLock::new(|| {
     let mut actions_and_weights: HashMap<CSAction, u32> = HashMap::new();
     for d1 in 1..=6 {
         for d2 in 1..=6 {
@@ -50,17 +52,23 @@ static DICE_ACTIONS: LazyLock<Vec<(CSAction, u32)>> = LazyLock::new(|| {
             }
         }
     }
-    actions_and_weights
+    let mut actions_and_weights: Vec<(CSAction, u32)> = actions_and_weights
         .iter()
         .map(|(action, weight)| (*action, *weight))
-        .collect()
+        .collect();
+    // In a fixed order, so seeded games can be replayed
+    actions_and_weights.sort_by_key(|(action, _)| match action {
+        CSAction::DiceRoll(d1, d2, d3, d4) => (*d1, *d2, *d3, *d4),
+        _ => unreachable!(),
+    });
+    actions_and_weights.into()
 });
 // Python code to do almost what we're doing here
 // all_combos = [str(sorted(l)) for l in itertools.product([1,2,3,4,5,6],[1,2,3,4,5,6],[1,2,3,4,5,6],[1,2,3,4,5,6])]
 // set_combos = set(all_combos)
 // [(i,len([_ for _ in all_combos if _ == i])) for i in sorted(list(set_combos))]
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize)]
 pub enum CSAction {
     DiceRoll(u8, u8, u8, u8),
     Move(u8, Option<u8>),
@@ -80,7 +88,7 @@ impl Action for CSAction {
                     new_state.next_player = (state.next_player + 1) % new_state.player_count;
                     new_state.locked_in_columns.clear();
                     new_state.temp_position = TEMPORARY_INIT.clone();
-                    new_state.next_actor = Actor::GameAction(DICE_ACTIONS.to_vec());
+                    new_state.next_actor = Actor::GameAction(DICE_ACTIONS.clone());
                 } else {
                     new_state.next_actor = Actor::Player(new_state.next_player);
                 }
@@ -138,7 +146,7 @@ impl Action for CSAction {
 type PlayerID = u8;
 type ColumnID = u8;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CSState {
     next_actor: Actor<CSAction>,
     // 2 sources of truth here :s - temp_position Nones could be used too.
@@ -280,6 +288,61 @@ impl State for CSState {
         }
     }
 
+    fn reward_range(&self) -> Option<(f64, f64)> {
+        Some((0.0, 1.0))
+    }
+
+    fn heap_bytes(&self) -> usize {
+        hash_set_bytes(&self.locked_in_columns)
+            + self.positions.capacity() * std::mem::size_of::<[u8; COLUMN_COUNT]>()
+            + hash_map_bytes(&self.claimed_columns)
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+
+    fn evaluate(&self) -> Option<Vec<f64>> {
+        // How far each player is up their best three columns (claimed columns
+        // counting as all the way), so three claimed is a win. Progress that
+        // hasn't been banked with Done yet could still be lost, so it's left out.
+        Some(
+            (0..self.player_count)
+                .map(|player_id| {
+                    let mut progress: Vec<f64> = (2..=12)
+                        .map(|column| match self.claimed_columns.get(&column) {
+                            Some(Some(owner)) if *owner == player_id => 1.0,
+                            Some(_) => 0.0,
+                            None => {
+                                self.positions[player_id as usize][column as usize - 2] as f64
+                                    / COLUMNS[&column] as f64
+                            }
+                        })
+                        .collect();
+                    progress.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
+                    progress.iter().take(3).sum::<f64>() / 3.0
+                })
+                .collect(),
+        )
+    }
+
+    fn state_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.next_actor.hash(&mut hasher);
+        // Sorted, as the set and map iterate in no particular order
+        let mut locked_in_columns = self.locked_in_columns.iter().collect::<Vec<_>>();
+        locked_in_columns.sort_unstable();
+        locked_in_columns.hash(&mut hasher);
+        let mut claimed_columns = self.claimed_columns.iter().collect::<Vec<_>>();
+        claimed_columns.sort_unstable();
+        claimed_columns.hash(&mut hasher);
+        self.last_roll.hash(&mut hasher);
+        self.next_player.hash(&mut hasher);
+        self.positions.hash(&mut hasher);
+        self.temp_position.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
     fn terminal(&self) -> bool {
         self.player_claimed_count()
             .values()
@@ -336,6 +399,30 @@ impl Game for CS {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mon2y::ismcts::{InformationSetNode, InformationSetTree};
+    use std::sync::{Arc, RwLock};
+
+    fn has_dice_nodes(node: &Arc<RwLock<InformationSetNode<CSAction>>>) -> bool {
+        node.read().unwrap().children.iter().any(|(action, child)| {
+            matches!(action, CSAction::DiceRoll(..)) || has_dice_nodes(child)
+        })
+    }
+
+    #[test]
+    fn test_open_loop_keeps_no_dice_nodes() {
+        let game = CS { player_count: 2 };
+        let state = CSAction::DiceRoll(1, 2, 3, 4).execute(&game.init_game());
+
+        let closed_loop = InformationSetTree::new(state.clone(), 2.0_f64.sqrt());
+        let open_loop = InformationSetTree::new(state, 2.0_f64.sqrt()).with_open_loop();
+        for _ in 0..200 {
+            closed_loop.iterate();
+            open_loop.iterate();
+        }
+        assert!(has_dice_nodes(&closed_loop.root));
+        assert!(!has_dice_nodes(&open_loop.root));
+        assert_eq!(open_loop.root.read().unwrap().visit_count, 200);
+    }
 
     #[test]
     fn test_dice_actions_weights() {
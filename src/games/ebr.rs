@@ -1,11 +1,12 @@
 use log::warn;
+use serde::Serialize;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::hash::Hash;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::LazyLock;
 
 use crate::game::Game;
-use crate::mon2y::game::{Action, Actor, State};
+use crate::mon2y::game::{hash_map_bytes, Action, Actor, State};
 
 /*
 OK - here's the deal. This is to help me playtest something.
@@ -22,7 +23,7 @@ enum EndGameReason {
     Resources,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum ChoosableAction {
     BuildTrack,
     AuctionShare,
@@ -53,7 +54,7 @@ const ACTION_CUBE_INIT: ActionCubeSpaces = [
     false, false, false, false, false, true, true, true, false, false, true,
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 struct Bond {
     face_value: usize,
     coupon: usize,
@@ -89,7 +90,7 @@ const BONDS: [Bond; 7] = [
     },
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Serialize)]
 struct BondDetails {
     bond: Bond,
     deferred: bool,
@@ -120,7 +121,7 @@ enum FeatureType {
     Water2,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Serialize)]
 enum Company {
     EBRC,
     LW,
@@ -156,9 +157,9 @@ struct CompanyFixedDetails {
 
 type Coordinate = (usize, usize);
 
-static COMPANY_FIXED_DETAILS: LazyLock<HashMap<Company, CompanyFixedDetails>> =
+static COMPANY_FIXED_DETAILS: LazyLock<BTreeMap<Company, CompanyFixedDetails>> =
     LazyLock::new(|| {
-        let mut m = HashMap::new();
+        let mut m = BTreeMap::new();
         m.insert(
             Company::EBRC,
             CompanyFixedDetails {
@@ -240,7 +241,7 @@ static COMPANY_FIXED_DETAILS: LazyLock<HashMap<Company, CompanyFixedDetails>> =
     });
 
 const INITIAL_RESOURCE_CUBES: [Coordinate; 4] = [(2, 4), (2, 3), (3, 4), (3, 4)];
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 struct CompanyDetails {
     shares_held: usize,
     shares_remaining: usize,
@@ -526,7 +527,7 @@ const TAKE_DIVIDEND: usize = 1;
 const TAKE_TOWN_DELIVER_DIVIDEND: usize = 1;
 const TAKE_PORT_DELIVER_DIVIDEND: usize = 1;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize)]
 pub enum EBRAction {
     Bid(usize),
     Pass,
@@ -658,7 +659,7 @@ impl Action for EBRAction {
                                         _ => unreachable!(),
                                     },
                                     winning_bidder: None,
-                                    passed: HashSet::new(),
+                                    passed: BTreeSet::new(),
                                 }
                             }
                         } else {
@@ -715,7 +716,7 @@ impl Action for EBRAction {
                         current_bid: None,
                         lot: *company,
                         winning_bidder: None,
-                        passed: HashSet::new(),
+                        passed: BTreeSet::new(),
                     };
                 } else {
                     state.stage = Stage::ChoosePrivateStart(*company);
@@ -730,7 +731,7 @@ impl Action for EBRAction {
                     current_bid: None,
                     lot: *company,
                     winning_bidder: None,
-                    passed: HashSet::new(),
+                    passed: BTreeSet::new(),
                 };
                 if !state.track.iter().any(|t| t.location == *location && t.track_type == TrackType::Narrow) {
                 state.track.push(Track {
@@ -944,26 +945,26 @@ impl Action for EBRAction {
 
 type PlayerID = u8;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
 enum TrackType {
     CompanyOwned(Company),
     Narrow,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 struct Track {
     location: Coordinate,
     track_type: TrackType,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 enum Stage {
     Auction {
         initial_auction: bool,
         current_bid: Option<isize>,
         lot: Company,
         winning_bidder: Option<PlayerID>,
-        passed: HashSet<PlayerID>,
+        passed: BTreeSet<PlayerID>,
     },
     BuildTrack {
         company: Company,
@@ -984,7 +985,7 @@ enum Stage {
     ChooseMerge,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct EBRState {
     terminal: bool,
     next_actor: Actor<EBRAction>,
@@ -997,7 +998,7 @@ pub struct EBRState {
     action_cubes: ActionCubeSpaces,
     revenue: HashMap<Company, isize>,
     dividends_paid: usize,
-    company_details: HashMap<Company, CompanyDetails>,
+    company_details: BTreeMap<Company, CompanyDetails>,
     unissued_bonds: Vec<Bond>,
     resource_cubes: Vec<Coordinate>,
     narrow_gauge_remaining: usize,
@@ -1169,7 +1170,7 @@ impl EBRState {
             // All neighboring
             .map(|t| get_neighbors(t.location))
             .flatten()
-            .collect::<HashSet<Coordinate>>() // Unique
+            .collect::<BTreeSet<Coordinate>>() // Unique, in order
             .iter()
             .filter(|t| t.0 < WIDTH && t.1 < HEIGHT)
             .filter_map(|t| {
@@ -1381,9 +1382,9 @@ impl EBRState {
         track_terrain_revenue + track_feature_revenue - bond_interest as isize
     }
 
-    fn pay_dividend(&mut self) {
-        let rev_per_share = self
-            .company_details
+    /// What each company would pay per share if dividends were paid now
+    fn revenue_per_share(&self) -> HashMap<Company, isize> {
+        self.company_details
             .iter()
             .map(|c| {
                 (
@@ -1401,7 +1402,11 @@ impl EBRState {
                     },
                 )
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<HashMap<_, _>>()
+    }
+
+    fn pay_dividend(&mut self) {
+        let rev_per_share = self.revenue_per_share();
         self.next_actor = {
             let Actor::Player(actor) = self.next_actor else {
                 unreachable!()
@@ -1454,6 +1459,22 @@ impl EBRState {
             .count()
                 >= 2
     }
+
+    /// 1 for the most cash, -1 for the least, 0 for others
+    fn rank_by_cash(&self, player_cash: &HashMap<PlayerID, isize>) -> Vec<f64> {
+        let mut cash_rewards = vec![0f64; self.player_count as usize];
+        let mut sorted_cash: Vec<(u8, isize)> = player_cash
+            .iter()
+            .map(|(player, cash)| (*player, *cash))
+            .collect();
+        // Ties go to the lower player, rather than whichever the map lists first
+        sorted_cash.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        cash_rewards[sorted_cash[0].0 as usize] = 1f64;
+        if self.player_count > 1 {
+            cash_rewards[sorted_cash[self.player_count as usize - 1].0 as usize] = -1f64;
+        }
+        cash_rewards
+    }
 }
 
 impl State for EBRState {
@@ -1663,23 +1684,116 @@ impl State for EBRState {
         if !self.terminal {
             return vec![0f64; self.player_count as usize];
         }
-        let mut cash_rewards = vec![0f64; self.player_count as usize];
-        let mut sorted_cash: Vec<(u8, isize)> = self
+        self.rank_by_cash(&self.player_cash)
+    }
+
+    fn reward_range(&self) -> Option<(f64, f64)> {
+        Some((-1.0, 1.0))
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let holdings: usize = self
+            .holdings
+            .values()
+            .map(|companies| companies.capacity() * std::mem::size_of::<Company>())
+            .sum();
+        let company_details: usize = self
+            .company_details
+            .values()
+            .map(|details| {
+                details.bonds.capacity() * std::mem::size_of::<BondDetails>()
+                    + details.owned_privates.capacity() * std::mem::size_of::<Company>()
+            })
+            .sum();
+        self.track.capacity() * std::mem::size_of::<Track>()
+            + hash_map_bytes(&self.holdings)
+            + holdings
+            + hash_map_bytes(&self.player_cash)
+            + hash_map_bytes(&self.revenue)
+            // Not counting the B-tree's nodes' spare room
+            + self.company_details.len() * std::mem::size_of::<(Company, CompanyDetails)>()
+            + company_details
+            + self.unissued_bonds.capacity() * std::mem::size_of::<Bond>()
+            + self.resource_cubes.capacity() * std::mem::size_of::<Coordinate>()
+    }
+
+    fn evaluate(&self) -> Option<Vec<f64>> {
+        // Ranked on the cash players would have if every dividend left paid
+        // what it would now
+        let dividends_left = (FINAL_DIVIDEND_COUNT - self.dividends_paid) as isize;
+        let rev_per_share = self.revenue_per_share();
+        let projected_cash: HashMap<PlayerID, isize> = self
             .player_cash
             .iter()
-            .map(|(player, cash)| (*player, *cash))
+            .map(|(player, cash)| {
+                (
+                    *player,
+                    cash + dividends_left
+                        * self.holdings[player]
+                            .iter()
+                            .map(|company| rev_per_share[company])
+                            .sum::<isize>(),
+                )
+            })
             .collect();
-        sorted_cash.sort_by(|a, b| b.1.cmp(&a.1));
-        cash_rewards[sorted_cash[0].0 as usize] = 1f64;
-        if self.player_count > 1 {
-            cash_rewards[sorted_cash[self.player_count as usize - 1].0 as usize] = -1f64;
-        }
-        cash_rewards
+        Some(self.rank_by_cash(&projected_cash))
     }
 
     fn terminal(&self) -> bool {
         self.terminal
     }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+
+    fn state_hash(&self) -> Option<u64> {
+        // Maps (and the order track was built in) are sorted, so that the same
+        // position reached in a different order hashes the same.
+        let mut hasher = DefaultHasher::new();
+        self.terminal.hash(&mut hasher);
+        match &self.next_actor {
+            Actor::Player(player) => Some(*player).hash(&mut hasher),
+            Actor::GameAction(_) | Actor::SampledGameAction => {
+                None::<PlayerID>.hash(&mut hasher)
+            }
+        }
+        self.active_player.hash(&mut hasher);
+        let mut track: Vec<(Coordinate, &TrackType)> = self
+            .track
+            .iter()
+            .map(|t| (t.location, &t.track_type))
+            .collect();
+        track.sort();
+        track.hash(&mut hasher);
+        self.stage.hash(&mut hasher);
+        let holdings: BTreeMap<PlayerID, BTreeSet<Company>> = self
+            .holdings
+            .iter()
+            .map(|(player, companies)| (*player, companies.iter().copied().collect()))
+            .collect();
+        holdings.hash(&mut hasher);
+        self.player_cash
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(&mut hasher);
+        self.action_cubes.hash(&mut hasher);
+        self.revenue
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(&mut hasher);
+        self.dividends_paid.hash(&mut hasher);
+        self.company_details
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .hash(&mut hasher);
+        self.unissued_bonds.hash(&mut hasher);
+        let mut resource_cubes = self.resource_cubes.clone();
+        resource_cubes.sort();
+        resource_cubes.hash(&mut hasher);
+        self.narrow_gauge_remaining.hash(&mut hasher);
+        Some(hasher.finish())
+    }
 }
 
 pub struct EBR {
@@ -1702,7 +1816,7 @@ impl Game for EBR {
                 current_bid: None,
                 lot: Company::LW,
                 winning_bidder: None,
-                passed: HashSet::new(),
+                passed: BTreeSet::new(),
             },
             holdings: (0..self.player_count)
                 .map(|i| (i, Vec::new()))
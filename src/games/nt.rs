@@ -1,8 +1,13 @@
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
 
 use crate::game::Game;
-use crate::mon2y::game::{Action, Actor, State};
+use crate::mon2y::game::{hash_map_bytes, Action, Actor, State};
+use crate::mon2y::rng::with_rng;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum NTAction {
@@ -59,13 +64,18 @@ impl Action for NTAction {
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize)]
 enum CardState<Actor> {
     Drawable,
     Taken(Actor),
+    /// Put aside face down at the start of the game
+    Removed,
 }
 
-#[derive(Clone)]
+/// How many cards are put aside at the start of the game
+const REMOVED_CARDS: usize = 9;
+
+#[derive(Clone, Serialize)]
 pub struct NTState {
     cards: HashMap<u8, CardState<u8>>,
     tokens: HashMap<u8, u8>,
@@ -77,10 +87,9 @@ pub struct NTState {
 
 impl NTState {
     fn scores(&self) -> Vec<f64> {
-        let mut scores = self
-            .tokens
-            .iter()
-            .map(|(_, tokens)| -1.0 * *tokens as f64)
+        // Indexed by player - not in the map's order
+        let mut scores = (0..self.tokens.len() as u8)
+            .map(|player| -(self.tokens[&player] as f64))
             .collect::<Vec<_>>();
         // I know this could be functional, but maybe later.
         for (card, card_state) in &self.cards {
@@ -103,7 +112,8 @@ impl State for NTState {
     fn next_actor(&self) -> Actor<NTAction> {
         match self.to_draw {
             false => Actor::Player(self.next_player),
-            true => Actor::GameAction(self.possible_non_player_actions()),
+            // Too many cards to be worth listing - a search keeps a sample
+            true => Actor::SampledGameAction,
         }
     }
 
@@ -116,19 +126,32 @@ impl State for NTState {
     }
 
     fn possible_non_player_actions(&self) -> Vec<(Self::ActionType, u32)> {
-        self.cards
+        let mut draws: Vec<u8> = self
+            .cards
             .iter()
             .filter(|(_, card_state)| matches!(card_state, CardState::Drawable))
-            .map(|(card, _)| (NTAction::Draw(*card), 1))
+            .map(|(card, _)| *card)
+            .collect();
+        // In card order, rather than the map's (which changes from run to run)
+        draws.sort_unstable();
+        draws
+            .into_iter()
+            .map(|card| (NTAction::Draw(card), 1))
             .collect()
     }
 
+    fn sample_game_action(&self, rng: &mut dyn RngCore) -> Option<(NTAction, Option<f64>)> {
+        // Any card left in the deck is as likely as any other
+        let draws = self.possible_non_player_actions();
+        let (action, _) = draws.choose(rng)?;
+        Some((*action, Some(1.0 / draws.len() as f64)))
+    }
+
     fn terminal(&self) -> bool {
+        // Over once every card that wasn't removed has been taken
         self.cards
-            .iter()
-            .filter(|(_, card_state)| matches!(card_state, CardState::Taken(_)))
-            .count()
-            >= 24
+            .values()
+            .all(|card_state| !matches!(card_state, CardState::Drawable))
     }
 
     fn reward(&self) -> Vec<f64> {
@@ -150,6 +173,106 @@ impl State for NTState {
         log::trace!("Reward: {:?}", reward);
         reward
     }
+
+    /// Last place gets 0 and first gets 1 - with an extra 1 for player 0
+    fn reward_range(&self) -> Option<(f64, f64)> {
+        Some((0.0, 2.0))
+    }
+
+    fn heap_bytes(&self) -> usize {
+        hash_map_bytes(&self.cards) + hash_map_bytes(&self.tokens)
+    }
+
+    fn action_priors(&self, actions: &[NTAction]) -> Option<Vec<f64>> {
+        let card = self.current_card?;
+        // Taking costs the card (unless it extends a run), less the tokens on it
+        let extends_run = [card - 1, card + 1].iter().any(|neighbour| {
+            matches!(self.cards.get(neighbour), Some(CardState::Taken(owner)) if *owner == self.next_player)
+        });
+        let cost = if extends_run { 0.0 } else { card as f64 } - self.tokens_on_card as f64;
+        let take = 1.0 / (1.0 + (cost / 5.0).exp());
+        Some(
+            actions
+                .iter()
+                .map(|action| match action {
+                    NTAction::Take => take,
+                    NTAction::NoThanks => 1.0 - take,
+                    NTAction::Draw(_) => 0.0,
+                })
+                .collect(),
+        )
+    }
+
+    fn evaluate(&self) -> Option<Vec<f64>> {
+        // Ranked on the scores so far, as if the game ended now
+        Some(self.reward())
+    }
+
+    fn state_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        // Sorted, as the maps iterate in no particular order
+        let mut cards = self.cards.iter().collect::<Vec<_>>();
+        cards.sort_unstable_by_key(|(card, _)| **card);
+        cards.hash(&mut hasher);
+        let mut tokens = self.tokens.iter().collect::<Vec<_>>();
+        tokens.sort_unstable();
+        tokens.hash(&mut hasher);
+        self.next_player.hash(&mut hasher);
+        self.to_draw.hash(&mut hasher);
+        self.current_card.hash(&mut hasher);
+        self.tokens_on_card.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn to_json(&self) -> Option<serde_json::Value> {
+        // Nobody knows which cards were removed, so they're shown as still
+        // in the deck
+        let cards = self
+            .cards
+            .iter()
+            .map(|(card, card_state)| match card_state {
+                CardState::Removed => (*card, CardState::Drawable),
+                _ => (*card, card_state.clone()),
+            })
+            .collect();
+        serde_json::to_value(NTState {
+            cards,
+            ..self.clone()
+        })
+        .ok()
+    }
+
+    fn determinize(&self, _observer: u8) -> Self {
+        // Nobody knows which cards were removed - so shuffle the cards that
+        // haven't been seen yet, and remove as many again
+        let mut unseen: Vec<u8> = self
+            .cards
+            .iter()
+            .filter(|(card, card_state)| {
+                matches!(card_state, CardState::Drawable | CardState::Removed)
+                    && Some(**card) != self.current_card
+            })
+            .map(|(card, _)| *card)
+            .collect();
+        // Sorted first, so a seeded shuffle doesn't depend on the map's order
+        unseen.sort_unstable();
+        with_rng(|rng| unseen.shuffle(rng));
+        let mut cards = self.cards.clone();
+        for (i, card) in unseen.into_iter().enumerate() {
+            cards.insert(
+                card,
+                if i < REMOVED_CARDS {
+                    CardState::Removed
+                } else {
+                    CardState::Drawable
+                },
+            );
+        }
+        NTState {
+            cards,
+            ..self.clone()
+        }
+    }
 }
 
 pub struct NT {
@@ -192,9 +315,24 @@ impl Game for NT {
 
     fn init_game(&self) -> Self::StateType {
         NTState {
-            cards: (3..35)
-                .map(|card| (card, CardState::Drawable))
-                .collect::<HashMap<_, _>>(),
+            cards: {
+                let mut cards: Vec<u8> = (3..=35).collect();
+                with_rng(|rng| cards.shuffle(rng));
+                cards
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, card)| {
+                        (
+                            card,
+                            if i < REMOVED_CARDS {
+                                CardState::Removed
+                            } else {
+                                CardState::Drawable
+                            },
+                        )
+                    })
+                    .collect::<HashMap<_, _>>()
+            },
             tokens: (0..self.player_count)
                 .map(|player_id| {
                     (
@@ -214,3 +352,123 @@ impl Game for NT {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::{Search, SearchConfig};
+
+    fn removed_count(state: &NTState) -> usize {
+        state
+            .cards
+            .values()
+            .filter(|card_state| matches!(card_state, CardState::Removed))
+            .count()
+    }
+
+    fn taken(state: &NTState) -> HashMap<u8, u8> {
+        state
+            .cards
+            .iter()
+            .filter_map(|(card, card_state)| match card_state {
+                CardState::Taken(player) => Some((*card, *player)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_determinize_keeps_what_is_known() {
+        let game = NT { player_count: 3 };
+        let mut state = game.init_game();
+        assert_eq!(state.cards.len(), 33);
+        assert_eq!(removed_count(&state), 9);
+
+        let first_card = state.possible_non_player_actions()[0].0;
+        state = first_card.execute(&state);
+        state = NTAction::Take.execute(&state);
+        let second_card = state.possible_non_player_actions()[0].0;
+        state = second_card.execute(&state);
+
+        for _ in 0..20 {
+            let determinized = state.determinize(0);
+            assert_eq!(removed_count(&determinized), 9);
+            assert!(matches!(
+                determinized.cards[&state.current_card.unwrap()],
+                CardState::Drawable
+            ));
+            assert_eq!(taken(&determinized), taken(&state));
+        }
+    }
+
+    #[test]
+    fn test_plays_through_with_information_sets() {
+        let game = NT { player_count: 3 };
+        let mut state = game.init_game();
+        let mut search = Search::new(
+            SearchConfig::default()
+                .with_iterations(50)
+                .with_threads(2)
+                .with_information_sets(),
+        );
+        while !state.terminal() {
+            let action = match state.next_actor() {
+                Actor::Player(_) => search.calculate_best_turn(state.clone()).unwrap().action,
+                Actor::SampledGameAction => state.possible_non_player_actions()[0].0,
+                Actor::GameAction(_) => unreachable!(),
+            };
+            state = action.execute(&state);
+            search.advance(&action);
+        }
+        assert_eq!(removed_count(&state), 9);
+    }
+
+    #[test]
+    fn test_missed_advance_discards_tree() {
+        let game = NT { player_count: 3 };
+        let mut state = game.init_game();
+        let first_card = state.possible_non_player_actions()[0].0;
+        state = first_card.execute(&state);
+        let mut search = Search::new(SearchConfig::default().with_iterations(50).with_threads(1));
+        search.calculate_best_turn(state.clone()).unwrap();
+        let kept_visits = search.root_visit_count().unwrap();
+
+        // Round the table without advancing - the same player's to move, with
+        // the same choices, but the tokens have moved
+        let mut moved = state.clone();
+        for _ in 0..3 {
+            moved = NTAction::NoThanks.execute(&moved);
+        }
+        assert_eq!(moved.next_actor(), state.next_actor());
+        assert_eq!(moved.permitted_actions(), state.permitted_actions());
+        assert_ne!(moved.state_hash(), state.state_hash());
+        search.calculate_best_turn(moved).unwrap();
+        assert!(search.root_visit_count().unwrap() < kept_visits * 2);
+    }
+
+    #[test]
+    fn test_json_hides_removed_cards() {
+        let game = NT { player_count: 3 };
+        let state = game.init_game();
+        let json = state.to_json().unwrap();
+        let cards = json["cards"].as_object().unwrap();
+        assert_eq!(cards.len(), 33);
+        assert!(cards.values().all(|card_state| card_state == "Drawable"));
+    }
+
+    #[test]
+    fn test_priors_discourage_costly_takes() {
+        let game = NT { player_count: 3 };
+        let mut state = game.init_game();
+        state.current_card = Some(30);
+        state.to_draw = false;
+        state.tokens_on_card = 0;
+        let actions = state.permitted_actions();
+        let priors = state.action_priors(&actions).unwrap();
+        assert!(priors[0] < 0.01, "Take was {}", priors[0]);
+
+        state.tokens_on_card = 40;
+        let priors = state.action_priors(&actions).unwrap();
+        assert!(priors[0] > 0.8, "Take was {}", priors[0]);
+    }
+}
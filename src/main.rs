@@ -1,7 +1,4 @@
-mod game;
-mod games;
-mod mon2y;
-mod test;
+use mon2y_rs::{game, games, mon2y};
 
 //use crate::mon2y::action_log::{Action, ActionLogEntry};
 use clap::{Parser, ValueEnum};
@@ -10,10 +7,17 @@ use game::Game;
 use games::Games;
 use games::{C4, CS, EBR, NT};
 use log::Record;
-use mon2y::game::{Action, Actor, State};
-use mon2y::{calculate_best_turn, BestTurnPolicy};
+use mon2y::dataset::DatasetWriter;
+use mon2y::game::{final_reward, Action, Actor, State};
+use mon2y::rng;
+use mon2y::tree_export::{write_tree, ExportOptions, TreeFormat};
+use mon2y::weighted_random::random_game_action;
+use mon2y::{CancelToken, Search, SearchConfig, SearchHandle};
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use rand::Rng;
@@ -36,22 +40,67 @@ struct Args {
     players: Vec<PlayerType>,
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
-    #[arg(short, long, default_value_t = 10000)]
-    iterations: usize,
+    #[command(flatten)]
+    search: SearchConfig,
     #[arg(short, long, default_value_t = 1)]
     episodes: usize,
-    #[arg(short, long, default_value_t = 4)]
-    threads: usize,
     #[arg(short('I'), long, default_value_t = false)]
     inject_game_turns: bool,
-    #[arg(short('T'), long)]
-    limit_time: Option<f32>,
-    #[arg(short('P'), long, default_value_t=BestTurnPolicy::MostVisits)]
-    policy: BestTurnPolicy,
-    #[arg(short('c'), long, default_value_t = 1.4142135623730951)]
-    exploration_constant: f64,
+    /// Seed for the first episode (each one after adds one), so games can be replayed
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Show the best move so far every this many seconds while an MCTS player thinks
+    #[arg(long)]
+    show_eval: Option<f32>,
+    /// Pressing Enter stops the MCTS player that's thinking, so it plays its best move so
+    /// far (only without human players or injected game turns)
     #[arg(long, default_value_t = false)]
-    log_children: bool,
+    stop_on_enter: bool,
+    /// Write every decision the MCTS players make to this file, as JSON Lines, for training
+    /// models on
+    #[arg(long)]
+    dataset: Option<PathBuf>,
+    /// Write the tree of every MCTS search to this file (like --log-children) - as Graphviz
+    /// if it ends in .dot or .gv, otherwise as JSON Lines
+    #[arg(long)]
+    tree_out: Option<PathBuf>,
+    #[command(flatten)]
+    tree_export: ExportOptions,
+}
+
+/// The search that's running, for stopping it from another thread
+type Thinking = Arc<Mutex<Option<CancelToken>>>;
+
+/// Where the MCTS players' search trees are written
+struct TreeOut {
+    file: BufWriter<File>,
+    format: TreeFormat,
+    options: ExportOptions,
+    /// Searches written so far, to name the next one
+    searches: usize,
+}
+
+impl TreeOut {
+    /// Write the trees of `player`'s finished search
+    fn write<StateType, ActionType>(
+        &mut self,
+        handle: &SearchHandle<StateType, ActionType>,
+        seed: u64,
+        player: u8,
+    ) where
+        StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+        ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+    {
+        let trees = handle.export_trees(&self.options);
+        for (i, tree) in trees.iter().enumerate() {
+            let mut name = format!("Search {} (seed {} player {})", self.searches, seed, player);
+            if trees.len() > 1 {
+                name.push_str(&format!(" tree {}", i));
+            }
+            write_tree(&mut self.file, tree, &name, self.format).expect("Failed to write tree");
+        }
+        self.searches += 1;
+    }
 }
 
 /// Play a game of the given type with the given players.
@@ -65,86 +114,175 @@ struct Args {
 ///
 /// If `inject_game_turns` is true, the game will pause after each game action
 /// and ask the user to enter the index of the action to take.
+///
+/// Each MCTS player searches as `config` says.
+///
+/// Everything random in the game, and in each MCTS player's search, is drawn
+/// from streams derived from `seed`.
+///
+/// While an MCTS player thinks, its best move so far is shown every
+/// `show_eval` seconds, and its search is left in `thinking` so it can be
+/// stopped early.
+///
+/// Each MCTS player's decisions are recorded in `dataset`, and its trees
+/// written to `tree_out`, if there are ones.
+#[allow(clippy::too_many_arguments)]
 fn run_game<G: Game>(
     game: G,
     players: Vec<PlayerType>,
-    iterations: usize,
-    time_limit: Option<f32>,
-    threads: usize,
+    config: &SearchConfig,
     inject_game_turns: bool,
-    policy: BestTurnPolicy,
-    constant: f64,
-    log_children: bool,
+    seed: u64,
+    show_eval: Option<f32>,
+    thinking: Option<Thinking>,
+    mut dataset: Option<&mut DatasetWriter>,
+    mut tree_out: Option<&mut TreeOut>,
 ) {
+    let reuse_tree = config.reuses_tree();
+    rng::seed_thread(seed);
     let mut state = game.init_game();
+    let mut searches: Vec<Search<G::StateType, G::ActionType>> = players
+        .iter()
+        .enumerate()
+        .map(|(i, _)| Search::new(config.clone()).with_seed(rng::derive_seed(seed, i as u64)))
+        .collect();
     while !state.terminal() {
         let actor = state.next_actor();
         game.visualise_state(&state);
         match actor {
             Actor::Player(player) => {
+                if config.ponder {
+                    for (i, player_type) in players.iter().enumerate() {
+                        if i != player as usize && matches!(player_type, PlayerType::M) {
+                            searches[i].start_pondering(&state);
+                        }
+                    }
+                }
                 let action: G::ActionType = match players.get(player as usize) {
                     Some(PlayerType::H) => game.get_human_turn(&state),
                     Some(PlayerType::R) => {
                         let permitted_actions = state.permitted_actions();
-                        permitted_actions[rand::thread_rng().gen_range(0..permitted_actions.len())]
-                            .clone()
+                        permitted_actions
+                            [rng::with_rng(|rng| rng.gen_range(0..permitted_actions.len()))]
+                        .clone()
                     }
-                    Some(PlayerType::M) => calculate_best_turn(
-                        iterations,
-                        match time_limit {
-                            None => None,
-                            Some(time_limit) => {
-                                Some(std::time::Duration::from_secs_f32(time_limit))
+                    Some(PlayerType::M) => {
+                        let mut handle = searches[player as usize]
+                            .start_search(state.clone())
+                            .unwrap_or_else(|e| panic!("Invalid search config: {}", e));
+                        if let Some(thinking) = &thinking {
+                            *thinking.lock().unwrap() = Some(handle.cancel_token());
+                        }
+                        if let Some(show_eval) = show_eval {
+                            let interval = std::time::Duration::from_secs_f32(show_eval);
+                            let mut shown = std::time::Instant::now();
+                            while !handle.is_finished() {
+                                thread::sleep(std::time::Duration::from_millis(10));
+                                if shown.elapsed() >= interval {
+                                    println!("Player {} thinking: {}", player, handle.snapshot());
+                                    shown = std::time::Instant::now();
+                                }
                             }
-                        },
-                        threads,
-                        state.clone(),
-                        policy,
-                        constant,
-                        log_children,
-                    ),
+                        }
+                        if let Some(tree_out) = tree_out.as_deref_mut() {
+                            handle.wait();
+                            tree_out.write(&handle, seed, player);
+                        }
+                        let result = handle.finish();
+                        if let Some(thinking) = &thinking {
+                            *thinking.lock().unwrap() = None;
+                        }
+                        log::info!("Player {} searched: {}", player, result);
+                        if let Some(dataset) = dataset.as_deref_mut() {
+                            dataset.record(seed, &state, player, &result);
+                        }
+                        result.action
+                    }
                     _ => todo!(),
                 };
                 log::info!("Player {} plays {:?}", player, action);
                 state = action.execute(&state);
+                advance_searches(&mut searches, &action, reuse_tree);
             }
-            Actor::GameAction(actions) => {
-                if inject_game_turns {
-                    println!("GAME ACTION");
-                    let mut sorted_actions = actions.clone();
-                    sorted_actions.sort_by(|a, b| format!("{:?}", a.0).cmp(&format!("{:?}", b.0)));
-
-                    for (i, action) in sorted_actions.iter().enumerate() {
-                        println!("{} {:?} {}", i, action.0, action.1);
-                    }
-                    loop {
-                        let mut input = String::new();
-                        if io::stdin().read_line(&mut input).is_err() {
-                            println!("Failed to read line. Please try again.");
-                            continue;
-                        }
-                        let action = match input.trim().parse::<usize>() {
-                            Ok(action) => sorted_actions[action].clone(),
-                            Err(_) => {
-                                println!("Failed to parse action. Please enter a valid number.");
-                                continue;
-                            }
-                        };
-                        state = action.0.execute(&state);
-                        break;
-                    }
+            Actor::GameAction(_) | Actor::SampledGameAction => {
+                let action = if inject_game_turns {
+                    get_injected_game_action(&state)
                 } else {
-                    //TODO: Use a weighted random (because the second variable is supposed to be the weight)
-                    let action = actions[rand::thread_rng().gen_range(0..actions.len())]
-                        .0
-                        .clone();
-                    log::info!("Game Action {:?}", action);
-                    state = action.execute(&state);
-                }
+                    random_game_action(&state)
+                };
+                let Some(action) = action else {
+                    log::warn!("No game action could be sampled - ending the game");
+                    break;
+                };
+                log::info!("Game Action {:?}", action);
+                state = action.execute(&state);
+                advance_searches(&mut searches, &action, reuse_tree);
             }
         }
     }
     game.visualise_state(&state);
+    if let Some(dataset) = dataset {
+        dataset
+            .finish_episode(&final_reward(&state))
+            .expect("Failed to write dataset");
+    }
+}
+
+///
+/// Ask the user which of the game's actions to take. Sampled game actions
+/// can only be picked from if the state lists them (with
+/// [`State::possible_non_player_actions`]) - otherwise one is sampled, if the
+/// state can.
+///
+fn get_injected_game_action<StateType: State>(state: &StateType) -> Option<StateType::ActionType> {
+    let mut sorted_actions = match state.next_actor() {
+        Actor::GameAction(actions) => actions.to_vec(),
+        _ => state.possible_non_player_actions(),
+    };
+    if sorted_actions.is_empty() {
+        println!("GAME ACTION (sampled)");
+        return random_game_action(state);
+    }
+    println!("GAME ACTION");
+    sorted_actions.sort_by(|a, b| format!("{:?}", a.0).cmp(&format!("{:?}", b.0)));
+
+    for (i, action) in sorted_actions.iter().enumerate() {
+        println!("{} {:?} {}", i, action.0, action.1);
+    }
+    loop {
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).is_err() {
+            println!("Failed to read line. Please try again.");
+            continue;
+        }
+        match input.trim().parse::<usize>() {
+            Ok(action) if action < sorted_actions.len() => {
+                return Some(sorted_actions[action].0.clone());
+            }
+            _ => {
+                println!("Failed to parse action. Please enter a valid number.");
+                continue;
+            }
+        };
+    }
+}
+
+/// Let each search know which action was played, so it can keep its subtree
+fn advance_searches<StateType, ActionType>(
+    searches: &mut [Search<StateType, ActionType>],
+    action: &ActionType,
+    reuse_tree: bool,
+) where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    for search in searches.iter_mut() {
+        if reuse_tree {
+            search.advance(action);
+        } else {
+            search.reset();
+        }
+    }
 }
 
 fn main() {
@@ -167,19 +305,51 @@ fn main() {
 
     let players = args.players;
 
-    for _ in 0..args.episodes {
+    let thinking = if !args.stop_on_enter {
+        None
+    } else if players.iter().any(|player| matches!(player, PlayerType::H)) || args.inject_game_turns
+    {
+        log::warn!("--stop-on-enter needs the keyboard to itself - ignoring it");
+        None
+    } else {
+        let thinking: Thinking = Arc::new(Mutex::new(None));
+        let thinking_clone = Arc::clone(&thinking);
+        thread::spawn(move || {
+            for _ in io::stdin().lines() {
+                if let Some(cancel) = thinking_clone.lock().unwrap().as_ref() {
+                    cancel.cancel();
+                }
+            }
+        });
+        Some(thinking)
+    };
+
+    let mut dataset = args
+        .dataset
+        .as_ref()
+        .map(|path| DatasetWriter::create(path).expect("Failed to create dataset"));
+    let mut tree_out = args.tree_out.as_ref().map(|path| TreeOut {
+        file: BufWriter::new(File::create(path).expect("Failed to create tree file")),
+        format: TreeFormat::from_path(path),
+        options: args.tree_export.clone(),
+        searches: 0,
+    });
+    let first_seed = args.seed.unwrap_or_else(rand::random);
+    for episode in 0..args.episodes {
+        let seed = first_seed.wrapping_add(episode as u64);
+        log::info!("Episode {} seed {}", episode, seed);
         match args.game {
             Games::C4 => {
                 run_game(
                     C4,
                     players.clone(),
-                    args.iterations,
-                    args.limit_time,
-                    args.threads,
+                    &args.search,
                     args.inject_game_turns,
-                    args.policy,
-                    args.exploration_constant,
-                    args.log_children,
+                    seed,
+                    args.show_eval,
+                    thinking.clone(),
+                    dataset.as_mut(),
+                    tree_out.as_mut(),
                 );
             }
             Games::NT => {
@@ -188,13 +358,13 @@ fn main() {
                         player_count: players.len() as u8,
                     },
                     players.clone(),
-                    args.iterations,
-                    args.limit_time,
-                    args.threads,
+                    &args.search,
                     args.inject_game_turns,
-                    args.policy,
-                    args.exploration_constant,
-                    args.log_children,
+                    seed,
+                    args.show_eval,
+                    thinking.clone(),
+                    dataset.as_mut(),
+                    tree_out.as_mut(),
                 );
             }
             Games::CS => {
@@ -203,13 +373,13 @@ fn main() {
                         player_count: players.len() as u8,
                     },
                     players.clone(),
-                    args.iterations,
-                    args.limit_time,
-                    args.threads,
+                    &args.search,
                     args.inject_game_turns,
-                    args.policy,
-                    args.exploration_constant,
-                    args.log_children,
+                    seed,
+                    args.show_eval,
+                    thinking.clone(),
+                    dataset.as_mut(),
+                    tree_out.as_mut(),
                 );
             }
             Games::EBR => {
@@ -218,13 +388,13 @@ fn main() {
                         player_count: players.len() as u8,
                     },
                     players.clone(),
-                    args.iterations,
-                    args.limit_time,
-                    args.threads,
+                    &args.search,
                     args.inject_game_turns,
-                    args.policy,
-                    args.exploration_constant,
-                    args.log_children,
+                    seed,
+                    args.show_eval,
+                    thinking.clone(),
+                    dataset.as_mut(),
+                    tree_out.as_mut(),
                 );
             }
         }
@@ -1,18 +1,133 @@
+pub mod arena;
+pub mod config;
+pub use config::{ConfigError, SearchConfig};
+pub mod dataset;
 pub mod game;
+pub mod ismcts;
 mod mcts;
-pub use mcts::calculate_best_turn;
+pub use mcts::{calculate_best_turn, CancelToken, Search, SearchHandle};
+pub mod network;
 pub mod node;
+pub mod prior;
+pub mod rng;
+pub mod rollout;
+pub mod search_result;
+pub mod selection;
 pub mod tree;
+pub mod tree_export;
 pub mod weighted_random;
 use clap::ValueEnum;
+use rand::Rng;
+use rng::with_rng;
 use serde::Deserialize;
+use std::cmp::Ordering;
 
 pub type Reward = f64;
 
-#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+///
+/// How the action to play is chosen from the root's children once searching
+/// is done. Proven wins are always played, and proven losses avoided, first.
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
 pub enum BestTurnPolicy {
     MostVisits,
     Ucb0,
+    /// The most visited child, searching on (for up to as long again) until
+    /// it's also the child with the best value
+    MaxRobust,
+    /// The child with the best lower confidence bound, so children that
+    /// haven't been searched much aren't trusted
+    SecureChild,
+    /// Sampled in proportion to visits raised to `1 / temperature` - higher
+    /// temperatures play weaker and more varied moves
+    Temperature,
+}
+
+impl BestTurnPolicy {
+    ///
+    /// Choose from the root's `children`, as (action, visits, value sum).
+    /// `constant` widens [`BestTurnPolicy::SecureChild`]'s bound, and
+    /// `temperature` is [`BestTurnPolicy::Temperature`]'s (0 plays the most
+    /// visited).
+    ///
+    pub fn choose<A: Clone + std::fmt::Debug>(
+        &self,
+        children: &[(A, u32, f64)],
+        constant: f64,
+        temperature: f64,
+    ) -> A {
+        let mean = |(_, visits, value_sum): &(A, u32, f64)| value_sum / (*visits).max(1) as f64;
+        let most_visits = || children.iter().max_by_key(|(_, visits, _)| *visits);
+        match self {
+            // Searching on has already done what it can to bring the most
+            // visited child and the best valued one together
+            BestTurnPolicy::MostVisits | BestTurnPolicy::MaxRobust => most_visits(),
+            BestTurnPolicy::Ucb0 => children
+                .iter()
+                .max_by(|a, b| mean(a).partial_cmp(&mean(b)).unwrap_or(Ordering::Equal)),
+            BestTurnPolicy::SecureChild => {
+                let parent_visits: u32 = children.iter().map(|(_, visits, _)| visits).sum();
+                let lower_bound = |child: &(A, u32, f64)| {
+                    if child.1 == 0 {
+                        return f64::NEG_INFINITY;
+                    }
+                    mean(child) - constant * ((parent_visits as f64).ln() / child.1 as f64).sqrt()
+                };
+                log::debug!(
+                    "Action, LCB: {:?}",
+                    children
+                        .iter()
+                        .map(|child| (child.0.clone(), lower_bound(child)))
+                        .collect::<Vec<_>>()
+                );
+                children.iter().max_by(|a, b| {
+                    lower_bound(a)
+                        .partial_cmp(&lower_bound(b))
+                        .unwrap_or(Ordering::Equal)
+                })
+            }
+            BestTurnPolicy::Temperature => {
+                let weights: Vec<f64> = children
+                    .iter()
+                    .map(|(_, visits, _)| (*visits as f64).powf(1.0 / temperature))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                if temperature <= 0.0 || !total.is_finite() || total <= 0.0 {
+                    most_visits()
+                } else {
+                    let mut random = with_rng(|rng| rng.gen_range(0.0..total));
+                    children
+                        .iter()
+                        .zip(weights)
+                        .find_map(|(child, weight)| {
+                            random -= weight;
+                            (random < 0.0).then_some(child)
+                        })
+                        // Rounding can leave a sliver past the last child
+                        .or_else(most_visits)
+                }
+            }
+        }
+        .unwrap()
+        .0
+        .clone()
+    }
+
+    ///
+    /// Whether [`BestTurnPolicy::MaxRobust`] can stop searching: the most
+    /// visited of `children` (as (action, visits, value sum)) has the best
+    /// value too
+    ///
+    pub fn is_robust<A>(children: &[(A, u32, f64)]) -> bool {
+        let mean = |(_, visits, value_sum): &(A, u32, f64)| value_sum / (*visits).max(1) as f64;
+        let Some(most_visited) = children.iter().max_by_key(|(_, visits, _)| *visits) else {
+            return true;
+        };
+        children
+            .iter()
+            .filter(|(_, visits, _)| *visits > 0)
+            .all(|child| mean(child) <= mean(most_visited))
+    }
 }
 
 impl std::fmt::Display for BestTurnPolicy {
@@ -20,6 +135,142 @@ impl std::fmt::Display for BestTurnPolicy {
         match self {
             BestTurnPolicy::MostVisits => write!(f, "most-visits"),
             BestTurnPolicy::Ucb0 => write!(f, "ucb0"),
+            BestTurnPolicy::MaxRobust => write!(f, "max-robust"),
+            BestTurnPolicy::SecureChild => write!(f, "secure-child"),
+            BestTurnPolicy::Temperature => write!(f, "temperature"),
+        }
+    }
+}
+
+///
+/// How a search uses multiple threads
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+pub enum Parallelism {
+    /// Every thread iterates the same tree
+    SharedTree,
+    /// Every thread iterates its own tree, and the roots are merged at the end
+    RootParallel,
+}
+
+impl std::fmt::Display for Parallelism {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Parallelism::SharedTree => write!(f, "shared-tree"),
+            Parallelism::RootParallel => write!(f, "root-parallel"),
         }
     }
 }
+
+///
+/// How a node values the rewards of a playout, for the player choosing it.
+///
+/// In a two player game these all come to the same thing. The searching
+/// player always values their own reward.
+///
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+pub enum Backup {
+    /// Every player values their own reward (max^n)
+    MaxN,
+    /// Every other player is against the searching player, and values the
+    /// searching player's reward negated
+    Paranoid,
+    /// Every other player values the best reward among the other players, as
+    /// if the searching player faced a single strongest opponent
+    BestReply,
+}
+
+impl Backup {
+    ///
+    /// What `reward` is worth to `player`, when `perspective` is the player
+    /// searching
+    ///
+    pub fn value(&self, reward: &[Reward], player: u8, perspective: u8) -> Reward {
+        let own = |player: u8| *reward.get(player as usize).unwrap_or(&0.0);
+        if player == perspective {
+            return own(player);
+        }
+        match self {
+            Backup::MaxN => own(player),
+            Backup::Paranoid => -own(perspective),
+            Backup::BestReply => reward
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != perspective as usize)
+                .map(|(_, reward)| *reward)
+                .fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+
+    ///
+    /// The least any player can value a reward between `lowest` and
+    /// `highest` - when other players value the searching player's reward
+    /// negated, that's the searching player's best reward
+    ///
+    pub fn worst_value(&self, lowest: Reward, highest: Reward) -> Reward {
+        match self {
+            Backup::Paranoid => lowest.min(-highest),
+            Backup::MaxN | Backup::BestReply => lowest,
+        }
+    }
+}
+
+impl std::fmt::Display for Backup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Backup::MaxN => write!(f, "max-n"),
+            Backup::Paranoid => write!(f, "paranoid"),
+            Backup::BestReply => write!(f, "best-reply"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secure_child_distrusts_few_visits() {
+        // One lucky visit against a well searched good move
+        let children = [("lucky", 1, 1.0), ("solid", 99, 79.0)];
+        assert_eq!(
+            BestTurnPolicy::Ucb0.choose(&children, std::f64::consts::SQRT_2, 1.0),
+            "lucky"
+        );
+        assert_eq!(
+            BestTurnPolicy::SecureChild.choose(&children, std::f64::consts::SQRT_2, 1.0),
+            "solid"
+        );
+    }
+
+    #[test]
+    fn test_temperature_sampling() {
+        let children = [("rare", 10, 0.0), ("common", 90, 0.0)];
+        assert_eq!(
+            BestTurnPolicy::Temperature.choose(&children, 1.0, 0.0),
+            "common"
+        );
+        rng::seed_thread(7);
+        let rare = (0..1000)
+            .filter(|_| BestTurnPolicy::Temperature.choose(&children, 1.0, 1.0) == "rare")
+            .count();
+        rng::unseed_thread();
+        assert!((50..150).contains(&rare), "Rare picked {} times", rare);
+    }
+
+    #[test]
+    fn test_worst_value() {
+        assert_eq!(Backup::MaxN.worst_value(0.0, 1.0), 0.0);
+        assert_eq!(Backup::BestReply.worst_value(-1.0, 1.0), -1.0);
+        // Another player's value is the searching player's reward negated
+        assert_eq!(Backup::Paranoid.worst_value(0.0, 1.0), -1.0);
+    }
+
+    #[test]
+    fn test_is_robust() {
+        assert!(BestTurnPolicy::is_robust(&[("a", 10, 5.0), ("b", 5, 2.0)]));
+        assert!(!BestTurnPolicy::is_robust(&[("a", 10, 5.0), ("b", 5, 4.0)]));
+        // Unvisited children don't count against it
+        assert!(BestTurnPolicy::is_robust(&[("a", 10, -5.0), ("b", 0, 0.0)]));
+    }
+}
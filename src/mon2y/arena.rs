@@ -0,0 +1,241 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// Slots in the first chunk - each chunk after that is twice the size
+const FIRST_CHUNK_SIZE: usize = 1024;
+/// Enough chunks for every `u32` index
+const CHUNK_COUNT: usize = 23;
+
+///
+/// Append-only storage that values can be added to from many threads at once,
+/// without a lock. Values are referred to by index, and never move or get
+/// removed - they're all freed together when the arena is dropped.
+///
+/// Storage is a list of chunks that double in size, each allocated the first
+/// time it's needed.
+///
+pub struct Arena<T> {
+    chunks: [OnceLock<Box<[OnceLock<T>]>>; CHUNK_COUNT],
+    len: AtomicUsize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena {
+            chunks: std::array::from_fn(|_| OnceLock::new()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// The chunk an index is in, and where in the chunk
+    fn locate(index: usize) -> (usize, usize) {
+        let chunk = (index / FIRST_CHUNK_SIZE + 1).ilog2() as usize;
+        (chunk, index - Self::chunk_start(chunk))
+    }
+
+    /// The index of the first slot in a chunk
+    fn chunk_start(chunk: usize) -> usize {
+        FIRST_CHUNK_SIZE * ((1 << chunk) - 1)
+    }
+
+    fn chunk(&self, chunk: usize) -> &[OnceLock<T>] {
+        self.chunks[chunk].get_or_init(|| {
+            (0..FIRST_CHUNK_SIZE << chunk)
+                .map(|_| OnceLock::new())
+                .collect()
+        })
+    }
+
+    ///
+    /// Add a value, returning its index. Panics if the arena is full (over
+    /// `u32::MAX` values).
+    ///
+    pub fn alloc(&self, value: T) -> u32 {
+        let index = self.len.fetch_add(1, Ordering::Relaxed);
+        let index = u32::try_from(index).expect("Arena is full");
+        let (chunk, offset) = Self::locate(index as usize);
+        if self.chunk(chunk)[offset].set(value).is_err() {
+            panic!("Arena slot {} allocated twice", index);
+        }
+        index
+    }
+
+    ///
+    /// Add `values` next to each other, returning the index of the first.
+    ///
+    /// They're always kept in one chunk, so if they don't fit in what's left
+    /// of the current one, its last few slots are skipped.
+    ///
+    pub fn alloc_range<I>(&self, values: I) -> u32
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let values = values.into_iter();
+        let count = values.len();
+        if count == 0 {
+            return 0;
+        }
+        let mut start = self.len.load(Ordering::Relaxed);
+        let start = loop {
+            let (chunk, offset) = Self::locate(start);
+            let fits = if offset + count <= FIRST_CHUNK_SIZE << chunk {
+                start
+            } else {
+                // Chunks double, so one will be big enough eventually
+                let mut chunk = chunk + 1;
+                while count > FIRST_CHUNK_SIZE << chunk {
+                    chunk += 1;
+                }
+                Self::chunk_start(chunk)
+            };
+            match self.len.compare_exchange_weak(
+                start,
+                fits + count,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break fits,
+                Err(len) => start = len,
+            }
+        };
+        assert!(start + count - 1 <= u32::MAX as usize, "Arena is full");
+        let (chunk, offset) = Self::locate(start);
+        for (slot, value) in self.chunk(chunk)[offset..].iter().zip(values) {
+            if slot.set(value).is_err() {
+                panic!("Arena slot {} allocated twice", start);
+            }
+        }
+        start as u32
+    }
+
+    ///
+    /// The `len` values from `start`, as added by `alloc_range`. Panics if
+    /// they haven't all been added yet.
+    ///
+    pub fn get_range(
+        &self,
+        start: u32,
+        len: u32,
+    ) -> impl ExactSizeIterator<Item = &T> + DoubleEndedIterator + Clone {
+        let slots = match len {
+            0 => &[],
+            _ => {
+                let (chunk, offset) = Self::locate(start as usize);
+                self.chunks[chunk]
+                    .get()
+                    .and_then(|chunk| chunk.get(offset..offset + len as usize))
+                    .unwrap_or_else(|| panic!("Nothing at arena index {}", start))
+            }
+        };
+        slots.iter().map(move |slot| {
+            slot.get()
+                .unwrap_or_else(|| panic!("Nothing in arena range from {}", start))
+        })
+    }
+
+    ///
+    /// The value at `index`. Panics if `alloc` hasn't returned that index yet.
+    ///
+    pub fn get(&self, index: u32) -> &T {
+        let (chunk, offset) = Self::locate(index as usize);
+        self.chunks[chunk]
+            .get()
+            .and_then(|chunk| chunk[offset].get())
+            .unwrap_or_else(|| panic!("Nothing at arena index {}", index))
+    }
+
+    ///
+    /// How many slots have been used - the values added, and any slots
+    /// skipped by `alloc_range`
+    ///
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken by the slots used so far
+    pub fn used_bytes(&self) -> usize {
+        self.len() * std::mem::size_of::<OnceLock<T>>()
+    }
+
+    /// Bytes allocated for slots, including ones not used yet
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| chunk.get())
+            .map(|chunk| std::mem::size_of_val(&**chunk))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_locate_spans_chunks() {
+        assert_eq!(Arena::<u8>::locate(0), (0, 0));
+        assert_eq!(
+            Arena::<u8>::locate(FIRST_CHUNK_SIZE - 1),
+            (0, FIRST_CHUNK_SIZE - 1)
+        );
+        assert_eq!(Arena::<u8>::locate(FIRST_CHUNK_SIZE), (1, 0));
+        assert_eq!(Arena::<u8>::locate(FIRST_CHUNK_SIZE * 3), (2, 0));
+        let (chunk, offset) = Arena::<u8>::locate(u32::MAX as usize);
+        assert!(chunk < CHUNK_COUNT);
+        assert!(offset < FIRST_CHUNK_SIZE << chunk);
+    }
+
+    #[test]
+    fn test_alloc_from_threads() {
+        let arena = Arc::new(Arena::new());
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let arena = Arc::clone(&arena);
+                std::thread::spawn(move || {
+                    (0..1000)
+                        .map(|i| (arena.alloc(thread * 1000 + i), thread * 1000 + i))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for thread in threads {
+            for (index, value) in thread.join().unwrap() {
+                assert_eq!(*arena.get(index), value);
+            }
+        }
+        assert_eq!(arena.len(), 4000);
+    }
+
+    #[test]
+    fn test_alloc_range_stays_in_a_chunk() {
+        let arena = Arena::new();
+        for i in 0..FIRST_CHUNK_SIZE - 1 {
+            arena.alloc(i);
+        }
+        // Only one slot is left in the first chunk, so the range skips it
+        let start = arena.alloc_range([1, 2, 3]);
+        assert_eq!(start as usize, FIRST_CHUNK_SIZE);
+        assert_eq!(
+            arena.get_range(start, 3).copied().collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(arena.get_range(start, 0).count(), 0);
+        assert_eq!(arena.alloc_range(Vec::new()), 0);
+        // A range bigger than the next chunk goes in the first one it fits in
+        let start = arena.alloc_range(0..FIRST_CHUNK_SIZE * 5);
+        assert_eq!(start as usize, FIRST_CHUNK_SIZE * 7);
+        assert_eq!(arena.get_range(start, 5).last(), Some(&4));
+    }
+}
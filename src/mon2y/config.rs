@@ -0,0 +1,615 @@
+use super::game::State;
+use super::prior::Priors;
+use super::rollout::Rollout;
+use super::selection::TreePolicy;
+use super::tree::TreeBudget;
+use super::{Backup, BestTurnPolicy, Parallelism, Reward};
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+///
+/// Everything that decides how a [`super::Search`] searches.
+///
+/// Binaries take it straight from their flags (it's a [`clap::Args`], to
+/// `#[command(flatten)]`), and the arena from its JSON - fields left out of
+/// either get the defaults in [`SearchConfig::default`]. In code, start from
+/// the default and use the `with_` methods.
+///
+#[derive(Debug, Clone, PartialEq, Deserialize, clap::Args)]
+// Without these, this comment would become the binary's description
+#[command(about = None, long_about = None, next_help_heading = "Search")]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Iterations to run each turn
+    #[arg(short, long, default_value_t = 10000)]
+    pub iterations: usize,
+    /// Stop searching after this many seconds, even if the iterations aren't done
+    #[arg(short('T'), long("limit-time"), value_parser = parse_seconds)]
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub time_limit: Option<Duration>,
+    /// Threads to search with
+    #[arg(short, long, default_value_t = 4)]
+    pub threads: usize,
+    /// How to choose the action to play once searching's done
+    #[arg(short('P'), long, default_value_t = BestTurnPolicy::MostVisits)]
+    pub policy: BestTurnPolicy,
+    /// How far the temperature policy flattens the visits it samples from
+    #[arg(long, default_value_t = 1.0)]
+    pub temperature: f64,
+    /// How much to explore rather than exploit
+    #[arg(short('c'), long, default_value_t = std::f64::consts::SQRT_2)]
+    pub exploration_constant: f64,
+    /// Log the tree under the root after searching
+    #[arg(long, default_value_t = false)]
+    pub log_children: bool,
+    /// Keep the search tree between turns
+    #[arg(short('R'), long, default_value_t = false)]
+    pub reuse_tree: bool,
+    /// Keep searching while other players decide (implies reuse-tree)
+    #[arg(long, default_value_t = false)]
+    pub ponder: bool,
+    /// Share nodes between positions reached by different move orders
+    #[arg(long, default_value_t = false)]
+    pub transpositions: bool,
+    /// How to pick actions in playouts
+    #[arg(long, default_value_t = Rollout::Uniform)]
+    pub rollout: Rollout,
+    /// How often the epsilon-greedy rollout picks randomly
+    #[arg(long, default_value_t = 0.1)]
+    pub rollout_epsilon: f64,
+    /// Stop playouts after this many actions and use the game's evaluation
+    /// instead (0 evaluates without playing out at all)
+    #[arg(long)]
+    pub playout_depth: Option<usize>,
+    /// How to pick which node to explore
+    #[arg(short('S'), long, default_value_t = TreePolicy::Ucb1)]
+    pub selection: TreePolicy,
+    /// Where player actions' priors come from (PUCT selection uses them)
+    #[arg(long, default_value_t = Priors::None)]
+    pub priors: Priors,
+    /// Mix Dirichlet noise with this alpha into the root's priors
+    #[arg(long)]
+    pub dirichlet_alpha: Option<f64>,
+    /// How much of the root's priors the Dirichlet noise makes up
+    #[arg(long, default_value_t = 0.25)]
+    pub dirichlet_weight: f64,
+    /// The trained network file network priors use
+    #[arg(long)]
+    pub network: Option<PathBuf>,
+    /// How much of a new leaf's reward is the priors' value estimate, when they have one, rather than a playout (0 to 1)
+    #[arg(long, default_value_t = 1.0)]
+    pub value_weight: f64,
+    /// Search over information sets, so hidden information isn't used
+    #[arg(long, default_value_t = false)]
+    pub information_sets: bool,
+    /// Sample the game's actions as the tree is searched, rather than keeping nodes for them
+    #[arg(long, default_value_t = false)]
+    pub open_loop: bool,
+    /// Losing visits each thread's iteration in flight counts as, so threads spread out
+    #[arg(long, default_value_t = 0)]
+    pub virtual_loss: u32,
+    /// What each virtual loss is worth - the worst reward in the game (taken
+    /// from the game's reward range if not given)
+    #[arg(long, allow_hyphen_values = true)]
+    pub virtual_loss_value: Option<f64>,
+    /// How the threads share the work
+    #[arg(long, default_value_t = Parallelism::SharedTree)]
+    pub parallelism: Parallelism,
+    /// How other players are taken to value the rewards in 3+ player games
+    #[arg(long, default_value_t = Backup::MaxN)]
+    pub backup: Backup,
+    /// Most nodes the tree can hold
+    #[arg(long)]
+    pub max_nodes: Option<usize>,
+    /// Most memory (in MiB) the tree can take - the nodes, and what their
+    /// states allocate if the game says (see `State::heap_bytes`)
+    #[arg(long)]
+    pub max_memory: Option<usize>,
+}
+
+impl Default for SearchConfig {
+    ///
+    /// 10000 iterations on 4 threads with no time limit, playing the most
+    /// visited action (a temperature of 1 if sampling is picked), exploring with a constant of sqrt(2), UCB1 selection
+    /// and uniform playouts (0.1 epsilon if epsilon-greedy is picked) - left
+    /// out for the priors' value estimate, when they have one.
+    ///
+    /// Everything else is off: no kept tree, pondering, transpositions,
+    /// priors, root noise, information sets, open loop or virtual loss, one shared tree with every player
+    /// valuing their own reward, and no limit on its size.
+    ///
+    fn default() -> Self {
+        SearchConfig {
+            iterations: 10000,
+            time_limit: None,
+            threads: 4,
+            policy: BestTurnPolicy::MostVisits,
+            temperature: 1.0,
+            exploration_constant: std::f64::consts::SQRT_2,
+            log_children: false,
+            reuse_tree: false,
+            ponder: false,
+            transpositions: false,
+            rollout: Rollout::Uniform,
+            rollout_epsilon: 0.1,
+            playout_depth: None,
+            selection: TreePolicy::Ucb1,
+            priors: Priors::None,
+            dirichlet_alpha: None,
+            dirichlet_weight: 0.25,
+            network: None,
+            value_weight: 1.0,
+            information_sets: false,
+            open_loop: false,
+            virtual_loss: 0,
+            virtual_loss_value: None,
+            parallelism: Parallelism::SharedTree,
+            backup: Backup::MaxN,
+            max_nodes: None,
+            max_memory: None,
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: BestTurnPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    ///
+    /// Flatten (above 1) or sharpen (below 1) the visits
+    /// [`BestTurnPolicy::Temperature`] samples the action to play from
+    ///
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_exploration_constant(mut self, exploration_constant: f64) -> Self {
+        self.exploration_constant = exploration_constant;
+        self
+    }
+
+    pub fn with_log_children(mut self) -> Self {
+        self.log_children = true;
+        self
+    }
+
+    pub fn with_reuse_tree(mut self) -> Self {
+        self.reuse_tree = true;
+        self
+    }
+
+    /// Ponder while other players decide (which keeps the tree too)
+    pub fn with_ponder(mut self) -> Self {
+        self.ponder = true;
+        self
+    }
+
+    ///
+    /// Share nodes between positions reached by different move orders.
+    ///
+    /// Only has an effect for states that implement
+    /// [`super::game::State::state_hash`]. Positions are only shared between
+    /// paths of the same length, and each parent selects on its own edge's
+    /// statistics (see [`super::tree::Tree::with_transpositions`]).
+    ///
+    pub fn with_transpositions(mut self) -> Self {
+        self.transpositions = true;
+        self
+    }
+
+    /// `epsilon` is only used by [`Rollout::EpsilonGreedy`]
+    pub fn with_rollout(mut self, rollout: Rollout, epsilon: f64) -> Self {
+        self.rollout = rollout;
+        self.rollout_epsilon = epsilon;
+        self
+    }
+
+    ///
+    /// Cut playouts short after `depth` actions, and use
+    /// [`super::game::State::evaluate`] for the rewards instead. A depth of 0
+    /// evaluates new nodes straight away.
+    ///
+    /// Games that can't be evaluated are still played out to the end.
+    ///
+    pub fn with_playout_depth(mut self, depth: usize) -> Self {
+        self.playout_depth = Some(depth);
+        self
+    }
+
+    pub fn with_selection(mut self, selection: TreePolicy) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    ///
+    /// Give player actions priors from `priors`, for selection policies that
+    /// use them (see [`super::selection::Puct`]).
+    ///
+    /// Information set and open loop searches don't use priors.
+    ///
+    pub fn with_priors(mut self, priors: Priors) -> Self {
+        self.priors = priors;
+        self
+    }
+
+    ///
+    /// Use the network saved at `path` for priors and values (see
+    /// [`super::network::Network`])
+    ///
+    pub fn with_network(mut self, path: PathBuf) -> Self {
+        self.priors = Priors::Network;
+        self.network = Some(path);
+        self
+    }
+
+    ///
+    /// Make `weight` of a new leaf's reward the priors' value estimate, and
+    /// the rest a playout (see [`super::tree::Tree::with_value_weight`])
+    ///
+    pub fn with_value_weight(mut self, weight: f64) -> Self {
+        self.value_weight = weight;
+        self
+    }
+
+    ///
+    /// Mix Dirichlet(`alpha`) noise into the priors of the root's children,
+    /// making up `weight` of them (see [`super::prior::add_dirichlet_noise`])
+    ///
+    pub fn with_dirichlet_noise(mut self, alpha: f64, weight: f64) -> Self {
+        self.dirichlet_alpha = Some(alpha);
+        self.dirichlet_weight = weight;
+        self
+    }
+
+    ///
+    /// Search over information sets (see [`super::ismcts::InformationSetTree`]),
+    /// so hidden information in the state isn't used.
+    ///
+    /// The tree is rebuilt every turn, so this doesn't keep a tree, ponder, or
+    /// share transpositions - and it doesn't prove wins or losses. Settings it
+    /// doesn't use are listed by [`SearchConfig::ignored_settings`].
+    ///
+    pub fn with_information_sets(mut self) -> Self {
+        self.information_sets = true;
+        self
+    }
+
+    ///
+    /// Sample the game's actions (dice rolls, card draws) afresh every
+    /// iteration, rather than keeping a node for each outcome - so the tree
+    /// only branches on players' actions, and statistics are shared across
+    /// outcomes (see [`super::ismcts::InformationSetTree::with_open_loop`]).
+    ///
+    /// Like information sets, the tree is rebuilt every turn.
+    ///
+    pub fn with_open_loop(mut self) -> Self {
+        self.open_loop = true;
+        self
+    }
+
+    ///
+    /// Count iterations in flight through a node as `virtual_loss` losing
+    /// visits, so multiple threads spread out (see
+    /// [`super::tree::Tree::with_virtual_loss`])
+    ///
+    pub fn with_virtual_loss(mut self, virtual_loss: u32) -> Self {
+        self.virtual_loss = virtual_loss;
+        self
+    }
+
+    ///
+    /// Make each virtual loss worth `value`, rather than the worst value in
+    /// the game's reward range (see [`SearchConfig::virtual_loss_value_for`])
+    ///
+    pub fn with_virtual_loss_value(mut self, value: f64) -> Self {
+        self.virtual_loss_value = Some(value);
+        self
+    }
+
+    ///
+    /// Choose how threads share the work (see [`Parallelism`]).
+    ///
+    /// Root-parallel searches build fresh trees every turn, so don't keep a
+    /// tree or ponder.
+    ///
+    pub fn with_parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// How the other players value rewards (see [`Backup`])
+    pub fn with_backup(mut self, backup: Backup) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    ///
+    /// Stop growing the tree once it reaches `budget` (see [`TreeBudget`]).
+    /// Root-parallel searches give each thread's tree an equal share.
+    ///
+    /// Information set searches keep no states in their nodes, and aren't
+    /// limited.
+    ///
+    pub fn with_budget(mut self, budget: TreeBudget) -> Self {
+        self.max_nodes = budget.max_nodes;
+        self.max_memory = budget.max_bytes.map(|bytes| bytes.div_ceil(1024 * 1024));
+        self
+    }
+
+    /// The limits on the tree's size
+    pub fn budget(&self) -> TreeBudget {
+        TreeBudget {
+            max_nodes: self.max_nodes,
+            max_bytes: self.max_memory.map(|mib| mib * 1024 * 1024),
+        }
+    }
+
+    /// Whether the tree is kept between turns (pondering keeps it too)
+    pub fn reuses_tree(&self) -> bool {
+        self.reuse_tree || self.ponder
+    }
+
+    ///
+    /// What each virtual loss is worth when searching `state`: the value
+    /// given, or else the worst value in the game's
+    /// [`State::reward_range`] under the backup. 0 if virtual losses are off.
+    ///
+    /// An error if virtual losses are on, but neither says what they're worth.
+    ///
+    pub fn virtual_loss_value_for<StateType: State>(
+        &self,
+        state: &StateType,
+    ) -> Result<Reward, ConfigError> {
+        if self.virtual_loss == 0 {
+            return Ok(0.0);
+        }
+        if let Some(value) = self.virtual_loss_value {
+            return Ok(value);
+        }
+        let (lowest, highest) = state
+            .reward_range()
+            .ok_or(ConfigError::NoVirtualLossValue)?;
+        Ok(self.backup.worst_value(lowest, highest))
+    }
+
+    ///
+    /// The flags for settings that are given, but that information set and
+    /// open loop searches don't use: priors and their value estimates, virtual
+    /// loss, keeping the tree, pondering, transpositions, root parallelism and
+    /// limits on the tree's size.
+    ///
+    /// Empty for other searches, which use them all.
+    ///
+    pub fn ignored_settings(&self) -> Vec<&'static str> {
+        if !self.information_sets && !self.open_loop {
+            return vec![];
+        }
+        let default = SearchConfig::default();
+        [
+            ("priors", self.priors != default.priors),
+            ("network", self.network.is_some()),
+            ("value-weight", self.value_weight != default.value_weight),
+            ("dirichlet-alpha", self.dirichlet_alpha.is_some()),
+            ("virtual-loss", self.virtual_loss != default.virtual_loss),
+            ("virtual-loss-value", self.virtual_loss_value.is_some()),
+            ("reuse-tree", self.reuse_tree),
+            ("ponder", self.ponder),
+            ("transpositions", self.transpositions),
+            (
+                "parallelism",
+                matches!(self.parallelism, Parallelism::RootParallel),
+            ),
+            ("max-nodes", self.max_nodes.is_some()),
+            ("max-memory", self.max_memory.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, given)| *given)
+        .map(|(flag, _)| flag)
+        .collect()
+    }
+}
+
+///
+/// A setting that can't be used with the game being searched
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// Virtual losses are on, but there's no virtual loss value, and the game
+    /// has no reward range to take one from
+    NoVirtualLossValue,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::NoVirtualLossValue => write!(
+                f,
+                "virtual-loss needs a virtual-loss-value for games with no reward range"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+/// Time limits are written in seconds (which can be fractional)
+fn deserialize_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(|seconds| Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::game::{Action, Actor};
+    use crate::test::injectable_game::InjectableGameState;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        config: SearchConfig,
+    }
+
+    #[test]
+    fn test_defaults_agree() {
+        assert_eq!(Cli::parse_from(["test"]).config, SearchConfig::default());
+        assert_eq!(
+            serde_json::from_str::<SearchConfig>("{}").unwrap(),
+            SearchConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_flags_and_json_agree() {
+        let from_flags = Cli::parse_from([
+            "test",
+            "-i",
+            "500",
+            "-T",
+            "1.5",
+            "--transpositions",
+            "--parallelism",
+            "root-parallel",
+            "--max-memory",
+            "64",
+            "--playout-depth",
+            "10",
+            "-P",
+            "temperature",
+            "--temperature",
+            "0.5",
+            "--priors",
+            "game",
+            "--dirichlet-alpha",
+            "0.3",
+        ])
+        .config;
+        let from_json: SearchConfig = serde_json::from_str(
+            r#"{"iterations": 500, "time_limit": 1.5, "transpositions": true,
+                "parallelism": "RootParallel", "max_memory": 64,
+                "playout_depth": 10, "policy": "Temperature",
+                "temperature": 0.5, "priors": "Game", "dirichlet_alpha": 0.3}"#,
+        )
+        .unwrap();
+        let built = SearchConfig::default()
+            .with_iterations(500)
+            .with_time_limit(Duration::from_millis(1500))
+            .with_transpositions()
+            .with_parallelism(Parallelism::RootParallel)
+            .with_playout_depth(10)
+            .with_policy(BestTurnPolicy::Temperature)
+            .with_temperature(0.5)
+            .with_priors(Priors::Game)
+            .with_dirichlet_noise(0.3, 0.25)
+            .with_budget(TreeBudget {
+                max_nodes: None,
+                max_bytes: Some(64 * 1024 * 1024),
+            });
+        assert_eq!(from_flags, built);
+        assert_eq!(from_json, built);
+    }
+
+    /// A game that's already over, and doesn't say what its rewards can be
+    #[derive(Clone)]
+    struct Unranged;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct NoAction;
+
+    impl Action for NoAction {
+        type StateType = Unranged;
+        fn execute(&self, _state: &Unranged) -> Unranged {
+            Unranged
+        }
+    }
+
+    impl State for Unranged {
+        type ActionType = NoAction;
+        fn permitted_actions(&self) -> Vec<NoAction> {
+            vec![]
+        }
+        fn next_actor(&self) -> Actor<NoAction> {
+            Actor::Player(0)
+        }
+        fn terminal(&self) -> bool {
+            true
+        }
+        fn reward(&self) -> Vec<Reward> {
+            vec![0.0]
+        }
+    }
+
+    #[test]
+    fn test_virtual_loss_value_for() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0, 0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![],
+            player_count: 2,
+            next_actor: Actor::Player(0),
+        };
+        let config = SearchConfig::default();
+        assert_eq!(config.virtual_loss_value_for(&state), Ok(0.0));
+        let config = config.with_virtual_loss(1).with_backup(Backup::Paranoid);
+        // The injectable game's rewards go from -1 to 1
+        assert_eq!(config.virtual_loss_value_for(&state), Ok(-1.0));
+        assert_eq!(
+            config
+                .with_virtual_loss_value(-0.5)
+                .virtual_loss_value_for(&state),
+            Ok(-0.5)
+        );
+        assert_eq!(
+            SearchConfig::default()
+                .with_virtual_loss(1)
+                .virtual_loss_value_for(&Unranged),
+            Err(ConfigError::NoVirtualLossValue)
+        );
+    }
+
+    #[test]
+    fn test_ignored_settings() {
+        let config = SearchConfig::default()
+            .with_priors(Priors::Game)
+            .with_virtual_loss(1)
+            .with_ponder()
+            .with_parallelism(Parallelism::RootParallel);
+        assert!(config.ignored_settings().is_empty());
+        assert_eq!(
+            config.clone().with_open_loop().ignored_settings(),
+            vec!["priors", "virtual-loss", "ponder", "parallelism"]
+        );
+        assert_eq!(
+            config.with_information_sets().ignored_settings(),
+            vec!["priors", "virtual-loss", "ponder", "parallelism"]
+        );
+    }
+}
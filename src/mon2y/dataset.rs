@@ -0,0 +1,172 @@
+use super::game::State;
+use super::search_result::SearchResult;
+use super::Reward;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+///
+/// One decision made by an MCTS player, as written to a dataset
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecisionRecord {
+    /// Seed of the episode the decision was made in, which tells episodes
+    /// apart
+    pub episode_seed: u64,
+    /// How many decisions were recorded before this one in the episode
+    pub decision: usize,
+    pub player: u8,
+    /// [`State::to_json`] of the state decided from, if the game has it
+    pub state: Option<serde_json::Value>,
+    /// The legal actions, in the order the game lists them (as `{:?}` shows
+    /// them)
+    pub actions: Vec<String>,
+    /// The search's visits to each of `actions` - all 0 for a forced action,
+    /// which isn't searched
+    pub visits: Vec<u32>,
+    /// The mean reward of each of `actions` for the player choosing (`None`
+    /// if it wasn't visited)
+    pub values: Vec<Option<f64>>,
+    /// Every player's mean reward from the root, as the search saw it
+    pub root_rewards: Vec<Reward>,
+    /// The action played
+    pub chosen: String,
+    /// Every player's reward at the end of the episode
+    pub reward: Vec<Reward>,
+}
+
+///
+/// Writes every decision MCTS players make to a dataset, as JSON Lines (one
+/// [`DecisionRecord`] to a line).
+///
+/// An episode's decisions are held back until it ends, so they can be
+/// written with its reward.
+///
+pub struct DatasetWriter<W: Write = BufWriter<File>> {
+    out: W,
+    pending: Vec<DecisionRecord>,
+}
+
+impl DatasetWriter {
+    /// Write to the file at `path`, replacing anything already there
+    pub fn create(path: &Path) -> std::io::Result<DatasetWriter> {
+        Ok(DatasetWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> DatasetWriter<W> {
+    pub fn new(out: W) -> DatasetWriter<W> {
+        DatasetWriter {
+            out,
+            pending: vec![],
+        }
+    }
+
+    ///
+    /// Hold on to `player`'s decision at `state`, from the search's
+    /// `result`, until the episode ends
+    ///
+    pub fn record<StateType: State>(
+        &mut self,
+        episode_seed: u64,
+        state: &StateType,
+        player: u8,
+        result: &SearchResult<StateType::ActionType>,
+    ) {
+        let actions = state.permitted_actions();
+        let stats: Vec<_> = actions
+            .iter()
+            .map(|action| result.actions.iter().find(|stats| stats.action == *action))
+            .collect();
+        self.pending.push(DecisionRecord {
+            episode_seed,
+            decision: self.pending.len(),
+            player,
+            state: state.to_json(),
+            actions: actions
+                .iter()
+                .map(|action| format!("{:?}", action))
+                .collect(),
+            visits: stats
+                .iter()
+                .map(|stats| stats.map_or(0, |stats| stats.visits))
+                .collect(),
+            values: stats
+                .iter()
+                .map(|stats| {
+                    stats
+                        .filter(|stats| stats.visits > 0)
+                        .map(|stats| stats.mean)
+                })
+                .collect(),
+            root_rewards: result.rewards.clone(),
+            chosen: format!("{:?}", result.action),
+            reward: vec![],
+        });
+    }
+
+    ///
+    /// Write the episode's decisions, now its `reward` is known, and start
+    /// on the next
+    ///
+    pub fn finish_episode(&mut self, reward: &[Reward]) -> std::io::Result<()> {
+        for mut record in self.pending.drain(..) {
+            record.reward = reward.to_vec();
+            serde_json::to_writer(&mut self.out, &record)?;
+            writeln!(self.out)?;
+        }
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::games::c4::C4;
+    use crate::mon2y::game::Action;
+    use crate::mon2y::{Search, SearchConfig};
+
+    #[test]
+    fn test_dataset_records_decisions_with_reward() {
+        let mut search = Search::new(SearchConfig::default().with_iterations(50)).with_seed(1);
+        let mut dataset = DatasetWriter::new(vec![]);
+        let mut state = C4.init_game();
+        let mut searched = vec![];
+        for player in 0..2 {
+            let result = search.calculate_best_turn(state.clone()).unwrap();
+            dataset.record(7, &state, player, &result);
+            state = result.action.execute(&state);
+            searched.push(result);
+        }
+        dataset.finish_episode(&[0.5, -0.5]).unwrap();
+
+        let out = String::from_utf8(dataset.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["episode_seed"], 7);
+            assert_eq!(line["decision"], i);
+            assert_eq!(line["player"], i);
+            assert_eq!(line["state"]["next_player"], i);
+            assert_eq!(line["actions"].as_array().unwrap().len(), 7);
+            assert_eq!(line["actions"][3], "Drop(3)");
+            let visits: Vec<u32> = searched[i]
+                .actions
+                .iter()
+                .map(|stats| stats.visits)
+                .collect();
+            assert_eq!(line["visits"], serde_json::json!(visits));
+            assert_eq!(line["chosen"], format!("{:?}", searched[i].action));
+            assert_eq!(line["reward"], serde_json::json!([0.5, -0.5]));
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use super::Reward;
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::Arc;
 
 pub trait Action: Debug + Clone + Eq + std::hash::Hash {
     type StateType: State<ActionType = Self>;
@@ -12,13 +16,17 @@ pub trait Action: Debug + Clone + Eq + std::hash::Hash {
 /// A player is just an identifier, typically a number between 0 and n-1.
 ///
 /// A game action is a action that the game takes, rather than a player.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Hash, Serialize)]
 pub enum Actor<ActionType> {
     /// A player is just an identifier, typically a number between 0 and n-1.
     Player(u8),
     /// A game action is a action that the game takes (such as rolling a dice, or drawing a card), rather than a player.
-    /// The value is a list of possible actions and their probabilities.
-    GameAction(Vec<(ActionType, u32)>),
+    /// The value is a list of possible actions and their probabilities. It's shared, so a game
+    /// with a fixed list (such as every roll of the dice) can hand out the same one each time.
+    GameAction(Arc<[(ActionType, u32)]>),
+    /// A game action with too many possible outcomes to list (such as shuffling a deck) - the
+    /// state samples one instead, with [`State::sample_game_action`].
+    SampledGameAction,
 }
 
 pub trait State: Clone {
@@ -30,4 +38,153 @@ pub trait State: Clone {
     fn next_actor(&self) -> Actor<Self::ActionType>;
     fn terminal(&self) -> bool;
     fn reward(&self) -> Vec<Reward>;
+    ///
+    /// A hash identifying this position, used to share nodes between move
+    /// orders that reach the same position (transpositions).
+    ///
+    /// Two states with the same hash are treated as interchangeable, so it needs
+    /// to cover everything that affects the rest of the game - including whose
+    /// turn it is. Returning `None` (the default) opts the state out - and
+    /// without it, a [`super::Search`] can't tell its kept tree is still for
+    /// the position searched, so it starts a new one each turn.
+    ///
+    fn state_hash(&self) -> Option<u64> {
+        None
+    }
+    ///
+    /// How promising `action` looks for the player to move, used by the
+    /// epsilon-greedy rollout policy. Higher is better - only the ordering
+    /// matters.
+    ///
+    fn playout_heuristic(&self, _action: &Self::ActionType) -> f64 {
+        0.0
+    }
+    ///
+    /// The action a heavy playout should take from here, if the game has an
+    /// opinion (such as taking a winning move). `None` falls back to random.
+    ///
+    fn heavy_playout_action(&self, _actions: &[Self::ActionType]) -> Option<Self::ActionType> {
+        None
+    }
+    ///
+    /// How likely each of `actions` (in the same order) is to be the one the
+    /// player to move should take, as weights - used as priors by
+    /// [`super::prior::GamePriors`]. `None` (the default) has no opinion.
+    ///
+    fn action_priors(&self, _actions: &[Self::ActionType]) -> Option<Vec<f64>> {
+        None
+    }
+    ///
+    /// This state as numbers for a learned model (see
+    /// [`super::network::Network`]), from the point of view of the player to
+    /// move. Every state of a game needs the same number of them. `None` (the
+    /// default) means the game has no encoding.
+    ///
+    fn features(&self) -> Option<Vec<f64>> {
+        None
+    }
+    ///
+    /// Where `action` goes in a learned model's policy, below
+    /// [`State::action_slots`]
+    ///
+    fn action_index(&self, _action: &Self::ActionType) -> Option<usize> {
+        None
+    }
+    ///
+    /// How many places a learned model's policy has for actions
+    ///
+    fn action_slots(&self) -> usize {
+        0
+    }
+    ///
+    /// This state as JSON, for reading outside of Rust (see
+    /// [`super::dataset::DatasetWriter`]). It's what the players can see -
+    /// anything hidden from all of them (such as the cards No Thanks puts
+    /// aside) is left out. `None` (the default) means the game can't be
+    /// serialized.
+    ///
+    fn to_json(&self) -> Option<serde_json::Value> {
+        None
+    }
+    ///
+    /// An estimate of every player's reward from here, on the same scale as
+    /// [`State::reward`], for cutting playouts short. Only asked about
+    /// states that aren't terminal. `None` (the default) means the state
+    /// can't be evaluated, and is played out to the end.
+    ///
+    fn evaluate(&self) -> Option<Vec<Reward>> {
+        None
+    }
+    ///
+    /// The lowest and highest reward [`State::reward`] can give any player,
+    /// used to make virtual losses worth the worst a player can do. `None`
+    /// (the default) means the game doesn't say.
+    ///
+    fn reward_range(&self) -> Option<(Reward, Reward)> {
+        None
+    }
+    ///
+    /// One of the game's actions, drawn from `rng`, when the next actor is
+    /// [`Actor::SampledGameAction`] - and its probability, if the game knows
+    /// it. Probabilities make the outcomes a search keeps better weighted.
+    ///
+    /// Only `rng` should be drawn from, so seeded searches can be replayed.
+    ///
+    /// Games with sampled game actions need to implement this. `None` (the
+    /// default) means nothing can be drawn, so the game can't go on - a
+    /// playout stops there, and the search can't go past it.
+    ///
+    fn sample_game_action(
+        &self,
+        _rng: &mut dyn RngCore,
+    ) -> Option<(Self::ActionType, Option<f64>)> {
+        None
+    }
+    ///
+    /// A state `observer` can't tell apart from this one, picked at random -
+    /// hidden information (such as cards face down) is shuffled, and only what
+    /// the observer knows is kept. Used by information set MCTS.
+    ///
+    /// The default is the state itself, for games where everyone sees
+    /// everything.
+    ///
+    fn determinize(&self, _observer: u8) -> Self {
+        self.clone()
+    }
+    ///
+    /// Roughly how many bytes the state has allocated, on top of its own size
+    /// (such as a board kept in a `Vec`). Counted towards a tree's memory
+    /// budget (see [`super::node::NodeArena::used_bytes`]). 0 (the default)
+    /// means the game doesn't say, and only the nodes are counted.
+    ///
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+///
+/// Roughly the bytes a `HashMap` has allocated - an entry and a control byte
+/// for every slot. For [`State::heap_bytes`]; anything the keys or values
+/// allocate themselves isn't counted.
+///
+pub fn hash_map_bytes<K, V, S>(map: &HashMap<K, V, S>) -> usize {
+    map.capacity() * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+/// Like [`hash_map_bytes`], for a `HashSet`
+pub fn hash_set_bytes<T, S>(set: &HashSet<T, S>) -> usize {
+    set.capacity() * (std::mem::size_of::<T>() + 1)
+}
+
+///
+/// The rewards a game ended on - or, if it had to stop before the end (such as
+/// a game action that couldn't be sampled), [`State::evaluate`]'s guess at them,
+/// as a playout does.
+///
+pub fn final_reward<StateType: State>(state: &StateType) -> Vec<Reward> {
+    if state.terminal() {
+        state.reward()
+    } else {
+        state.evaluate().unwrap_or_else(|| state.reward())
+    }
 }
@@ -0,0 +1,477 @@
+use super::game::{Action, Actor, State};
+use super::rng::with_rng;
+use super::rollout::{play_out, RolloutPolicy, UniformRollout};
+use super::search_result::{ActionStats, SearchResult};
+use super::selection::{ChildStats, SelectionPolicy, Ucb1};
+use super::weighted_random::{random_game_action, sample_game_action, weighted_random};
+use super::{Backup, BestTurnPolicy, Reward};
+use log::trace;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+
+///
+/// A node in an information set tree.
+///
+/// Nodes don't hold a state - they're reached by a sequence of actions, and
+/// the state comes from whichever determinization the iteration is using. So
+/// one node stands for every state the searching player can't tell apart.
+///
+#[derive(Debug)]
+pub struct InformationSetNode<ActionType> {
+    pub children: HashMap<ActionType, Arc<RwLock<InformationSetNode<ActionType>>>>,
+    pub visit_count: u32,
+    /// Sum of the values of the rewards to the player who chose this node
+    /// (see [`Backup`])
+    pub value_sum: f64,
+    pub value_sq_sum: f64,
+    /// Sum of every player's rewards
+    pub reward_sums: Vec<Reward>,
+    /// How many times this node could have been chosen (its action was
+    /// permitted in the determinization being searched) - counted while
+    /// selecting, under a read lock
+    pub availability: AtomicU32,
+}
+
+impl<ActionType> InformationSetNode<ActionType> {
+    ///
+    /// A node that's been available `availability` times - once for a
+    /// player's action that's just been tried
+    ///
+    fn new(availability: u32) -> InformationSetNode<ActionType> {
+        InformationSetNode {
+            children: HashMap::new(),
+            visit_count: 0,
+            value_sum: 0.0,
+            value_sq_sum: 0.0,
+            reward_sums: vec![],
+            availability: AtomicU32::new(availability),
+        }
+    }
+
+    pub fn availability(&self) -> u32 {
+        self.availability.load(Ordering::Relaxed)
+    }
+
+    fn visit(&mut self, value: f64, reward: &[Reward]) {
+        self.visit_count += 1;
+        self.value_sum += value;
+        self.value_sq_sum += value * value;
+        self.reward_sums.resize(reward.len(), 0.0);
+        for (sum, reward) in self.reward_sums.iter_mut().zip(reward.iter()) {
+            *sum += reward;
+        }
+    }
+
+    /// How many nodes there are under (and including) this one, and how
+    /// many actions deep they go
+    fn size(&self) -> (usize, usize) {
+        self.children
+            .values()
+            .map(|child| child.read().unwrap().size())
+            .fold((1, 0), |(count, depth), (child_count, child_depth)| {
+                (count + child_count, depth.max(child_depth + 1))
+            })
+    }
+}
+
+type NodeRef<ActionType> = Arc<RwLock<InformationSetNode<ActionType>>>;
+
+///
+/// Information set MCTS (single observer).
+///
+/// Every iteration searches a fresh determinization of the root state (see
+/// [`State::determinize`]) from the point of view of the player to move, so
+/// the search can't make use of information that player doesn't have.
+/// Children are scored against how often they were available, rather than
+/// against their parent's visits.
+///
+/// There's no tree reuse or solving here - a determinization can't prove
+/// anything about the real game.
+///
+/// The same tree does open loop search (see
+/// [`InformationSetTree::with_open_loop`]): the game's actions are sampled
+/// fresh every iteration rather than kept as nodes, so the tree only
+/// branches on players' actions, and each node's statistics are shared
+/// across every random outcome that led to it.
+///
+pub struct InformationSetTree<StateType: State, ActionType: Action<StateType = StateType>> {
+    pub root: NodeRef<ActionType>,
+    pub root_state: StateType,
+    pub observer: u8,
+    pub constant: f64,
+    pub rollout_policy: Arc<dyn RolloutPolicy<StateType>>,
+    /// Actions after which playouts are cut short and evaluated
+    pub playout_depth: Option<usize>,
+    pub selection_policy: Arc<dyn SelectionPolicy>,
+    /// How rewards are valued, with the observer as the player searching
+    pub backup: Backup,
+    /// Whether each iteration searches a determinization, rather than the
+    /// root state as it is
+    pub determinize: bool,
+    /// Whether the game's actions are sampled rather than kept in the tree
+    pub open_loop: bool,
+    /// Used when the action to play is sampled by
+    /// [`BestTurnPolicy::Temperature`]
+    pub temperature: f64,
+}
+
+impl<StateType, ActionType> InformationSetTree<StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    ///
+    /// Search `root_state` for the player to move. Panics if it's the game's
+    /// turn rather than a player's.
+    ///
+    pub fn new(root_state: StateType, constant: f64) -> InformationSetTree<StateType, ActionType> {
+        let observer = match root_state.next_actor() {
+            Actor::Player(player) => player,
+            _ => panic!("Information set search needs a player to move"),
+        };
+        InformationSetTree {
+            root: Arc::new(RwLock::new(InformationSetNode::new(0))),
+            root_state,
+            observer,
+            constant,
+            rollout_policy: Arc::new(UniformRollout),
+            playout_depth: None,
+            selection_policy: Arc::new(Ucb1),
+            backup: Backup::MaxN,
+            determinize: true,
+            open_loop: false,
+            temperature: 1.0,
+        }
+    }
+
+    ///
+    /// Use `policy` to pick player actions in playouts
+    ///
+    pub fn with_rollout_policy(
+        mut self,
+        policy: Arc<dyn RolloutPolicy<StateType>>,
+    ) -> InformationSetTree<StateType, ActionType> {
+        self.rollout_policy = policy;
+        self
+    }
+
+    ///
+    /// Stop playouts after `depth` actions and use the determinization's
+    /// evaluation instead, if it has one (see [`State::evaluate`])
+    ///
+    pub fn with_playout_depth(mut self, depth: usize) -> InformationSetTree<StateType, ActionType> {
+        self.playout_depth = Some(depth);
+        self
+    }
+
+    ///
+    /// Use `policy` to pick which child of a player node to explore
+    ///
+    pub fn with_selection_policy(
+        mut self,
+        policy: Arc<dyn SelectionPolicy>,
+    ) -> InformationSetTree<StateType, ActionType> {
+        self.selection_policy = policy;
+        self
+    }
+
+    ///
+    /// Value rewards with `backup` (see [`Backup`])
+    ///
+    pub fn with_backup(mut self, backup: Backup) -> InformationSetTree<StateType, ActionType> {
+        self.backup = backup;
+        self
+    }
+
+    ///
+    /// Don't keep nodes for the game's actions - sample them every iteration
+    /// instead, so the tree is keyed on the players' actions alone
+    ///
+    pub fn with_open_loop(mut self) -> InformationSetTree<StateType, ActionType> {
+        self.open_loop = true;
+        self
+    }
+
+    ///
+    /// Search the root state as it is, rather than determinizations of it -
+    /// for open loop search where hidden information can be used
+    ///
+    pub fn without_determinization(mut self) -> InformationSetTree<StateType, ActionType> {
+        self.determinize = false;
+        self
+    }
+
+    ///
+    /// Sample the action to play at `temperature`, when it's chosen by
+    /// [`BestTurnPolicy::Temperature`]
+    ///
+    pub fn with_temperature(
+        mut self,
+        temperature: f64,
+    ) -> InformationSetTree<StateType, ActionType> {
+        self.temperature = temperature;
+        self
+    }
+
+    ///
+    /// Pick the action to take from `node`, given the actions permitted in
+    /// this determinization. Untried actions come first.
+    ///
+    /// Every child that's permitted was available this time, whichever is
+    /// picked - an untried action's child starts out available once, when
+    /// it's added.
+    ///
+    fn select(&self, node: &NodeRef<ActionType>, actions: &[ActionType]) -> ActionType {
+        let children: Vec<Option<NodeRef<ActionType>>> = {
+            let node = node.read().unwrap();
+            actions
+                .iter()
+                .map(|action| node.children.get(action).cloned())
+                .collect()
+        };
+        for child in children.iter().flatten() {
+            child
+                .read()
+                .unwrap()
+                .availability
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        let untried: Vec<&ActionType> = actions
+            .iter()
+            .zip(children.iter())
+            .filter(|(_, child)| child.is_none())
+            .map(|(action, _)| action)
+            .collect();
+        if !untried.is_empty() {
+            return untried[with_rng(|rng| rng.gen_range(0..untried.len()))].clone();
+        }
+
+        let mut best: Option<(&ActionType, f64)> = None;
+        for (action, child) in actions.iter().zip(children.iter()) {
+            let child = child.as_ref().unwrap().read().unwrap();
+            let stats = ChildStats {
+                visit_count: child.visit_count as f64,
+                value_sum: child.value_sum,
+                value_sq_sum: child.value_sq_sum,
+                prior: None,
+            };
+            // Random used to break ties
+            let score = self.selection_policy.score(
+                &stats,
+                child.availability() as f64,
+                actions.len(),
+                self.constant,
+            ) + with_rng(|rng| rng.gen::<f64>()) * 1e-6;
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((action, score));
+            }
+        }
+        best.unwrap().0.clone()
+    }
+
+    ///
+    /// Run one iteration, on a new determinization if the tree uses them
+    ///
+    pub fn iterate(&self) {
+        let mut state = if self.determinize {
+            self.root_state.determinize(self.observer)
+        } else {
+            self.root_state.clone()
+        };
+        let mut node = self.root.clone();
+        // Each node on the path, with the player that chose it
+        let mut path: Vec<(NodeRef<ActionType>, Option<u8>)> = vec![(self.root.clone(), None)];
+
+        while !state.terminal() {
+            let (action, actor) = match state.next_actor() {
+                Actor::Player(player) => {
+                    (self.select(&node, &state.permitted_actions()), Some(player))
+                }
+                _ if self.open_loop => {
+                    // Stays on the same node - whatever was rolled
+                    match random_game_action(&state) {
+                        Some(action) => state = action.execute(&state),
+                        // Left to the playout to deal with
+                        None => break,
+                    }
+                    continue;
+                }
+                Actor::GameAction(actions) => (weighted_random(&actions), None),
+                Actor::SampledGameAction => match sample_game_action(&state) {
+                    Some(action) => (action, None),
+                    None => break,
+                },
+            };
+            let (child, expanded) = {
+                let mut node = node.write().unwrap();
+                match node.children.get(&action) {
+                    Some(child) => (child.clone(), false),
+                    None => {
+                        // Only players' actions are selected from
+                        let availability = u32::from(actor.is_some());
+                        let child = Arc::new(RwLock::new(InformationSetNode::new(availability)));
+                        node.children.insert(action.clone(), child.clone());
+                        (child, true)
+                    }
+                }
+            };
+            state = action.execute(&state);
+            path.push((child.clone(), actor));
+            node = child;
+            if expanded {
+                break;
+            }
+        }
+
+        let reward = play_out(state, self.rollout_policy.as_ref(), self.playout_depth);
+        // The outcomes of a chance node are valued for whoever chose to go to
+        // the chance node
+        let mut chooser = None;
+        for (node, actor) in path {
+            chooser = actor.or(chooser);
+            let value = chooser.map_or(0.0, |player| {
+                self.backup.value(&reward, player, self.observer)
+            });
+            node.write().unwrap().visit(value, &reward);
+        }
+    }
+
+    ///
+    /// The actions the observer can actually take, with their visits and
+    /// value sums
+    ///
+    pub fn root_children(&self) -> Vec<(ActionType, u32, f64)> {
+        let root = self.root.read().unwrap();
+        self.root_state
+            .permitted_actions()
+            .into_iter()
+            .map(|action| match root.children.get(&action) {
+                Some(child) => {
+                    let child = child.read().unwrap();
+                    (action, child.visit_count, child.value_sum)
+                }
+                None => (action, 0, 0.0),
+            })
+            .collect()
+    }
+
+    ///
+    /// The action to play - only actions the observer can actually take are
+    /// considered.
+    ///
+    pub fn best_action(&self, policy: BestTurnPolicy) -> ActionType {
+        let picks = self.root_children();
+        trace!("Action, Visits, Value: {:?}", picks);
+        policy.choose(&picks, self.constant, self.temperature)
+    }
+
+    ///
+    /// The action to play (see [`InformationSetTree::best_action`]), and what
+    /// the search found. Nothing can be proven, so `proven` is never set.
+    ///
+    pub fn search_result(
+        &self,
+        policy: BestTurnPolicy,
+        iterations: usize,
+        elapsed: std::time::Duration,
+    ) -> SearchResult<ActionType> {
+        let action = self.best_action(policy);
+        let root = self.root.read().unwrap();
+        let actions = self
+            .root_state
+            .permitted_actions()
+            .into_iter()
+            .map(|action| match root.children.get(&action) {
+                Some(child) => {
+                    let child = child.read().unwrap();
+                    ActionStats::new(
+                        action,
+                        child.visit_count,
+                        child.value_sum,
+                        child.value_sq_sum,
+                        &child.reward_sums,
+                        None,
+                    )
+                }
+                None => ActionStats::new(action, 0, 0.0, 0.0, &[], None),
+            })
+            .collect();
+        let rewards = root
+            .reward_sums
+            .iter()
+            .map(|sum| sum / root.visit_count.max(1) as f64)
+            .collect();
+        let (node_count, max_depth) = root.size();
+        drop(root);
+
+        let mut principal_variation = vec![];
+        let mut node = self.root.clone();
+        loop {
+            let most_visited = node
+                .read()
+                .unwrap()
+                .children
+                .iter()
+                .max_by_key(|(_, child)| child.read().unwrap().visit_count)
+                .map(|(action, child)| (action.clone(), child.clone()));
+            let Some((action, child)) = most_visited else {
+                break;
+            };
+            principal_variation.push(action);
+            node = child;
+        }
+        SearchResult {
+            action,
+            actions,
+            principal_variation,
+            iterations,
+            elapsed,
+            node_count,
+            used_bytes: 0,
+            max_depth,
+            fully_explored: false,
+            proven: None,
+            rewards,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::injectable_game::{InjectableGameAction, InjectableGameState};
+
+    #[test]
+    fn test_prefers_win() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![InjectableGameAction::Win, InjectableGameAction::Lose],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = InformationSetTree::new(state, 2.0_f64.sqrt());
+        for _ in 0..50 {
+            tree.iterate();
+        }
+        assert_eq!(
+            tree.best_action(BestTurnPolicy::MostVisits),
+            InjectableGameAction::Win
+        );
+        assert_eq!(
+            tree.best_action(BestTurnPolicy::Ucb0),
+            InjectableGameAction::Win
+        );
+        // Both were always available, from when they were added (one on the
+        // first iteration, and the other on the second)
+        let root = tree.root.read().unwrap();
+        let win = root.children[&InjectableGameAction::Win].read().unwrap();
+        let lose = root.children[&InjectableGameAction::Lose].read().unwrap();
+        assert_eq!(win.availability() + lose.availability(), 99);
+        for child in [win, lose] {
+            assert!(child.visit_count <= child.availability());
+        }
+    }
+}
@@ -1,173 +1,1458 @@
-use std::sync::atomic::AtomicUsize;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
 
 use log::trace;
 
 use crate::mon2y::game::Actor;
 use crate::mon2y::tree::Selection;
 
+use super::config::{ConfigError, SearchConfig};
 use super::game::{Action, State};
-use super::node::{best_pick, create_expanded_node, Node};
-use super::tree::Tree;
-use super::BestTurnPolicy;
+use super::ismcts::InformationSetTree;
+use super::node::{
+    create_expanded_node_with_priors, loses, update_proven, wins, Edge, NodeArena, NodeId,
+};
+use super::prior::PriorProvider;
+use super::rng::{derive_seed, seed_thread};
+use super::rollout::RolloutPolicy;
+use super::search_result::{ActionStats, SearchResult};
+use super::selection::SelectionPolicy;
+use super::tree::{TranspositionTable, Tree};
+use super::tree_export::{export_tree, ExportOptions, ExportedNode};
+use super::{Backup, BestTurnPolicy, Parallelism, Reward};
 
 /// Run multiple iterations of the MCTS algorithm on a state.
+///
+/// The tree is discarded afterwards - use a [`Search`] to keep it between turns.
+///
+/// An error if `config` can't be used to search this game (see
+/// [`Search::start_search`]).
 pub fn calculate_best_turn<
-    'a,
     StateType: State<ActionType = ActionType> + Sync + Send + 'static,
     ActionType: Action<StateType = StateType> + Sync + Send + 'static,
 >(
-    iterations: usize,
-    time_limit: Option<std::time::Duration>,
-    thread_count: usize,
     state: StateType,
-    policy: BestTurnPolicy,
-    exploration_constant: f64,
-    log_children: bool,
-) -> <StateType as State>::ActionType
+    config: &SearchConfig,
+) -> Result<SearchResult<ActionType>, ConfigError> {
+    Search::new(config.clone()).calculate_best_turn(state)
+}
+
+///
+/// A search session that keeps its tree between turns.
+///
+/// Every action that is played (by any player, or by the game) needs to be
+/// passed to [`Search::advance`], so the tree can be re-rooted on the child
+/// that was reached. The subtree under that child is copied into a fresh
+/// arena, and the old one (with the pruned siblings) is freed.
+///
+/// While another player is deciding, the session can ponder - keep iterating
+/// the current root in the background - so the work done is waiting in the
+/// tree when the actual move arrives.
+///
+pub struct Search<StateType: State, ActionType: Action<StateType = StateType>> {
+    config: SearchConfig,
+    /// The tree being kept, and its root
+    root: Option<(Arc<NodeArena<StateType, ActionType>>, NodeId)>,
+    ponder: Option<Ponder>,
+    transpositions: Option<Arc<TranspositionTable>>,
+    rollout_policy: Arc<dyn RolloutPolicy<StateType>>,
+    selection_policy: Arc<dyn SelectionPolicy>,
+    prior_provider: Option<Arc<dyn PriorProvider<StateType>>>,
+    /// Seed for the next search's threads, if searches are seeded
+    seed: Option<u64>,
+    /// The player searching - known once they've been asked to move
+    player: Option<u8>,
+}
+
+/// Background threads iterating the tree until told to stop
+struct Ponder {
+    cancel: CancelToken,
+    finished_iterations: Arc<AtomicUsize>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+///
+/// Tells a running search to stop. Clones share the same flag, so it can be
+/// handed to whatever decides when to stop (a GUI's stop button, a server's
+/// request handler).
+///
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    ///
+    /// Stop the search - each thread finishes the iteration it's on first
+    ///
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+impl<StateType, ActionType> Default for Search<StateType, ActionType>
 where
-    StateType: State<ActionType = ActionType>,
-    ActionType: Action<StateType = StateType>,
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
 {
-    log::debug!("Starting next turn");
-    let root_node = create_expanded_node(state, None);
-    if let Node::Expanded { children, .. } = &root_node {
-        if children.len() == 1 {
-            log::debug!("Short circuited - only one option");
-            return children.keys().next().unwrap().clone();
+    fn default() -> Self {
+        Self::new(SearchConfig::default())
+    }
+}
+
+impl<StateType, ActionType> Search<StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    ///
+    /// A session searching as `config` says. The rollout and selection
+    /// policies can be swapped for ones that aren't built in afterwards.
+    ///
+    /// Warns about each setting the search will ignore (see
+    /// [`SearchConfig::ignored_settings`]).
+    ///
+    pub fn new(config: SearchConfig) -> Self {
+        for setting in config.ignored_settings() {
+            log::warn!("Information set and open loop searches ignore {}", setting);
+        }
+        Search {
+            root: None,
+            ponder: None,
+            transpositions: config
+                .transpositions
+                .then(|| Arc::new(RwLock::new(HashMap::new()))),
+            rollout_policy: config.rollout.policy(config.rollout_epsilon),
+            selection_policy: config.selection.policy(),
+            prior_provider: config.priors.provider(config.network.as_deref()),
+            seed: None,
+            player: None,
+            config,
         }
     }
 
-    let tree = Arc::new(Tree::new_with_constant(root_node, exploration_constant));
-    let mut threads = vec![];
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
 
-    let finished_iterations: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    ///
+    /// Seed every search, so they can be replayed. Each search (and each of
+    /// its threads) gets its own stream derived from `seed`.
+    ///
+    /// With one thread and no time limit, the same seed and the same calls
+    /// give the same moves and statistics. With more, the threads' timing
+    /// still changes how their iterations interleave.
+    ///
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
 
-    for _ in 0..thread_count {
-        let tree_clone = Arc::clone(&tree);
-        let finished_iterations_clone: Arc<AtomicUsize> = Arc::clone(&finished_iterations);
-        let time_started = std::time::Instant::now();
-        threads.push(std::thread::spawn(move || loop {
-            {
-                trace!(
-                    "Starting iteration {}",
-                    finished_iterations_clone.load(std::sync::atomic::Ordering::SeqCst)
-                );
-                let result = tree_clone.iterate();
-                let current_iterations =
-                    finished_iterations_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                trace!("Finished iteration {}", current_iterations);
-                if current_iterations >= iterations
-                    || result == Selection::FullyExplored
-                    || time_started.elapsed() > time_limit.unwrap_or(std::time::Duration::MAX)
-                {
-                    break;
-                }
+    /// The seed for the next search, moving on to the one after
+    fn next_seed(&mut self) -> Option<u64> {
+        let seed = self.seed?;
+        self.seed = Some(derive_seed(seed, 0));
+        Some(seed)
+    }
+
+    /// Use `policy` to pick which child of a player node to explore
+    pub fn with_selection_policy(mut self, policy: Arc<dyn SelectionPolicy>) -> Self {
+        self.selection_policy = policy;
+        self
+    }
+
+    /// Use `policy` to pick player actions in playouts
+    pub fn with_rollout_policy(mut self, policy: Arc<dyn RolloutPolicy<StateType>>) -> Self {
+        self.rollout_policy = policy;
+        self
+    }
+
+    ///
+    /// Use `provider` for the priors (and value estimates) of nodes added to
+    /// the tree
+    ///
+    pub fn with_prior_provider(mut self, provider: Arc<dyn PriorProvider<StateType>>) -> Self {
+        self.prior_provider = Some(provider);
+        self
+    }
+
+    ///
+    /// The kept tree, if it's rooted at `state` - otherwise it's dropped, so
+    /// a missed [`Search::advance`] doesn't leave the search looking at a
+    /// position that's no longer in play.
+    ///
+    fn take_root_for(
+        &mut self,
+        state: &StateType,
+    ) -> Option<(Arc<NodeArena<StateType, ActionType>>, NodeId)> {
+        let (nodes, root) = self.root.take()?;
+        if same_position(nodes[root].state(), state) {
+            return Some((nodes, root));
+        }
+        log::debug!("Kept tree isn't rooted at the state searched - discarding tree");
+        if let Some(table) = &self.transpositions {
+            table.write().unwrap().clear();
+        }
+        None
+    }
+
+    /// A new arena holding just a root for `state`
+    fn new_tree(&self, state: StateType) -> (Arc<NodeArena<StateType, ActionType>>, NodeId) {
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(create_expanded_node_with_priors(
+            state,
+            None,
+            self.prior_provider.as_deref(),
+        ));
+        (Arc::new(nodes), root)
+    }
+
+    ///
+    /// A tree searching as configured, with virtual losses worth
+    /// `virtual_loss_value` (see [`SearchConfig::virtual_loss_value_for`])
+    ///
+    fn tree(
+        &self,
+        nodes: Arc<NodeArena<StateType, ActionType>>,
+        root: NodeId,
+        virtual_loss_value: Reward,
+    ) -> Tree<StateType, ActionType> {
+        let tree = Tree::new_in(nodes, root, self.config.exploration_constant)
+            .with_rollout_policy(self.rollout_policy.clone())
+            .with_selection_policy(self.selection_policy.clone())
+            .with_virtual_loss(self.config.virtual_loss, virtual_loss_value)
+            .with_budget(self.config.budget())
+            .with_backup(self.config.backup, self.player.unwrap_or(0))
+            .with_value_weight(self.config.value_weight);
+        let tree = match self.config.playout_depth {
+            Some(depth) => tree.with_playout_depth(depth),
+            None => tree,
+        };
+        let tree = match &self.prior_provider {
+            Some(provider) => tree.with_prior_provider(provider.clone()),
+            None => tree,
+        };
+        let tree = match self.config.dirichlet_alpha {
+            Some(alpha) => tree.with_root_noise(alpha, self.config.dirichlet_weight),
+            None => tree,
+        };
+        match &self.transpositions {
+            Some(table) => tree.with_transpositions(table.clone()),
+            None => tree,
+        }
+    }
+
+    ///
+    /// Start iterating the tree in the background, from `state` if there's no
+    /// tree being kept for it.
+    ///
+    /// Pondering stops after the configured iterations, when the tree is fully
+    /// explored, or when the session is next used (by [`Search::advance`],
+    /// [`Search::calculate_best_turn`], [`Search::reset`] or
+    /// [`Search::stop_pondering`]).
+    ///
+    /// Searches that don't use [`Backup::MaxN`] need to know whose side
+    /// they're on, so don't ponder until they've been asked to move. Nor do
+    /// searches that can't be started (see [`Search::start_search`]).
+    ///
+    pub fn start_pondering(&mut self, state: &StateType) {
+        if self.ponder.is_some()
+            || self.config.information_sets
+            || self.config.open_loop
+            || matches!(self.config.parallelism, Parallelism::RootParallel)
+            || (self.config.backup != Backup::MaxN && self.player.is_none())
+        {
+            return;
+        }
+        let Ok(virtual_loss_value) = self.config.virtual_loss_value_for(state) else {
+            return;
+        };
+        let (nodes, root) = match self.take_root_for(state) {
+            Some(kept) => kept,
+            None => self.new_tree(state.clone()),
+        };
+        self.root = Some((nodes.clone(), root));
+        let tree = Arc::new(self.tree(nodes, root, virtual_loss_value));
+        let cancel = CancelToken::new();
+        let finished_iterations = Arc::new(AtomicUsize::new(0));
+        let seed = self.next_seed();
+
+        log::debug!("Starting pondering");
+        let threads = spawn_iterations(
+            self.config.iterations,
+            None,
+            self.config.threads,
+            seed,
+            &cancel,
+            &finished_iterations,
+            move |_| tree.iterate() == Selection::FullyExplored,
+        );
+
+        self.ponder = Some(Ponder {
+            cancel,
+            finished_iterations,
+            threads,
+        });
+    }
+
+    ///
+    /// Stop pondering, if it's running.
+    ///
+    /// Returns how many iterations were completed while pondering.
+    ///
+    pub fn stop_pondering(&mut self) -> usize {
+        let Some(ponder) = self.ponder.take() else {
+            return 0;
+        };
+        ponder.cancel.cancel();
+        for thread in ponder.threads {
+            thread.join().unwrap();
+        }
+        let iterations = ponder
+            .finished_iterations
+            .load(std::sync::atomic::Ordering::SeqCst);
+        log::debug!("Pondered {} iterations", iterations);
+        iterations
+    }
+
+    /// Whether background pondering is running
+    pub fn is_pondering(&self) -> bool {
+        self.ponder.is_some()
+    }
+
+    ///
+    /// Re-root the tree on the child reached by `action`, keeping only the
+    /// nodes under it.
+    ///
+    /// If that child was never expanded, the tree is dropped, and the next
+    /// search starts from scratch. That's also what happens when a sampled
+    /// game action wasn't among the outcomes its chance node drew (see
+    /// [`super::node::SAMPLED_OUTCOMES`]).
+    ///
+    pub fn advance(&mut self, action: &ActionType) {
+        self.stop_pondering();
+        let Some((nodes, root)) = self.root.take() else {
+            return;
+        };
+        let Some(child) = nodes.child(root, action) else {
+            log::debug!("Action {:?} not in tree - discarding tree", action);
+            if let Some(table) = &self.transpositions {
+                table.write().unwrap().clear();
             }
-        }));
+            return;
+        };
+        let (kept, copied) = nodes.copy_subtree(child);
+        log::debug!("Kept {} of {} nodes", kept.len(), nodes.len());
+        // Transpositions outside the kept subtree are gone, and the rest are
+        // a level nearer the root
+        if let Some(table) = &self.transpositions {
+            let mut table = table.write().unwrap();
+            let entries = std::mem::take(&mut *table);
+            table.extend(entries.into_iter().filter_map(|((hash, depth), node)| {
+                let node = *copied.get(&node)?;
+                Some(((hash, depth - 1), node))
+            }));
+        }
+        self.root = Some((Arc::new(kept), copied[&child]));
     }
 
-    for thread in threads {
-        thread.join().unwrap();
+    /// Discard the tree, so the next search starts from scratch.
+    pub fn reset(&mut self) {
+        self.stop_pondering();
+        self.root = None;
+        if let Some(table) = &self.transpositions {
+            table.write().unwrap().clear();
+        }
     }
 
-    log::debug!(
-        "Completed {} iterations",
-        finished_iterations.load(std::sync::atomic::Ordering::SeqCst)
-    );
+    /// Visits at the current root, if there's a tree being kept.
+    pub fn root_visit_count(&self) -> Option<u32> {
+        self.root
+            .as_ref()
+            .map(|(nodes, root)| nodes[*root].visit_count())
+    }
 
-    if log::log_enabled!(log::Level::Trace) || log_children {
-        tree.root.clone().read().unwrap().log_children(0);
+    ///
+    /// Run multiple iterations of the MCTS algorithm on a state.
+    ///
+    /// If a tree is being kept from a previous turn, searching continues from
+    /// the kept root. If the kept root isn't `state` (an action was played
+    /// without [`Search::advance`]), the tree is discarded and the search
+    /// starts again from `state`.
+    ///
+    /// Returns the action chosen, along with what the search found out - or
+    /// an error if the configuration can't be used for this game (see
+    /// [`Search::start_search`]).
+    ///
+    pub fn calculate_best_turn(
+        &mut self,
+        state: StateType,
+    ) -> Result<SearchResult<ActionType>, ConfigError> {
+        Ok(self.start_search(state)?.finish())
     }
-    let root_ref = tree.root.clone();
 
-    match policy {
-        BestTurnPolicy::Ucb0 => {
-            let node = root_ref.read().unwrap();
-            // This bit of logic is reimplemented due to crashing when tree is fully explored
-            let mut picks = match &*node {
-                Node::Expanded { children, .. } => children
-                    .iter()
-                    // TODO: Add random factor
-                    .map(|(action, child)| {
-                        let child = child.read().unwrap();
-                        (
-                            action.clone(),
-                            child.value_sum() as f64
-                                / if child.visit_count() > 0 {
-                                    child.visit_count() as f64
-                                } else {
-                                    f64::INFINITY
-                                },
-                        )
+    ///
+    /// Start searching in the background, as
+    /// [`Search::calculate_best_turn`] would, and return straight away.
+    ///
+    /// The search runs until the configured iterations or time limit is
+    /// reached, there's nothing left to search, or it's cancelled (see
+    /// [`SearchHandle`]). [`BestTurnPolicy::MaxRobust`] searches can run on
+    /// past the limits.
+    ///
+    /// An error, before anything's searched, if virtual losses are on without
+    /// a value for them and the game has no reward range to take one from.
+    ///
+    pub fn start_search(
+        &mut self,
+        state: StateType,
+    ) -> Result<SearchHandle<'_, StateType, ActionType>, ConfigError> {
+        log::debug!("Starting next turn");
+        self.stop_pondering();
+        // Information set and open loop searches have no virtual losses
+        let virtual_loss_value = if self.config.information_sets || self.config.open_loop {
+            0.0
+        } else {
+            self.config.virtual_loss_value_for(&state)?
+        };
+        if let Actor::Player(player) = state.next_actor() {
+            self.player = Some(player);
+        }
+        let seed = self.next_seed();
+        // Only shared tree searches keep a tree
+        let kept = self.take_root_for(&state);
+        let thread_count = self.config.threads;
+        let cancel = CancelToken::new();
+        let finished_iterations = Arc::new(AtomicUsize::new(0));
+        let started = std::time::Instant::now();
+        // Max-robust searches run on, for up to as long again, until the most
+        // visited of the root's children (`children`) is the best valued too
+        let max_robust = self.config.policy == BestTurnPolicy::MaxRobust;
+        let spawn =
+            |iterate: Box<dyn Fn(usize) -> bool + Send + Sync>,
+             children: Box<dyn Fn() -> Vec<(ActionType, u32, f64)> + Send + Sync>| {
+                let (iterations, time_limit) = (self.config.iterations, self.config.time_limit);
+                let iterate: Box<dyn Fn(usize) -> bool + Send + Sync> = if max_robust {
+                    let finished_iterations = Arc::clone(&finished_iterations);
+                    Box::new(move |thread| {
+                        let done = iterate(thread);
+                        let over_budget = finished_iterations
+                            .load(std::sync::atomic::Ordering::SeqCst)
+                            >= iterations
+                            || time_limit.is_some_and(|limit| started.elapsed() > limit);
+                        done || (over_budget && BestTurnPolicy::is_robust(&children()))
                     })
-                    .collect::<Vec<_>>(),
-                _ => panic!("Root should be parent"),
+                } else {
+                    iterate
+                };
+                let extension = if max_robust { 2 } else { 1 };
+                // An unbounded budget (`usize::MAX` iterations) stays unbounded
+                spawn_iterations(
+                    iterations.saturating_mul(extension),
+                    time_limit.map(|limit| limit.saturating_mul(extension as u32)),
+                    thread_count,
+                    seed,
+                    &cancel,
+                    &finished_iterations,
+                    iterate,
+                )
             };
-            picks.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-            log::debug!("Action, UCB0: {:?}", picks);
-            picks[0].0.clone()
-        }
-
-        BestTurnPolicy::MostVisits => {
-            let root = root_ref.read().unwrap();
-            if let Node::Expanded { children, .. } = &*root {
-                log::debug!(
-                    "Action, Visits, Value: {:?}",
-                    children
-                        .iter()
-                        .map(|(action, node)| {
-                            let node = node.read().unwrap();
-                            (action.clone(), node.visit_count(), node.value_sum())
-                        })
-                        .collect::<Vec<_>>()
+
+        let (running, threads) = if self.config.information_sets || self.config.open_loop {
+            let permitted_actions = state.permitted_actions();
+            if permitted_actions.len() == 1 {
+                log::debug!("Short circuited - only one option");
+                (Running::Forced(permitted_actions[0].clone()), vec![])
+            } else {
+                let tree = InformationSetTree::new(state, self.config.exploration_constant)
+                    .with_rollout_policy(self.rollout_policy.clone())
+                    .with_selection_policy(self.selection_policy.clone())
+                    .with_backup(self.config.backup);
+                let tree = match self.config.playout_depth {
+                    Some(depth) => tree.with_playout_depth(depth),
+                    None => tree,
+                };
+                let tree = if self.config.open_loop {
+                    tree.with_open_loop()
+                } else {
+                    tree
+                };
+                let tree = if self.config.information_sets {
+                    tree
+                } else {
+                    tree.without_determinization()
+                };
+                let tree = Arc::new(tree.with_temperature(self.config.temperature));
+                let tree_clone = Arc::clone(&tree);
+                let children_tree = Arc::clone(&tree);
+                let threads = spawn(
+                    Box::new(move |_| {
+                        tree_clone.iterate();
+                        false
+                    }),
+                    Box::new(move || children_tree.root_children()),
                 );
-                // Short circuit on a winning move
-                // Implemented because (I think) the UCB formula doesn't end up prioritizing
-                // certainly winning moves, because they're already explored. Dunno if this
-                // is a cludge though.
-                let winning_moves: Vec<ActionType> = children
-                    .iter()
-                    .filter_map(|(action, node)| {
-                        let node_ref = node.clone();
-                        let node = node_ref.read().unwrap();
-                        if let Node::Placeholder { .. } = &*node {
-                            return None;
-                        }
-                        if node.state().terminal() {
-                            let actor = root.state().next_actor();
-                            if let Actor::Player(player_id) = actor {
-                                if let Some((index, _)) =
-                                    // Annoying - but necessary because I was dumb enough to use f64
-                                    // (otherwise, it'd be max_by_key)
-                                    node.state().reward().iter().enumerate().max_by(
-                                            |(_, a), (_, b)| {
-                                                a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Less)
-                                            },
-                                        )
-                                {
-                                    if index == player_id as usize {
-                                        return Some(action.clone());
-                                    }
-                                }
+                (Running::InformationSets(tree), threads)
+            }
+        } else if let Parallelism::RootParallel = self.config.parallelism {
+            let permitted_actions = state.permitted_actions();
+            if permitted_actions.len() == 1 {
+                log::debug!("Short circuited - only one option");
+                (Running::Forced(permitted_actions[0].clone()), vec![])
+            } else {
+                let trees: Vec<_> = (0..thread_count.max(1))
+                    .map(|_| {
+                        let (nodes, root) = self.new_tree(state.clone());
+                        let tree = self
+                            .tree(nodes, root, virtual_loss_value)
+                            .with_budget(self.config.budget().split(thread_count.max(1)));
+                        // Each tree keeps its own transpositions
+                        Arc::new(match self.transpositions {
+                            Some(_) => {
+                                tree.with_transpositions(Arc::new(RwLock::new(HashMap::new())))
                             }
-                        }
-                        None
+                            None => tree,
+                        })
                     })
                     .collect();
-                if let Some(action) = winning_moves.first() {
-                    return action.clone();
+                let trees_clone = trees.clone();
+                let children_trees = trees.clone();
+                // Each thread has a tree to itself, so there's no lock
+                // contention between them
+                let threads = spawn(
+                    Box::new(move |thread| {
+                        trees_clone[thread].iterate() == Selection::FullyExplored
+                    }),
+                    Box::new(move || merged_root_children(&children_trees)),
+                );
+                (Running::RootParallel(trees), threads)
+            }
+        } else {
+            let (nodes, root) = match kept {
+                Some((nodes, root)) => {
+                    log::debug!("Reusing tree with {} visits", nodes[root].visit_count());
+                    (nodes, root)
                 }
+                None => self.new_tree(state),
+            };
+            self.root = Some((nodes.clone(), root));
+            let only_action = match nodes.children(root).collect::<Vec<_>>()[..] {
+                [edge] => Some(edge.action.clone()),
+                _ => None,
+            };
+            if let Some(action) = only_action {
+                log::debug!("Short circuited - only one option");
+                (Running::Forced(action), vec![])
+            } else {
+                let tree = Arc::new(self.tree(nodes, root, virtual_loss_value));
+                let tree_clone = Arc::clone(&tree);
+                let children_tree = Arc::clone(&tree);
+                let threads = spawn(
+                    Box::new(move |_| tree_clone.iterate() == Selection::FullyExplored),
+                    Box::new(move || root_children(&children_tree.nodes, children_tree.root)),
+                );
+                (Running::SharedTree(tree), threads)
+            }
+        };
+
+        Ok(SearchHandle {
+            running,
+            cancel,
+            finished_iterations,
+            threads,
+            started,
+            config: self.config.clone(),
+            search: PhantomData,
+        })
+    }
+}
 
-                children
+/// What a [`SearchHandle`] is searching
+enum Running<StateType: State, ActionType: Action<StateType = StateType>> {
+    /// There's only one action, so there's nothing to search
+    Forced(ActionType),
+    SharedTree(Arc<Tree<StateType, ActionType>>),
+    /// A tree for each thread, merged at the root for results
+    RootParallel(Vec<Arc<Tree<StateType, ActionType>>>),
+    InformationSets(Arc<InformationSetTree<StateType, ActionType>>),
+}
+
+///
+/// A search running in the background (see [`Search::start_search`]).
+///
+/// It holds on to its [`Search`] until it's done with, so the tree can't be
+/// advanced or reset underneath it. Dropping the handle cancels the search,
+/// and waits for its threads.
+///
+pub struct SearchHandle<'a, StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    running: Running<StateType, ActionType>,
+    cancel: CancelToken,
+    finished_iterations: Arc<AtomicUsize>,
+    threads: Vec<JoinHandle<()>>,
+    started: std::time::Instant,
+    /// What the search was started with, for choosing the action
+    config: SearchConfig,
+    search: PhantomData<&'a mut Search<StateType, ActionType>>,
+}
+
+impl<StateType, ActionType> SearchHandle<'_, StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    /// A token that stops this search, which can be passed to other threads
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Whether every thread has stopped searching
+    pub fn is_finished(&self) -> bool {
+        self.threads.iter().all(|thread| thread.is_finished())
+    }
+
+    ///
+    /// The move the search would play if it stopped now, and what it's found
+    /// so far. The search carries on while this is worked out, so the
+    /// statistics can be an iteration or so out of step with each other.
+    ///
+    pub fn snapshot(&self) -> SearchResult<ActionType> {
+        self.result(false)
+    }
+
+    ///
+    /// The search tree, as `options` say - one for each thread in a
+    /// root-parallel search, and none for information set searches or when
+    /// there was nothing to search. Like [`SearchHandle::snapshot`], it can
+    /// be taken while the search runs ([`SearchHandle::wait`] first for the
+    /// tree the action is chosen from).
+    ///
+    pub fn export_trees(&self, options: &ExportOptions) -> Vec<ExportedNode> {
+        match &self.running {
+            Running::SharedTree(tree) => vec![export_tree(tree, options)],
+            Running::RootParallel(trees) => trees
+                .iter()
+                .map(|tree| export_tree(tree, options))
+                .collect(),
+            Running::Forced(_) | Running::InformationSets(_) => vec![],
+        }
+    }
+
+    /// Wait for the search to stop (cancel it first to stop it now)
+    pub fn wait(&mut self) {
+        for thread in std::mem::take(&mut self.threads) {
+            thread.join().unwrap();
+        }
+    }
+
+    ///
+    /// Wait for the search to stop (cancel it first to stop it now), and
+    /// return the move to play and what the search found.
+    ///
+    pub fn finish(mut self) -> SearchResult<ActionType> {
+        self.wait();
+        log::debug!(
+            "Completed {} iterations",
+            self.finished_iterations
+                .load(std::sync::atomic::Ordering::SeqCst)
+        );
+        match &self.running {
+            Running::SharedTree(tree) => log_tree_size(&tree.nodes),
+            Running::RootParallel(trees) => log::info!(
+                "Trees used {} nodes ({} bytes)",
+                trees.iter().map(|tree| tree.nodes.len()).sum::<usize>(),
+                trees
                     .iter()
-                    .max_by_key(|(_, node)| node.read().unwrap().visit_count())
-                    .unwrap()
-                    .0
-                    .clone()
-            } else {
-                panic!("Expected root to be an expanded node")
+                    .map(|tree| tree.nodes.used_bytes())
+                    .sum::<usize>()
+            ),
+            _ => {}
+        }
+        self.result(self.config.log_children)
+    }
+
+    fn result(&self, log_children: bool) -> SearchResult<ActionType> {
+        let iterations = self
+            .finished_iterations
+            .load(std::sync::atomic::Ordering::SeqCst);
+        let elapsed = self.started.elapsed();
+        match &self.running {
+            Running::Forced(action) => SearchResult::forced(action.clone()),
+            Running::SharedTree(tree) => search_result(
+                &tree.nodes,
+                tree.root,
+                &self.config,
+                log_children,
+                iterations,
+                elapsed,
+            ),
+            Running::RootParallel(trees) => {
+                root_parallel_result(trees, &self.config, log_children, iterations, elapsed)
+            }
+            Running::InformationSets(tree) => {
+                tree.search_result(self.config.policy, iterations, elapsed)
+            }
+        }
+    }
+}
+
+impl<StateType, ActionType> Drop for SearchHandle<'_, StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType> + Sync + Send + 'static,
+    ActionType: Action<StateType = StateType> + Sync + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        for thread in std::mem::take(&mut self.threads) {
+            // Don't panic in a drop - a panicking thread has already
+            // reported itself
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<StateType: State, ActionType: Action<StateType = StateType>> Drop
+    for Search<StateType, ActionType>
+{
+    fn drop(&mut self) {
+        if let Some(ponder) = self.ponder.take() {
+            ponder.cancel.cancel();
+            for thread in ponder.threads {
+                // Don't panic in a drop - a panicking ponder thread has
+                // already reported itself
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+///
+/// Merge the statistics of the trees' roots' children to choose the action
+/// (see [`merge_root`]), and gather up what the trees found
+///
+fn root_parallel_result<StateType, ActionType>(
+    trees: &[Arc<Tree<StateType, ActionType>>],
+    config: &SearchConfig,
+    log_children: bool,
+    iterations: usize,
+    elapsed: std::time::Duration,
+) -> SearchResult<ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    // The line under each of the roots' children, from each tree
+    let lines: Vec<(ActionType, u32, Vec<ActionType>)> = trees
+        .iter()
+        .flat_map(|tree| {
+            tree.nodes.children(tree.root).filter_map(|edge| {
+                let child = edge.child()?;
+                Some((
+                    edge.action.clone(),
+                    edge.visit_count(),
+                    tree.nodes.principal_variation(child),
+                ))
+            })
+        })
+        .collect();
+
+    // Merged into a fresh arena, so the trees can carry on being searched
+    let merged = NodeArena::new();
+    let root = merged.alloc(trees[0].nodes.shallow_copy(trees[0].root));
+    for edge in trees[0].nodes.children(trees[0].root) {
+        if let Some(child) = edge.child() {
+            merged.insert_child(
+                root,
+                edge.action.clone(),
+                trees[0].nodes.shallow_copy(child),
+            );
+        }
+    }
+    for tree in trees[1..].iter() {
+        merge_root(&merged, root, &tree.nodes, tree.root);
+    }
+    update_proven(&merged, root);
+    log::debug!("Merged trees with {} visits", merged[root].visit_count());
+
+    let mut result = search_result(&merged, root, config, log_children, iterations, elapsed);
+    result.node_count = trees.iter().map(|tree| tree.nodes.len()).sum();
+    result.used_bytes = trees.iter().map(|tree| tree.nodes.used_bytes()).sum();
+    result.max_depth = trees
+        .iter()
+        .map(|tree| tree.nodes.max_depth(tree.root))
+        .max()
+        .unwrap_or(0);
+    // Below the merged root, the line follows whichever tree searched the
+    // first move most
+    if let Some((_, _, line)) = result.principal_variation.first().and_then(|first| {
+        lines
+            .iter()
+            .filter(|(action, _, _)| action == first)
+            .max_by_key(|(_, visits, _)| *visits)
+    }) {
+        result.principal_variation.extend(line.iter().cloned());
+    }
+    result
+}
+
+///
+/// The trees' roots' children, as (action, visits, value sum), added
+/// together across the trees
+///
+fn merged_root_children<StateType, ActionType>(
+    trees: &[Arc<Tree<StateType, ActionType>>],
+) -> Vec<(ActionType, u32, f64)>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let mut children = root_children(&trees[0].nodes, trees[0].root);
+    for tree in trees[1..].iter() {
+        for (action, visits, value_sum) in root_children(&tree.nodes, tree.root) {
+            if let Some(child) = children.iter_mut().find(|(other, _, _)| *other == action) {
+                child.1 += visits;
+                child.2 += value_sum;
+            }
+        }
+    }
+    children
+}
+
+/// Report how big a tree grew
+fn log_tree_size<StateType, ActionType>(nodes: &NodeArena<StateType, ActionType>)
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    log::info!(
+        "Tree used {} nodes ({} bytes, {} allocated)",
+        nodes.len(),
+        nodes.used_bytes(),
+        nodes.allocated_bytes()
+    );
+}
+
+///
+/// Add the statistics of `other`'s children to `root`'s - children only
+/// `other` expanded are copied over (without their subtrees).
+///
+fn merge_root<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    root: NodeId,
+    other_nodes: &NodeArena<StateType, ActionType>,
+    other_root: NodeId,
+) where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    nodes.merge(root, other_nodes, other_root);
+    for edge in other_nodes.children(other_root) {
+        if let Some(merged_edge) = nodes.edge(root, &edge.action) {
+            merged_edge.merge(edge);
+        }
+        let Some(other_child) = edge.child() else {
+            continue;
+        };
+        match nodes.child(root, &edge.action) {
+            Some(child) => nodes.merge(child, other_nodes, other_child),
+            None => {
+                nodes.insert_child(
+                    root,
+                    edge.action.clone(),
+                    other_nodes.shallow_copy(other_child),
+                );
+            }
+        }
+    }
+}
+
+///
+/// Choose the action to play from a searched root (see [`choose_action`]),
+/// and gather up what the search found
+///
+fn search_result<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    root: NodeId,
+    config: &SearchConfig,
+    log_children: bool,
+    iterations: usize,
+    elapsed: std::time::Duration,
+) -> SearchResult<ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let action = choose_action(nodes, root, config, log_children);
+    let actions = nodes
+        .children(root)
+        .map(|edge| match edge.child() {
+            Some(child) => {
+                // Rewards are only summed per node, which for a child shared
+                // between transpositions counts visits through other parents
+                let reward_sums: Vec<Reward> = nodes
+                    .mean_rewards(child)
+                    .iter()
+                    .map(|mean| mean * edge.visit_count() as f64)
+                    .collect();
+                ActionStats::new(
+                    edge.action.clone(),
+                    edge.visit_count(),
+                    edge.value_sum(),
+                    edge.value_sq_sum(),
+                    &reward_sums,
+                    nodes[child].proven().cloned(),
+                )
+            }
+            None => ActionStats::new(edge.action.clone(), 0, 0.0, 0.0, &[], None),
+        })
+        .collect();
+    SearchResult {
+        action,
+        actions,
+        principal_variation: nodes.principal_variation(root),
+        iterations,
+        elapsed,
+        node_count: nodes.len(),
+        used_bytes: nodes.used_bytes(),
+        max_depth: nodes.max_depth(root),
+        fully_explored: nodes.fully_explored(root),
+        proven: nodes[root].proven().cloned(),
+        rewards: nodes.mean_rewards(root),
+    }
+}
+
+///
+/// Pick the action to play from a searched root: a proven win if there is
+/// one, otherwise by the configured policy, avoiding proven losses.
+///
+fn choose_action<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    root: NodeId,
+    config: &SearchConfig,
+    log_children: bool,
+) -> ActionType
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    if log::log_enabled!(log::Level::Trace) || log_children {
+        nodes.log_children(root, 0);
+    }
+    let proven = |edge: &Edge<ActionType>| edge.child().and_then(|child| nodes[child].proven());
+    // The visits through the root's own edges - a child shared between
+    // transpositions counts its visits through other parents too
+    let stats =
+        |edge: &Edge<ActionType>| (edge.visit_count(), edge.value_sum(), proven(edge).cloned());
+    let root_node = &nodes[root];
+    log::debug!(
+        "Action, Visits, Value, Proven: {:?}",
+        nodes
+            .children(root)
+            .map(|edge| (edge.action.clone(), stats(edge)))
+            .collect::<Vec<_>>()
+    );
+
+    let mut candidates: Vec<_> = nodes.children(root).collect();
+    if let Actor::Player(player) = root_node.state().next_actor() {
+        if let Some(action) = proven_choice(nodes, root, player) {
+            log::debug!("Picking proven {:?}", action);
+            return action;
+        }
+        // Don't pick a proven loss while anything else might not be
+        let unproven_losses: Vec<_> = candidates
+            .iter()
+            .filter(|edge| !proven(edge).is_some_and(|reward| loses(reward, player)))
+            .cloned()
+            .collect();
+        if !unproven_losses.is_empty() {
+            candidates = unproven_losses;
+        }
+    }
+
+    let picks: Vec<_> = candidates
+        .iter()
+        .map(|edge| {
+            let (visit_count, value_sum, _) = stats(edge);
+            (edge.action.clone(), visit_count, value_sum)
+        })
+        .collect();
+    config
+        .policy
+        .choose(&picks, config.exploration_constant, config.temperature)
+}
+
+/// The root's children, as (action, visits, value sum)
+fn root_children<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    root: NodeId,
+) -> Vec<(ActionType, u32, f64)>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    nodes
+        .children(root)
+        .map(|edge| match edge.child() {
+            Some(_) => (edge.action.clone(), edge.visit_count(), edge.value_sum()),
+            None => (edge.action.clone(), 0, 0.0),
+        })
+        .collect()
+}
+
+///
+/// Whether `kept` and `state` are the same position, by their hashes - a game
+/// without them can't tell, so its tree is never kept
+///
+fn same_position<StateType: State>(kept: &StateType, state: &StateType) -> bool {
+    match (kept.state_hash(), state.state_hash()) {
+        (Some(kept), Some(state)) => kept == state,
+        _ => false,
+    }
+}
+
+///
+/// Start `thread_count` threads calling `iterate` (with the thread's index),
+/// until `iterations` have been run between them, `time_limit` has passed,
+/// `cancel` is cancelled, or `iterate` returns true (there's nothing left for
+/// that thread to search).
+///
+/// Finished iterations are counted in `finished_iterations`.
+///
+fn spawn_iterations<F>(
+    iterations: usize,
+    time_limit: Option<std::time::Duration>,
+    thread_count: usize,
+    seed: Option<u64>,
+    cancel: &CancelToken,
+    finished_iterations: &Arc<AtomicUsize>,
+    iterate: F,
+) -> Vec<JoinHandle<()>>
+where
+    F: Fn(usize) -> bool + Send + Sync + 'static,
+{
+    let iterate = Arc::new(iterate);
+    let time_started = std::time::Instant::now();
+
+    (0..thread_count)
+        .map(|thread| {
+            let iterate_clone = Arc::clone(&iterate);
+            let cancel_clone = cancel.clone();
+            let finished_iterations_clone = Arc::clone(finished_iterations);
+            std::thread::spawn(move || {
+                if let Some(seed) = seed {
+                    seed_thread(derive_seed(seed, thread as u64));
+                }
+                while !cancel_clone.is_cancelled() {
+                    trace!(
+                        "Starting iteration {}",
+                        finished_iterations_clone.load(std::sync::atomic::Ordering::SeqCst)
+                    );
+                    let done = iterate_clone(thread);
+                    let current_iterations =
+                        finished_iterations_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    trace!("Finished iteration {}", current_iterations);
+                    if current_iterations >= iterations
+                        || done
+                        || time_started.elapsed() > time_limit.unwrap_or(std::time::Duration::MAX)
+                    {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+///
+/// The best proven child for `player`, if the root is proven or one of its
+/// children is a proven win.
+///
+fn proven_choice<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    root: NodeId,
+    player: u8,
+) -> Option<ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let proven: Vec<(ActionType, Vec<Reward>)> = nodes
+        .children(root)
+        .filter_map(|edge| {
+            let reward = nodes[edge.child()?].proven()?;
+            Some((edge.action.clone(), reward.clone()))
+        })
+        .collect();
+    let reward_range = nodes[root].state().reward_range();
+    if nodes[root].proven().is_none()
+        && !proven
+            .iter()
+            .any(|(_, reward)| wins(reward, player, reward_range))
+    {
+        return None;
+    }
+    proven
+        .into_iter()
+        .max_by(|(_, a), (_, b)| {
+            a[player as usize]
+                .partial_cmp(&b[player as usize])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(action, _)| action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::games::nt::{NTAction, NTState};
+    use crate::games::{C4, NT};
+    use crate::mon2y::node::{create_expanded_node, Node};
+    use crate::test::injectable_game::{InjectableGameAction, InjectableGameState};
+
+    #[test]
+    fn test_advance_keeps_subtree() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(3),
+                InjectableGameAction::WinInXTurns(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let mut search = Search::new(SearchConfig::default().with_iterations(100).with_threads(1));
+        search.calculate_best_turn(state).unwrap();
+
+        let child_visits = {
+            let (nodes, root) = search.root.as_ref().unwrap();
+            nodes[nodes.get_child(*root, InjectableGameAction::WinInXTurns(3))].visit_count()
+        };
+        assert!(child_visits > 0);
+
+        search.advance(&InjectableGameAction::WinInXTurns(3));
+        assert_eq!(search.root_visit_count(), Some(child_visits));
+
+        search.advance(&InjectableGameAction::Lose);
+        assert_eq!(search.root_visit_count(), None);
+    }
+
+    #[test]
+    fn test_advance_moves_transpositions_up() {
+        let mut search = Search::new(
+            SearchConfig::default()
+                .with_iterations(300)
+                .with_threads(1)
+                .with_transpositions(),
+        );
+        let action = search.calculate_best_turn(C4.init_game()).unwrap().action;
+        search.advance(&action);
+
+        let (nodes, root) = search.root.as_ref().unwrap();
+        let table = search.transpositions.as_ref().unwrap().read().unwrap();
+        assert!(!table.is_empty());
+        // Every node kept is as deep below the new root as the table says
+        for ((_, depth), node) in table.iter() {
+            let level = (0..*depth).fold(vec![*root], |level, _| {
+                level
+                    .iter()
+                    .flat_map(|id| nodes.children(*id).filter_map(|edge| edge.child()))
+                    .collect()
+            });
+            assert!(level.contains(node));
+        }
+    }
+
+    #[test]
+    fn test_choose_action_by_edge_visits() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(3),
+                InjectableGameAction::WinInXTurns(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(create_expanded_node(state.clone(), None));
+        for (action, edge_visits, node_visits) in [
+            (InjectableGameAction::WinInXTurns(3), 3, 10),
+            (InjectableGameAction::WinInXTurns(4), 5, 5),
+        ] {
+            let child = nodes.insert_child(
+                root,
+                action.clone(),
+                create_expanded_node(action.execute(&state), None),
+            );
+            for _ in 0..edge_visits {
+                nodes.edge(root, &action).unwrap().visit(0.0);
+            }
+            // As if shared, and also visited through another parent
+            for _ in 0..node_visits {
+                nodes.visit(child, 0.0, &[0.0]);
             }
         }
+        let config = SearchConfig::default().with_policy(BestTurnPolicy::MostVisits);
+        assert_eq!(
+            choose_action(&nodes, root, &config, false),
+            InjectableGameAction::WinInXTurns(4)
+        );
+    }
+
+    #[test]
+    fn test_missed_advance_discards_tree() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(60),
+                InjectableGameAction::WinInXTurns(70),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let mut search = Search::new(SearchConfig::default().with_iterations(50).with_threads(1));
+        search.calculate_best_turn(state.clone()).unwrap();
+        let kept_visits = search.root_visit_count().unwrap();
+
+        // The same position carries on from the kept tree
+        search.calculate_best_turn(state.clone()).unwrap();
+        assert!(search.root_visit_count().unwrap() > kept_visits);
+
+        // A move was played without advancing, so the tree is for the wrong
+        // position
+        let moved = InjectableGameAction::NextTurnInjectActionCount(3).execute(&state);
+        let result = search.calculate_best_turn(moved.clone()).unwrap();
+        assert!(moved.permitted_actions().contains(&result.action));
+        assert!(search.root_visit_count().unwrap() < kept_visits * 2);
+    }
+
+    #[test]
+    fn test_search_result() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::Lose,
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let result = calculate_best_turn(
+            state,
+            &SearchConfig::default().with_iterations(100).with_threads(1),
+        )
+        .unwrap();
+        assert_eq!(result.action, InjectableGameAction::WinInXTurns(1));
+        assert_eq!(
+            result.principal_variation,
+            vec![
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::WinInXTurns(0),
+                InjectableGameAction::Win
+            ]
+        );
+        // Everything's explored well before the iterations run out
+        assert!(result.iterations > 0 && result.iterations < 100);
+        assert_eq!(result.node_count, 5);
+        assert_eq!(result.max_depth, 3);
+        assert!(result.fully_explored);
+        assert_eq!(result.proven, Some(vec![1.0]));
+
+        let lose = &result.actions[1];
+        assert_eq!(lose.action, InjectableGameAction::Lose);
+        assert_eq!((lose.visits, lose.mean), (1, -1.0));
+        assert_eq!((lose.lower_bound, lose.upper_bound), (-1.0, -1.0));
+        assert_eq!(lose.proven, Some(vec![-1.0]));
+        assert_eq!(lose.rewards, vec![-1.0]);
+    }
+
+    ///
+    /// Test that the memory a search reports counts what its states allocate,
+    /// as well as the nodes
+    ///
+    #[test]
+    fn test_used_bytes_counts_states() {
+        let state = NT { player_count: 3 }.init_game();
+        let result = calculate_best_turn(
+            state.clone(),
+            &SearchConfig::default().with_iterations(50).with_threads(1),
+        )
+        .unwrap();
+        let node_bytes = std::mem::size_of::<Node<NTState, NTAction>>();
+        assert!(state.heap_bytes() > 0);
+        assert!(result.used_bytes >= result.node_count * (node_bytes + state.heap_bytes()));
+    }
+
+    #[test]
+    fn test_max_robust_searches_until_robust() {
+        let state = NT { player_count: 3 }.init_game();
+        let result = calculate_best_turn(
+            state,
+            &SearchConfig::default()
+                .with_iterations(20)
+                .with_threads(1)
+                .with_policy(BestTurnPolicy::MaxRobust),
+        )
+        .unwrap();
+        let children: Vec<_> = result
+            .actions
+            .iter()
+            .map(|stats| (stats.action, stats.visits, stats.mean * stats.visits as f64))
+            .collect();
+        // Either it got there, or it gave up at twice the iterations
+        assert!(BestTurnPolicy::is_robust(&children) || result.iterations > 40);
+        assert!(result.iterations >= 20);
+    }
+
+    #[test]
+    fn test_cancel_search() {
+        let state = NT { player_count: 3 }.init_game();
+        let mut search = Search::new(
+            SearchConfig::default()
+                .with_iterations(usize::MAX)
+                .with_threads(2),
+        );
+        let handle = search.start_search(state).unwrap();
+        while handle.snapshot().iterations < 50 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let snapshot = handle.snapshot();
+        assert!(!handle.is_finished());
+
+        handle.cancel_token().cancel();
+        let result = handle.finish();
+        assert!(result.iterations >= snapshot.iterations);
+        assert!(result.actions.iter().any(|stats| stats.visits > 0));
+        // The tree searched is kept
+        assert!(search.root_visit_count().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_cancel_unbounded_max_robust_search() {
+        let state = NT { player_count: 3 }.init_game();
+        let mut search = Search::new(
+            SearchConfig::default()
+                .with_iterations(usize::MAX)
+                .with_time_limit(std::time::Duration::MAX)
+                .with_threads(1)
+                .with_policy(BestTurnPolicy::MaxRobust),
+        );
+        let handle = search.start_search(state).unwrap();
+        while handle.snapshot().iterations < 20 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        handle.cancel_token().cancel();
+        let result = handle.finish();
+        assert!(result.iterations >= 20);
+    }
+
+    #[test]
+    fn test_pondering_grows_tree() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                // Long enough that the win isn't proven before the tree's
+                // grown
+                InjectableGameAction::WinInXTurns(60),
+                InjectableGameAction::WinInXTurns(70),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let mut search = Search::new(SearchConfig::default().with_iterations(50).with_threads(2));
+        search.start_pondering(&state);
+        assert!(search.is_pondering());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while search.root_visit_count().unwrap_or(0) < 10 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "Pondering didn't grow the tree"
+            );
+            std::thread::yield_now();
+        }
+        let pondered = search.stop_pondering();
+        assert!(!search.is_pondering());
+        assert!(pondered > 0);
+        assert!(search.root_visit_count().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_merge_root_adds_visits() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(3),
+                InjectableGameAction::WinInXTurns(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let trees: Vec<Tree<_, _>> = (0..2)
+            .map(|_| Tree::new(create_expanded_node(state.clone(), None)))
+            .collect();
+        for tree in trees.iter() {
+            for _ in 0..20 {
+                tree.iterate();
+            }
+        }
+        let visits = |tree: &Tree<_, _>, action| {
+            tree.nodes[tree.nodes.get_child(tree.root, action)].visit_count()
+        };
+        let expected: Vec<u32> = [
+            InjectableGameAction::WinInXTurns(3),
+            InjectableGameAction::WinInXTurns(4),
+        ]
+        .into_iter()
+        .map(|action| visits(&trees[0], action.clone()) + visits(&trees[1], action))
+        .collect();
+
+        merge_root(
+            &trees[0].nodes,
+            trees[0].root,
+            &trees[1].nodes,
+            trees[1].root,
+        );
+        assert_eq!(
+            visits(&trees[0], InjectableGameAction::WinInXTurns(3)),
+            expected[0]
+        );
+        assert_eq!(
+            visits(&trees[0], InjectableGameAction::WinInXTurns(4)),
+            expected[1]
+        );
+    }
+
+    #[test]
+    fn test_seeded_search_repeats() {
+        let search = || {
+            crate::mon2y::rng::seed_thread(7);
+            let state = NT { player_count: 3 }.init_game();
+            let mut search =
+                Search::new(SearchConfig::default().with_iterations(300).with_threads(1))
+                    .with_seed(11);
+            let result = search.calculate_best_turn(state).unwrap();
+            (result.action, result.actions, result.node_count)
+        };
+        assert_eq!(search(), search());
+        crate::mon2y::rng::unseed_thread();
     }
 }
@@ -0,0 +1,369 @@
+use super::game::{Actor, State};
+use super::prior::{normalize, Prior, PriorProvider};
+use super::rng::with_rng;
+use super::Reward;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+///
+/// A fully connected layer - `weights` holds a row of `inputs` weights for
+/// each output
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Layer {
+    inputs: usize,
+    outputs: usize,
+    weights: Vec<f64>,
+    biases: Vec<f64>,
+}
+
+impl Layer {
+    /// Weights drawn uniformly, scaled to the number of inputs (He)
+    fn new(inputs: usize, outputs: usize) -> Layer {
+        let limit = (6.0 / inputs as f64).sqrt();
+        let weights = with_rng(|rng| {
+            (0..inputs * outputs)
+                .map(|_| rng.gen_range(-limit..limit))
+                .collect()
+        });
+        Layer {
+            inputs,
+            outputs,
+            weights,
+            biases: vec![0.0; outputs],
+        }
+    }
+
+    fn forward(&self, input: &[f64]) -> Vec<f64> {
+        self.weights
+            .chunks(self.inputs)
+            .zip(self.biases.iter())
+            .map(|(row, bias)| bias + row.iter().zip(input).map(|(w, x)| w * x).sum::<f64>())
+            .collect()
+    }
+
+    ///
+    /// Add this layer's gradients for `input` and the gradient of its
+    /// outputs to `gradients`, and return the gradient of its input
+    ///
+    fn backward(&self, input: &[f64], output_gradient: &[f64], gradients: &mut Layer) -> Vec<f64> {
+        let mut input_gradient = vec![0.0; self.inputs];
+        for (output, gradient) in output_gradient.iter().enumerate() {
+            gradients.biases[output] += gradient;
+            let row = output * self.inputs;
+            for (i, x) in input.iter().enumerate() {
+                gradients.weights[row + i] += gradient * x;
+                input_gradient[i] += gradient * self.weights[row + i];
+            }
+        }
+        input_gradient
+    }
+
+    /// A layer of the same shape, all zeros
+    fn zeroed(&self) -> Layer {
+        Layer {
+            weights: vec![0.0; self.weights.len()],
+            biases: vec![0.0; self.biases.len()],
+            ..*self
+        }
+    }
+
+    /// Step against `gradients`, shrinking the weights by `decay`
+    fn step(&mut self, gradients: &Layer, rate: f64, decay: f64) {
+        for (weight, gradient) in self.weights.iter_mut().zip(gradients.weights.iter()) {
+            *weight -= rate * (gradient + decay * *weight);
+        }
+        for (bias, gradient) in self.biases.iter_mut().zip(gradients.biases.iter()) {
+            *bias -= rate * gradient;
+        }
+    }
+}
+
+///
+/// A position searched in self-play, and what the network should learn
+/// from it
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// [`State::features`] of the position
+    pub features: Vec<f64>,
+    /// How the search's visits were spread over the action slots (see
+    /// [`State::action_index`])
+    pub policy: Vec<f64>,
+    /// Every player's final reward, starting from the player to move and in
+    /// turn order after that
+    pub value: Vec<Reward>,
+}
+
+impl Sample {
+    ///
+    /// The cross entropy of `policy` against this sample's, and the squared
+    /// error of `value` summed over the players
+    ///
+    fn loss(&self, policy: &[f64], value: &[Reward]) -> (f64, f64) {
+        let policy_loss = -self
+            .policy
+            .iter()
+            .zip(policy.iter())
+            .map(|(target, p)| target * p.max(1e-12).ln())
+            .sum::<f64>();
+        let value_loss = self
+            .value
+            .iter()
+            .zip(value.iter())
+            .map(|(target, v)| (v - target).powi(2))
+            .sum::<f64>();
+        (policy_loss, value_loss)
+    }
+}
+
+///
+/// A small multilayer perceptron, trained on self-play to give priors and
+/// values (see [`NetworkPriors`]).
+///
+/// The hidden layers are ReLUs. The policy head is a softmax over the game's
+/// action slots, and the value head estimates every player's reward, starting
+/// from the player to move - so the same network plays every seat.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Network {
+    hidden: Vec<Layer>,
+    policy: Layer,
+    value: Layer,
+}
+
+impl Network {
+    pub fn new(inputs: usize, hidden: &[usize], policy_size: usize, players: usize) -> Network {
+        let mut layers = vec![];
+        let mut width = inputs;
+        for size in hidden {
+            layers.push(Layer::new(width, *size));
+            width = *size;
+        }
+        Network {
+            hidden: layers,
+            policy: Layer::new(width, policy_size),
+            value: Layer::new(width, players),
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.hidden.first().unwrap_or(&self.policy).inputs
+    }
+
+    pub fn policy_size(&self) -> usize {
+        self.policy.outputs
+    }
+
+    pub fn players(&self) -> usize {
+        self.value.outputs
+    }
+
+    /// Every layer's activations, from the features to the last hidden layer
+    fn activations(&self, features: &[f64]) -> Vec<Vec<f64>> {
+        let mut activations = vec![features.to_vec()];
+        for layer in self.hidden.iter() {
+            let output = layer
+                .forward(activations.last().unwrap())
+                .into_iter()
+                .map(|x| x.max(0.0))
+                .collect();
+            activations.push(output);
+        }
+        activations
+    }
+
+    ///
+    /// The probability of each action slot, and every player's reward
+    /// (starting from the player to move)
+    ///
+    pub fn predict(&self, features: &[f64]) -> (Vec<f64>, Vec<Reward>) {
+        let activations = self.activations(features);
+        let last = activations.last().unwrap();
+        (
+            softmax(&self.policy.forward(last)),
+            self.value.forward(last),
+        )
+    }
+
+    ///
+    /// One step of gradient descent on `batch`, minimising the policy's
+    /// cross entropy plus the value's squared error. Returns the batch's mean
+    /// loss before the step.
+    ///
+    pub fn train(&mut self, batch: &[Sample], learning_rate: f64, weight_decay: f64) -> f64 {
+        let mut hidden_gradients: Vec<Layer> = self.hidden.iter().map(Layer::zeroed).collect();
+        let mut policy_gradients = self.policy.zeroed();
+        let mut value_gradients = self.value.zeroed();
+        let mut loss = 0.0;
+        for sample in batch {
+            let activations = self.activations(&sample.features);
+            let last = activations.last().unwrap();
+            let policy = softmax(&self.policy.forward(last));
+            let value = self.value.forward(last);
+            let (policy_loss, value_loss) = sample.loss(&policy, &value);
+            loss += policy_loss + value_loss;
+
+            let policy_gradient: Vec<f64> = policy
+                .iter()
+                .zip(sample.policy.iter())
+                .map(|(p, target)| p - target)
+                .collect();
+            let value_gradient: Vec<f64> = value
+                .iter()
+                .zip(sample.value.iter())
+                .map(|(v, target)| 2.0 * (v - target))
+                .collect();
+            let mut gradient = self
+                .policy
+                .backward(last, &policy_gradient, &mut policy_gradients);
+            for (g, value_g) in gradient.iter_mut().zip(self.value.backward(
+                last,
+                &value_gradient,
+                &mut value_gradients,
+            )) {
+                *g += value_g;
+            }
+            for (i, layer) in self.hidden.iter().enumerate().rev() {
+                // Through the ReLU
+                for (g, activation) in gradient.iter_mut().zip(activations[i + 1].iter()) {
+                    if *activation <= 0.0 {
+                        *g = 0.0;
+                    }
+                }
+                gradient = layer.backward(&activations[i], &gradient, &mut hidden_gradients[i]);
+            }
+        }
+
+        let rate = learning_rate / batch.len().max(1) as f64;
+        // Decay is scaled back up, so it doesn't depend on the batch size
+        let decay = weight_decay * batch.len() as f64;
+        for (layer, gradients) in self.hidden.iter_mut().zip(hidden_gradients.iter()) {
+            layer.step(gradients, rate, decay);
+        }
+        self.policy.step(&policy_gradients, rate, decay);
+        self.value.step(&value_gradients, rate, decay);
+        loss / batch.len().max(1) as f64
+    }
+
+    ///
+    /// The mean policy cross entropy and value squared error over `samples`,
+    /// without training on them - for checking the network on samples it
+    /// hasn't seen
+    ///
+    pub fn loss(&self, samples: &[Sample]) -> (f64, f64) {
+        let (policy_loss, value_loss) = samples
+            .iter()
+            .map(|sample| {
+                let (policy, value) = self.predict(&sample.features);
+                sample.loss(&policy, &value)
+            })
+            .fold((0.0, 0.0), |(p, v), (policy, value)| {
+                (p + policy, v + value)
+            });
+        let count = samples.len().max(1) as f64;
+        (policy_loss / count, value_loss / count)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Network> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+fn softmax(logits: &[f64]) -> Vec<f64> {
+    let max = logits.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let exps: Vec<f64> = logits.iter().map(|logit| (logit - max).exp()).collect();
+    let total: f64 = exps.iter().sum();
+    exps.into_iter().map(|exp| exp / total).collect()
+}
+
+///
+/// Priors and values from a [`Network`], for states with
+/// [`State::features`]. States without them get uniform priors, and are
+/// played out as usual.
+///
+pub struct NetworkPriors {
+    network: Arc<Network>,
+}
+
+impl NetworkPriors {
+    pub fn new(network: Arc<Network>) -> NetworkPriors {
+        NetworkPriors { network }
+    }
+}
+
+impl<StateType: State> PriorProvider<StateType> for NetworkPriors {
+    fn prior(&self, state: &StateType, actions: &[StateType::ActionType]) -> Prior {
+        let (Some(features), Actor::Player(player)) = (state.features(), state.next_actor()) else {
+            return Prior {
+                policy: normalize(&vec![1.0; actions.len()]),
+                value: None,
+            };
+        };
+        let (policy, value) = self.network.predict(&features);
+        let weights: Vec<f64> = actions
+            .iter()
+            .map(|action| {
+                state
+                    .action_index(action)
+                    .and_then(|index| policy.get(index))
+                    .copied()
+                    .unwrap_or(0.0)
+            })
+            .collect();
+        // Back from the player to move's seat to every player's
+        let players = value.len();
+        let value = (0..players)
+            .map(|seat| value[(seat + players - player as usize % players) % players])
+            .collect();
+        Prior {
+            policy: normalize(&weights),
+            value: Some(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::rng;
+
+    #[test]
+    fn test_network_learns_samples() {
+        rng::seed_thread(11);
+        let samples = vec![
+            Sample {
+                features: vec![1.0, 0.0],
+                policy: vec![1.0, 0.0, 0.0],
+                value: vec![1.0, -1.0],
+            },
+            Sample {
+                features: vec![0.0, 1.0],
+                policy: vec![0.0, 0.0, 1.0],
+                value: vec![-1.0, 1.0],
+            },
+        ];
+        let mut network = Network::new(2, &[8], 3, 2);
+        let first_loss = network.train(&samples, 0.1, 0.0);
+        let mut loss = first_loss;
+        for _ in 0..500 {
+            loss = network.train(&samples, 0.1, 0.0);
+        }
+        rng::unseed_thread();
+        assert!(
+            loss < first_loss / 10.0,
+            "Loss went from {} to {}",
+            first_loss,
+            loss
+        );
+        let (policy, value) = network.predict(&[0.0, 1.0]);
+        assert!(policy[2] > 0.9);
+        assert!((value[0] + 1.0).abs() < 0.1);
+    }
+}
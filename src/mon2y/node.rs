@@ -1,374 +1,956 @@
+use super::arena::Arena;
 use super::game::{Action, Actor, State};
-use core::panic;
-use log::{trace, warn};
+use super::prior::PriorProvider;
+use super::rng::with_rng;
+use super::selection::{ChildStats, SelectionPolicy, Ucb1};
+use super::Reward;
+use log::trace;
 use rand::Rng;
-use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
-};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
 #[cfg(not(test))]
 const RANDOM_FACTOR: f64 = 1e-6;
 #[cfg(test)]
 const RANDOM_FACTOR: f64 = 0.0;
 
-#[derive(Debug)]
-pub struct CachedUcb {
-    ucb: f64,
-    value_sum: f64,
-    visit_count: u32,
-    parent_visit_count: u32,
+///
+/// How many outcomes are drawn for a chance node whose outcomes are sampled.
+///
+/// Only the outcomes drawn get children, so the one the game actually plays
+/// may not be among them - and then [`super::mcts::Search::advance`] has no
+/// subtree to keep. Games that can list their outcomes should use
+/// [`Actor::GameAction`] instead.
+///
+pub const SAMPLED_OUTCOMES: usize = 32;
+
+///
+/// An `f64` that can be added to from many threads without a lock (stored as
+/// its bits in an [`AtomicU64`]).
+///
+#[derive(Debug, Default)]
+pub struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    pub fn new(value: f64) -> AtomicF64 {
+        AtomicF64(AtomicU64::new(value.to_bits()))
+    }
+
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn fetch_add(&self, value: f64) {
+        // Can't fail - the closure always returns Some
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
 }
 
+/// Where a node is in its [`NodeArena`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+///
+/// The link from a node to the child reached by `action`.
+///
+/// The child is only built the first time it's selected, and is never
+/// replaced after that - so reading it needs no lock.
+///
+/// Visits are counted on the edge as well as the child. With transpositions
+/// a child can have several parents, and its own statistics count the visits
+/// through all of them - the edge's only count this parent's, which is what
+/// its selection needs.
+///
 #[derive(Debug)]
-pub enum Node<StateType: State, ActionType: Action<StateType = StateType>> {
-    Expanded {
-        state: StateType,
-        children: HashMap<ActionType, Arc<RwLock<Node<StateType, ActionType>>>>,
-        visit_count: u32,
-        /// Sum of rewards for this player
-        value_sum: f64,
-        cached_ucb: RwLock<Option<CachedUcb>>,
-        cached_fully_explored: RwLock<Option<bool>>,
-        game_action: bool,
-        weight: Option<u32>,
-    },
-    Placeholder {
-        weight: Option<u32>,
-    },
+pub struct Edge<ActionType> {
+    pub action: ActionType,
+    /// The weight of a game action, for the children of chance nodes
+    pub weight: Option<u32>,
+    /// The prior probability of a player's action, if there's a
+    /// [`PriorProvider`]
+    pub prior: Option<f64>,
+    child: OnceLock<NodeId>,
+    visit_count: AtomicU32,
+    /// Sum of the values of the visits through this edge, for the player
+    /// choosing it
+    value_sum: AtomicF64,
+    value_sq_sum: AtomicF64,
 }
 
-impl<StateType: State, ActionType: Action<StateType = StateType>> Node<StateType, ActionType> {
-    pub fn fully_explored(&self) -> bool {
-        match self {
-            Node::Expanded {
-                children,
-                cached_fully_explored,
-                ..
-            } => {
-                if let Ok(cached_fully_explored_read) = cached_fully_explored.try_read() {
-                    if let Some(cached_fully_explored_value) = *cached_fully_explored_read {
-                        //log::error!("CACHE HIT");
-                        return cached_fully_explored_value;
-                    }
-                }
-                //log::error!("CACHE MISS");
-                let child_nodes: Vec<Arc<RwLock<Node<StateType, ActionType>>>> =
-                    { children.values().cloned().collect() };
-                let fully_explored = child_nodes.is_empty()
-                    || child_nodes.iter().all(|child| {
-                        let child = child.clone();
-                        let child_node = child.read().unwrap();
-                        match *child_node {
-                            Node::Expanded { .. } => child_node.fully_explored(),
-                            Node::Placeholder { .. } => false,
-                        }
-                    });
-                if let Ok(mut cached_fully_explored) = cached_fully_explored.try_write() {
-                    *cached_fully_explored = Some(fully_explored);
-                    // log::error!("CACHE WRITE");
-                };
-                fully_explored
-            }
-            Node::Placeholder { .. } => false,
+impl<ActionType> Edge<ActionType> {
+    fn new(action: ActionType, weight: Option<u32>) -> Edge<ActionType> {
+        Edge {
+            action,
+            weight,
+            prior: None,
+            child: OnceLock::new(),
+            visit_count: AtomicU32::new(0),
+            value_sum: AtomicF64::new(0.0),
+            value_sq_sum: AtomicF64::new(0.0),
         }
     }
 
+    /// The child, if it's been expanded
+    pub fn child(&self) -> Option<NodeId> {
+        self.child.get().copied()
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+
     pub fn visit_count(&self) -> u32 {
-        match self {
-            Node::Expanded { visit_count, .. } => *visit_count,
-            Node::Placeholder { .. } => 0,
+        self.visit_count.load(Ordering::Relaxed)
+    }
+
+    pub fn value_sum(&self) -> f64 {
+        self.value_sum.load()
+    }
+
+    pub fn value_sq_sum(&self) -> f64 {
+        self.value_sq_sum.load()
+    }
+
+    /// Count a visit through this edge worth `value` to the player choosing it
+    pub fn visit(&self, value: f64) {
+        self.visit_count.fetch_add(1, Ordering::Relaxed);
+        self.value_sum.fetch_add(value);
+        self.value_sq_sum.fetch_add(value * value);
+    }
+
+    ///
+    /// Add the statistics from the same edge in another search (such as
+    /// another root-parallel tree) to this one's
+    ///
+    pub fn merge(&self, other: &Edge<ActionType>) {
+        self.visit_count
+            .fetch_add(other.visit_count(), Ordering::Relaxed);
+        self.value_sum.fetch_add(other.value_sum());
+        self.value_sq_sum.fetch_add(other.value_sq_sum());
+    }
+}
+
+///
+/// A node in the search tree.
+///
+/// The statistics are atomics, and the edges to the children are fixed when
+/// the node is created, so nodes are shared between threads without locks.
+/// Nodes live in a [`NodeArena`], and refer to their children by [`NodeId`].
+/// Their edges, reward sums and estimate are kept in the arena too, each
+/// node's next to each other.
+///
+#[derive(Debug)]
+pub struct Node<StateType: State, ActionType: Action<StateType = StateType>> {
+    state: StateType,
+    /// Where the edges to the children are in the arena
+    children: Span,
+    visit_count: AtomicU32,
+    /// Sum of the values of the rewards to the player who chose this node
+    /// (see [`super::Backup`])
+    value_sum: AtomicF64,
+    /// Sum of squared values for the player who chose this node
+    value_sq_sum: AtomicF64,
+    /// Where the sum of every player's rewards is in the arena - sized by the
+    /// first reward added
+    reward_sums: OnceLock<Span>,
+    /// Set once every child is known to be fully explored - the tree only
+    /// grows, so it never goes back
+    fully_explored: AtomicBool,
+    game_action: bool,
+    weight: Option<u32>,
+    /// The reward for every player under perfect play, once it's known
+    /// (from a terminal state, or backed up from the children)
+    proven: OnceLock<Vec<Reward>>,
+    /// Iterations currently running through this node, which count as
+    /// virtual losses until their reward is propagated
+    in_flight: AtomicU32,
+    /// Where every player's reward as the [`PriorProvider`] estimated it is
+    /// in the arena, used in place of playing out from here
+    estimate: Option<Span>,
+    action: PhantomData<ActionType>,
+}
+
+///
+/// A run of values next to each other in one of a [`NodeArena`]'s arenas.
+///
+/// Keeping them there rather than boxed saves an allocation for each, though
+/// an arena slot takes a few bytes more than its value.
+///
+#[derive(Debug, Clone, Copy, Default)]
+struct Span {
+    start: u32,
+    len: u32,
+}
+
+impl Span {
+    fn alloc<T, I>(arena: &Arena<T>, values: I) -> Span
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let values = values.into_iter();
+        let len = values.len() as u32;
+        Span {
+            start: arena.alloc_range(values),
+            len,
         }
     }
 
+    fn get<T>(
+        self,
+        arena: &Arena<T>,
+    ) -> impl ExactSizeIterator<Item = &T> + DoubleEndedIterator + Clone {
+        arena.get_range(self.start, self.len)
+    }
+}
+
+impl<StateType: State, ActionType: Action<StateType = StateType>> Node<StateType, ActionType> {
+    pub fn visit_count(&self) -> u32 {
+        self.visit_count.load(Ordering::Relaxed)
+    }
+
     pub fn game_action(&self) -> bool {
-        match self {
-            Node::Expanded { game_action, .. } => *game_action,
-            Node::Placeholder { .. } => false,
-        }
+        self.game_action
     }
 
     pub fn value_sum(&self) -> f64 {
-        match self {
-            Node::Expanded { value_sum, .. } => *value_sum,
-            Node::Placeholder { .. } => 0.0,
-        }
+        self.value_sum.load()
+    }
+
+    pub fn value_sq_sum(&self) -> f64 {
+        self.value_sq_sum.load()
+    }
+
+    pub fn proven(&self) -> Option<&Vec<Reward>> {
+        self.proven.get()
+    }
+
+    ///
+    /// Record the reward under perfect play. A node is only ever proven once
+    /// - if another thread got there first, its value is kept.
+    ///
+    pub fn set_proven(&self, reward: Vec<Reward>) {
+        let _ = self.proven.set(reward);
+    }
+
+    ///
+    /// Visits as seen by selection - each iteration in flight adds
+    /// `virtual_loss` visits
+    ///
+    pub fn effective_visit_count(&self, virtual_loss: u32) -> u32 {
+        self.visit_count() + self.virtual_visits(virtual_loss)
+    }
+
+    /// The losing visits the iterations in flight through this node add
+    pub fn virtual_visits(&self, virtual_loss: u32) -> u32 {
+        self.in_flight.load(Ordering::Relaxed) * virtual_loss
+    }
+
+    ///
+    /// Value sum as seen by selection - each virtual visit is worth
+    /// `loss_value`
+    ///
+    pub fn effective_value_sum(&self, virtual_loss: u32, loss_value: f64) -> f64 {
+        self.value_sum()
+            + (self.in_flight.load(Ordering::Relaxed) * virtual_loss) as f64 * loss_value
+    }
+
+    /// Squared value sum as seen by selection (see [`Node::effective_value_sum`])
+    pub fn effective_value_sq_sum(&self, virtual_loss: u32, loss_value: f64) -> f64 {
+        self.value_sq_sum()
+            + (self.in_flight.load(Ordering::Relaxed) * virtual_loss) as f64
+                * loss_value
+                * loss_value
+    }
+
+    /// Mark an iteration as running through this node
+    pub fn add_in_flight(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Mark an iteration through this node as finished
+    pub fn remove_in_flight(&self) {
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_flight| {
+                Some(in_flight.saturating_sub(1))
+            });
     }
 
     pub fn weight(&self) -> u32 {
-        match self {
-            Node::Expanded { weight, .. } => weight.unwrap_or(1),
-            Node::Placeholder { weight, .. } => weight.unwrap_or(1),
-        }
+        self.weight.unwrap_or(1)
     }
 
-    pub fn visit(&mut self, reward: f64) {
-        match self {
-            Node::Expanded {
-                visit_count,
-                value_sum,
-                cached_fully_explored,
-                ..
-            } => {
-                *visit_count += 1;
-                *value_sum += reward as f64;
-                if let Ok(mut cached_fully_explored) = cached_fully_explored.write() {
-                    *cached_fully_explored = None;
-                } else {
-                    panic!("Can't write cached fully explored");
-                }
-            }
-            Node::Placeholder { .. } => {
-                warn!("Visiting placeholder node");
-            }
-        }
+    fn add_visit(&self, visit_count: u32, value_sum: f64, value_sq_sum: f64) {
+        self.visit_count.fetch_add(visit_count, Ordering::Relaxed);
+        self.value_sum.fetch_add(value_sum);
+        self.value_sq_sum.fetch_add(value_sq_sum);
     }
 
-    pub fn cache_ucb(&self, ucb: f64, value_sum: f64, visit_count: u32, parent_visit_count: u32) {
-        match self {
-            Node::Expanded { cached_ucb, .. } => {
-                if let Ok(mut cached_ucb_ref) = cached_ucb.try_write() {
-                    *cached_ucb_ref = Some(CachedUcb {
-                        ucb,
-                        value_sum,
-                        visit_count,
-                        parent_visit_count,
-                    });
-                }
-            }
-            Node::Placeholder { .. } => {}
+    pub fn state(&self) -> &StateType {
+        &self.state
+    }
+
+    pub fn new_expanded(
+        state: StateType,
+        weight: Option<u32>,
+    ) -> NewNode<StateType, <StateType as State>::ActionType> {
+        create_expanded_node(state, weight)
+    }
+}
+
+///
+/// A node that hasn't been added to a [`NodeArena`] yet, with the edges,
+/// estimate and reward sums the arena keeps apart from it
+///
+#[derive(Debug)]
+pub struct NewNode<StateType: State, ActionType: Action<StateType = StateType>> {
+    node: Node<StateType, ActionType>,
+    children: Vec<Edge<ActionType>>,
+    estimate: Option<Vec<Reward>>,
+    reward_sums: Vec<Reward>,
+}
+
+impl<StateType: State, ActionType: Action<StateType = StateType>> NewNode<StateType, ActionType> {
+    pub fn state(&self) -> &StateType {
+        &self.node.state
+    }
+
+    pub fn children(&self) -> &[Edge<ActionType>] {
+        &self.children
+    }
+}
+
+///
+/// Storage for the nodes of a search.
+///
+/// Nodes are only ever added, and are all freed at once when the arena is
+/// dropped - so pruning a tree means copying the part that's kept into a new
+/// arena (see [`NodeArena::copy_subtree`]).
+///
+pub struct NodeArena<StateType: State, ActionType: Action<StateType = StateType>> {
+    nodes: Arena<Node<StateType, ActionType>>,
+    edges: Arena<Edge<ActionType>>,
+    rewards: Arena<AtomicF64>,
+    estimates: Arena<Reward>,
+    /// Bytes the states of every node added have allocated (see
+    /// [`State::heap_bytes`])
+    state_bytes: AtomicUsize,
+}
+
+impl<StateType, ActionType> Default for NodeArena<StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<StateType, ActionType> std::ops::Index<NodeId> for NodeArena<StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    type Output = Node<StateType, ActionType>;
+
+    fn index(&self, id: NodeId) -> &Node<StateType, ActionType> {
+        self.nodes.get(id.0)
+    }
+}
+
+impl<StateType, ActionType> NodeArena<StateType, ActionType>
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    pub fn new() -> NodeArena<StateType, ActionType> {
+        NodeArena {
+            nodes: Arena::new(),
+            edges: Arena::new(),
+            rewards: Arena::new(),
+            estimates: Arena::new(),
+            state_bytes: AtomicUsize::new(0),
         }
     }
 
-    pub fn cached_ucb(
-        &self,
-        value_sum: f64,
-        visit_count: u32,
-        parent_visit_count: u32,
-    ) -> Option<f64> {
-        match self {
-            Node::Expanded { cached_ucb, .. } => {
-                let ucb = cached_ucb.read().unwrap();
-                match *ucb {
-                    Some(CachedUcb {
-                        ucb: cached_ucb,
-                        value_sum: cached_value_sum,
-                        visit_count: cached_visit_count,
-                        parent_visit_count: cached_parent_visit_count,
-                    }) => {
-                        if cached_value_sum == value_sum
-                            && cached_visit_count == visit_count
-                            && cached_parent_visit_count == parent_visit_count
-                        {
-                            Some(cached_ucb)
-                        } else {
-                            None
-                        }
-                    }
-                    None => None,
-                }
-            }
-            Node::Placeholder { .. } => None,
+    pub fn alloc(&self, node: NewNode<StateType, ActionType>) -> NodeId {
+        let NewNode {
+            mut node,
+            children,
+            estimate,
+            reward_sums,
+        } = node;
+        node.children = Span::alloc(&self.edges, children);
+        node.estimate = estimate.map(|estimate| Span::alloc(&self.estimates, estimate));
+        if !reward_sums.is_empty() {
+            let sums = reward_sums.into_iter().map(AtomicF64::new);
+            let _ = node.reward_sums.set(Span::alloc(&self.rewards, sums));
         }
+        self.state_bytes
+            .fetch_add(node.state.heap_bytes(), Ordering::Relaxed);
+        NodeId(self.nodes.alloc(node))
     }
 
-    pub fn expansion(
+    /// The edges from `id` to its children
+    pub fn children(
         &self,
-        action: ActionType,
-        parent_state: &<ActionType as Action>::StateType,
-    ) -> Node<StateType, <StateType as State>::ActionType> {
-        match self {
-            Node::Expanded { .. } => {
-                panic!("Expanding an expanded node");
-            }
-            Node::Placeholder { weight, .. } => {
-                let state = action.execute(parent_state);
-                Self::new_expanded(state, *weight)
-            }
-        }
+        id: NodeId,
+    ) -> impl ExactSizeIterator<Item = &Edge<ActionType>> + DoubleEndedIterator + Clone {
+        self[id].children.get(&self.edges)
     }
 
-    pub fn state(&self) -> &StateType {
-        match self {
-            Node::Expanded { state, .. } => state,
-            Node::Placeholder { .. } => panic!("Placeholder node has no state"),
+    pub fn edge(&self, id: NodeId, action: &ActionType) -> Option<&Edge<ActionType>> {
+        self.children(id).find(|edge| edge.action == *action)
+    }
+
+    /// The child of `id` reached by `action`, if it's been expanded
+    pub fn child(&self, id: NodeId, action: &ActionType) -> Option<NodeId> {
+        self.edge(id, action).and_then(|edge| edge.child())
+    }
+
+    /// The estimate of every player's reward from `id`, if there is one
+    pub fn estimate(&self, id: NodeId) -> Option<Vec<Reward>> {
+        self[id]
+            .estimate
+            .map(|estimate| estimate.get(&self.estimates).copied().collect())
+    }
+
+    /// Sum of every player's rewards (empty if there haven't been any)
+    pub fn reward_sums(&self, id: NodeId) -> Vec<Reward> {
+        self[id]
+            .reward_sums
+            .get()
+            .map(|sums| sums.get(&self.rewards).map(AtomicF64::load).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every player's mean reward (empty if there haven't been any)
+    pub fn mean_rewards(&self, id: NodeId) -> Vec<Reward> {
+        let visits = self[id].visit_count().max(1) as f64;
+        self.reward_sums(id)
+            .into_iter()
+            .map(|sum| sum / visits)
+            .collect()
+    }
+
+    ///
+    /// Count a visit to `id` worth `value` to the player who chose it, with
+    /// `reward` going to each player
+    ///
+    pub fn visit(&self, id: NodeId, value: f64, reward: &[Reward]) {
+        self[id].add_visit(1, value, value * value);
+        self.add_reward_sums(id, reward);
+    }
+
+    fn add_reward_sums(&self, id: NodeId, reward: &[Reward]) {
+        if reward.is_empty() {
+            return;
+        }
+        let sums = self[id].reward_sums.get_or_init(|| {
+            Span::alloc(&self.rewards, reward.iter().map(|_| AtomicF64::default()))
+        });
+        for (sum, reward) in sums.get(&self.rewards).zip(reward.iter()) {
+            sum.fetch_add(*reward);
         }
     }
 
-    pub fn insert_child(&mut self, action: ActionType, child: Node<StateType, ActionType>) {
-        if let Node::Expanded { children, .. } = self {
-            children.insert(action, Arc::new(RwLock::new(child)));
-        } else {
-            panic!("Inserting child into placeholder");
+    ///
+    /// Add the statistics from another search of the same position (such as
+    /// another root-parallel tree) to `id`'s. A proven value is taken from
+    /// either.
+    ///
+    pub fn merge(&self, id: NodeId, other: &NodeArena<StateType, ActionType>, other_id: NodeId) {
+        let other_node = &other[other_id];
+        self[id].add_visit(
+            other_node.visit_count(),
+            other_node.value_sum(),
+            other_node.value_sq_sum(),
+        );
+        self.add_reward_sums(id, &other.reward_sums(other_id));
+        if let Some(reward) = other_node.proven() {
+            self[id].set_proven(reward.clone());
         }
     }
 
-    pub fn get_child(&self, action: ActionType) -> Arc<RwLock<Node<StateType, ActionType>>> {
-        if let Node::Expanded { children, .. } = self {
-            children.get(&action).unwrap().clone()
-        } else {
-            panic!("Getting child from placeholder");
+    ///
+    /// A copy of `id`'s state and statistics (and its edges'), without its
+    /// subtree - the copy's children are all unexpanded
+    ///
+    pub fn shallow_copy(&self, id: NodeId) -> NewNode<StateType, ActionType> {
+        let node = &self[id];
+        NewNode {
+            node: Node {
+                state: node.state.clone(),
+                children: Span::default(),
+                visit_count: AtomicU32::new(node.visit_count()),
+                value_sum: AtomicF64::new(node.value_sum()),
+                value_sq_sum: AtomicF64::new(node.value_sq_sum()),
+                reward_sums: OnceLock::new(),
+                fully_explored: AtomicBool::new(false),
+                game_action: node.game_action,
+                weight: node.weight,
+                proven: node.proven.clone(),
+                in_flight: AtomicU32::new(0),
+                estimate: None,
+                action: PhantomData,
+            },
+            children: self
+                .children(id)
+                .map(|edge| {
+                    let copy = Edge {
+                        prior: edge.prior,
+                        ..Edge::new(edge.action.clone(), edge.weight)
+                    };
+                    copy.merge(edge);
+                    copy
+                })
+                .collect(),
+            estimate: self.estimate(id),
+            reward_sums: self.reward_sums(id),
         }
     }
 
-    pub fn new_expanded(
-        state: StateType,
-        weight: Option<u32>,
-    ) -> Node<StateType, <StateType as State>::ActionType> {
-        create_expanded_node(state, weight)
+    /// How many nodes have been added
+    pub fn len(&self) -> usize {
+        self.nodes.len()
     }
 
-    pub fn get_node_by_path(
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    ///
+    /// The child on `edge` (one of `parent`'s), expanding it with `expand` if
+    /// no thread has yet. Every thread gets the same child.
+    ///
+    pub fn expand<F>(&self, edge: &Edge<ActionType>, expand: F) -> NodeId
+    where
+        F: FnOnce() -> NodeId,
+    {
+        *edge.child.get_or_init(expand)
+    }
+
+    ///
+    /// Add `child` as the child of `parent` reached by `action`, for building
+    /// trees by hand. If that child's already been expanded, it's kept, and
+    /// `child` is dropped.
+    ///
+    /// Panics if `action` isn't one of `parent`'s.
+    ///
+    pub fn insert_child(
         &self,
-        path: Vec<ActionType>,
-    ) -> Arc<RwLock<Node<StateType, ActionType>>> {
+        parent: NodeId,
+        action: ActionType,
+        child: NewNode<StateType, ActionType>,
+    ) -> NodeId {
+        let edge = self
+            .edge(parent, &action)
+            .unwrap_or_else(|| panic!("No edge for {:?}", action));
+        self.expand(edge, || self.alloc(child))
+    }
+
+    ///
+    /// The expanded child of `parent` reached by `action`. Panics if there
+    /// isn't one.
+    ///
+    pub fn get_child(&self, parent: NodeId, action: ActionType) -> NodeId {
+        self.child(parent, &action)
+            .unwrap_or_else(|| panic!("No expanded child for {:?}", action))
+    }
+
+    pub fn get_node_by_path(&self, from: NodeId, path: Vec<ActionType>) -> NodeId {
         if path.is_empty() {
             panic!("Can't return empty path")
         }
-        let mut node = None;
-        for action in path {
-            if node.is_none() {
-                node = Some(self.get_child(action));
-            } else {
-                node = Some(node.unwrap().read().unwrap().get_child(action).clone());
+        path.into_iter()
+            .fold(from, |node, action| self.get_child(node, action))
+    }
+
+    pub fn fully_explored(&self, id: NodeId) -> bool {
+        let node = &self[id];
+        if node.fully_explored.load(Ordering::Relaxed) || node.proven().is_some() {
+            return true;
+        }
+        // Checking for unexpanded children first saves walking down the tree
+        let fully_explored = self.children(id).all(|edge| edge.child().is_some())
+            && self
+                .children(id)
+                .all(|edge| edge.child().is_some_and(|child| self.fully_explored(child)));
+        if fully_explored {
+            node.fully_explored.store(true, Ordering::Relaxed);
+        }
+        fully_explored
+    }
+
+    ///
+    /// Copy the subtree under `root` into a new arena, leaving everything else
+    /// behind. Returns the new arena, and where each copied node went.
+    ///
+    pub fn copy_subtree(
+        &self,
+        root: NodeId,
+    ) -> (NodeArena<StateType, ActionType>, HashMap<NodeId, NodeId>) {
+        let copy = NodeArena::new();
+        let mut copied = HashMap::new();
+        self.copy_into(root, &copy, &mut copied);
+        (copy, copied)
+    }
+
+    fn copy_into(
+        &self,
+        id: NodeId,
+        into: &NodeArena<StateType, ActionType>,
+        copied: &mut HashMap<NodeId, NodeId>,
+    ) -> NodeId {
+        // Transpositions mean a node can be reached more than once
+        if let Some(copy) = copied.get(&id) {
+            return *copy;
+        }
+        let node = &self[id];
+        let copy = into.alloc(self.shallow_copy(id));
+        into[copy].fully_explored.store(
+            node.fully_explored.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        copied.insert(id, copy);
+        for (edge, copy_edge) in self.children(id).zip(into.children(copy)) {
+            if let Some(child) = edge.child() {
+                let child_copy = self.copy_into(child, into, copied);
+                let _ = copy_edge.child.set(child_copy);
+            }
+        }
+        copy
+    }
+
+    ///
+    /// Roughly how much memory the nodes in the tree take - the nodes
+    /// themselves, their edges, reward sums and estimates, and whatever their
+    /// states have allocated, as far as the game says (see
+    /// [`State::heap_bytes`]).
+    ///
+    pub fn used_bytes(&self) -> usize {
+        self.nodes.used_bytes()
+            + self.edges.used_bytes()
+            + self.rewards.used_bytes()
+            + self.estimates.used_bytes()
+            + self.state_bytes.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// Like [`NodeArena::used_bytes`], but including arena slots that have
+    /// been allocated and not filled yet.
+    ///
+    pub fn allocated_bytes(&self) -> usize {
+        self.nodes.allocated_bytes()
+            + self.edges.allocated_bytes()
+            + self.rewards.allocated_bytes()
+            + self.estimates.allocated_bytes()
+            + self.state_bytes.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// The most visited line of play from `id` - at each node, the expanded
+    /// child it's visited most, until there isn't one
+    ///
+    pub fn principal_variation(&self, id: NodeId) -> Vec<ActionType> {
+        let mut variation = vec![];
+        let mut seen = vec![id];
+        let mut node = id;
+        while let Some((edge, child)) = self
+            .children(node)
+            .filter_map(|edge| Some((edge, edge.child()?)))
+            .max_by_key(|(edge, _)| edge.visit_count())
+        {
+            // Can only happen with transpositions
+            if seen.contains(&child) {
+                break;
             }
+            variation.push(edge.action.clone());
+            seen.push(child);
+            node = child;
         }
-        node.unwrap()
+        variation
     }
 
-    pub fn log_children(&self, level: usize) {
+    ///
+    /// How many actions deep the tree under `id` goes
+    ///
+    pub fn max_depth(&self, id: NodeId) -> usize {
+        // The deepest each node's been reached at so far - with transpositions,
+        // a node can be reached by paths of different lengths
+        let mut depths: Vec<Option<usize>> = vec![None; self.len()];
+        let mut to_visit = vec![(id, 0)];
+        let mut max_depth = 0;
+        while let Some((node, depth)) = to_visit.pop() {
+            // The tree can grow while this runs, if it's still being searched
+            if node.0 as usize >= depths.len() {
+                depths.resize(node.0 as usize + 1, None);
+            }
+            let seen = &mut depths[node.0 as usize];
+            // No path without a repeat is as long as the tree has nodes, so
+            // that's a cycle through transpositions
+            if seen.is_some_and(|seen| seen >= depth) || depth >= self.len() {
+                continue;
+            }
+            *seen = Some(depth);
+            max_depth = max_depth.max(depth);
+            to_visit.extend(
+                self.children(node)
+                    .filter_map(|edge| edge.child())
+                    .map(|child| (child, depth + 1)),
+            );
+        }
+        max_depth
+    }
+
+    pub fn log_children(&self, id: NodeId, level: usize) {
         if level == 0 {
             log::info!("--- TREE ---");
         }
-        match self {
-            Node::Expanded { children, .. } => {
-                for (action, child) in children.iter() {
-                    let cloned_child = child.clone();
-                    let child_node = cloned_child.read().unwrap();
-                    match *child_node {
-                        Node::Expanded { .. } => {
-                            let action_name = format!("{:?}", action);
-                            log::info!("{} {}", "         |-".repeat(level), action_name);
-                            log::info!(
-                                "{} {:.6} {}",
-                                "         | ".repeat(level),
-                                child_node.value_sum(),
-                                child_node.visit_count()
-                            );
-                            log::info!(
-                                "{} {:.6}",
-                                "         | ".repeat(level),
-                                child_node.value_sum() / (child_node.visit_count() as f64)
-                            );
-                            child_node.log_children(level + 1);
-                        }
-                        Node::Placeholder { .. } => {
-                            let action_name = format!("({:?})", action);
-                            log::info!("{} {}", "         |-".repeat(level), action_name);
-                        }
-                    }
+        for edge in self.children(id) {
+            match edge.child() {
+                Some(child) => {
+                    let child_node = &self[child];
+                    let action_name = format!("{:?}", edge.action);
+                    log::info!("{} {}", "         |-".repeat(level), action_name);
+                    log::info!(
+                        "{} {:.6} {}",
+                        "         | ".repeat(level),
+                        child_node.value_sum(),
+                        child_node.visit_count()
+                    );
+                    log::info!(
+                        "{} {:.6} {:.3?}",
+                        "         | ".repeat(level),
+                        child_node.value_sum() / (child_node.visit_count() as f64),
+                        self.mean_rewards(child)
+                    );
+                    self.log_children(child, level + 1);
+                }
+                None => {
+                    let action_name = format!("({:?})", edge.action);
+                    log::info!("{} {}", "         |-".repeat(level), action_name);
                 }
             }
-            Node::Placeholder { .. } => return,
         }
     }
 }
 
+///
+/// Score the children of a node, best first. Children that are fully explored
+/// are left out.
+///
+/// `virtual_loss` is how many losing visits each iteration in flight through
+/// a child counts as, so that threads spread out (0 turns it off), each
+/// worth `loss_value`.
+///
+/// `priors` are used in place of the children's own (such as the root's,
+/// with noise mixed in), in the same order.
+///
 pub fn best_pick<StateType, ActionType>(
-    node_lock: &RwLock<Node<StateType, ActionType>>,
+    nodes: &NodeArena<StateType, ActionType>,
+    id: NodeId,
     constant: f64,
+    policy: &dyn SelectionPolicy,
+    virtual_loss: u32,
+    loss_value: f64,
+    priors: Option<&[f64]>,
 ) -> Vec<(ActionType, f64)>
 where
     StateType: State<ActionType = ActionType>,
     ActionType: Action<StateType = StateType>,
 {
-    let children: HashMap<ActionType, Arc<RwLock<Node<StateType, ActionType>>>> = {
-        let node = node_lock.read().unwrap();
-        match &*node {
-            Node::Expanded { children, .. } => children
-                .iter()
-                .map(|(action, child)| (action.clone(), child.clone()))
-                .collect(),
-            Node::Placeholder { .. } => {
-                return vec![];
+    let node = &nodes[id];
+    // Using a minimum of 1 here, because it's possible for another thread to
+    // have visited a child before the parent's count catches up - and ln(0)
+    // is NaN.
+    let parent_visit_count = std::cmp::max(node.effective_visit_count(virtual_loss), 1);
+    let game_action = node.game_action();
+    let sibling_count = nodes.children(id).len();
+    // Chance nodes are always picked by UCB1 on their weights, whatever the policy
+    let policy: &dyn SelectionPolicy = if game_action { &Ucb1 } else { policy };
+
+    let mut ucbs: Vec<(ActionType, f64)> = nodes
+        .children(id)
+        .enumerate()
+        .filter_map(|(i, edge)| {
+            let (visit_count, value_sum, value_sq_sum) = match edge.child() {
+                Some(child) => {
+                    if nodes.fully_explored(child) {
+                        log::trace!("Select short circuited - fully explored");
+                        return None;
+                    }
+                    // The edge's own visits, not the child's - a child shared
+                    // between transpositions has visits from its other parents
+                    let virtual_visits = nodes[child].virtual_visits(virtual_loss) as f64;
+                    (
+                        edge.visit_count() as f64 + virtual_visits,
+                        edge.value_sum() + virtual_visits * loss_value,
+                        edge.value_sq_sum() + virtual_visits * loss_value * loss_value,
+                    )
+                }
+                None => (0.0, 0.0, 0.0),
+            };
+            let stats = if game_action {
+                ChildStats {
+                    visit_count: visit_count / edge.weight() as f64,
+                    value_sum: 1.0,
+                    value_sq_sum: 1.0,
+                    prior: None,
+                }
+            } else {
+                ChildStats {
+                    visit_count,
+                    value_sum,
+                    value_sq_sum,
+                    prior: priors.map_or(edge.prior, |priors| priors.get(i).copied()),
+                }
+            };
+            let parent_visits = parent_visit_count as f64;
+            let score = policy.score(&stats, parent_visits, sibling_count, constant);
+            // Random used to break ties
+            let ucb: f64 = score + with_rng(|rng| rng.gen::<f64>()) * RANDOM_FACTOR;
+            trace!(
+                "UCB action: {:?}, value_sum: {}, visit_count: {}, parent_visits: {}, c: {} ucb: {}",
+                edge.action,
+                stats.value_sum,
+                stats.visit_count,
+                parent_visits,
+                constant,
+                ucb
+            );
+            Some((edge.action.clone(), ucb))
+        })
+        .collect();
+
+    ucbs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    trace!("UCBS action, ucb: {:?}", ucbs.iter().collect::<Vec<_>>());
+    ucbs
+}
+
+///
+/// Whether `player` got the most they can, the top of `reward_range` (see
+/// [`State::reward_range`]) - so once a player can force it, the rest of
+/// their options don't need exploring.
+///
+/// Beating every other player isn't always enough: with graded rewards, a
+/// player can come out ahead and still do better elsewhere. Without a
+/// range, nothing counts.
+///
+pub fn wins(reward: &[Reward], player: u8, reward_range: Option<(Reward, Reward)>) -> bool {
+    reward_range.is_some_and(|(_, highest)| reward[player as usize] >= highest)
+}
+
+/// Whether another player did better than `player`
+pub fn loses(reward: &[Reward], player: u8) -> bool {
+    let own = reward[player as usize];
+    reward.iter().any(|other| *other > own)
+}
+
+///
+/// Try to prove a node from its children (MCTS-Solver).
+///
+/// A player node is proven when one of its children is a proven win for the
+/// player (see [`wins`]), or when every child is proven - it's then worth the child that's
+/// best for the player. A chance node is proven when every child is proven,
+/// and is worth their weighted average.
+///
+/// Returns whether the node is proven.
+///
+pub fn update_proven<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    id: NodeId,
+) -> bool
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let node = &nodes[id];
+    if node.proven().is_some() {
+        return true;
+    }
+    let children = nodes.children(id);
+    if children.len() == 0 {
+        return false;
+    }
+    let mut proven_children: Vec<(Vec<Reward>, u32)> = Vec::with_capacity(children.len());
+    let mut all_proven = true;
+    for edge in children {
+        match edge.child().and_then(|child| nodes[child].proven()) {
+            Some(reward) => proven_children.push((reward.clone(), edge.weight())),
+            None => all_proven = false,
+        }
+    }
+
+    let proven = match node.state.next_actor() {
+        Actor::Player(player) => {
+            let best = proven_children
+                .into_iter()
+                .map(|(reward, _)| reward)
+                .max_by(|a, b| {
+                    a[player as usize]
+                        .partial_cmp(&b[player as usize])
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+            match best {
+                Some(reward) if all_proven || wins(&reward, player, node.state.reward_range()) => {
+                    Some(reward)
+                }
+                _ => None,
             }
         }
-    };
-    // Using a minimum of 1 here, because it's possible (can reproduce 1 in every few thousand iterations) that
-    // parent_visit_count is 0 but the value sum is non-zero meaning (I think) that another selector has clashed.
-    // This is faster than additional locks.
-    // The issue is that ln(0) == NaN. So - yeah.
-    let (game_action, parent_visit_count) = {
-        let node = node_lock.read().unwrap();
-        let parent_visit_count = std::cmp::max(node.visit_count(), 1);
-        (node.game_action(), parent_visit_count)
+        Actor::GameAction(_) if all_proven => {
+            let total_weight: u32 = proven_children.iter().map(|(_, weight)| weight).sum();
+            let mut expectation = vec![0.0; proven_children[0].0.len()];
+            for (reward, weight) in proven_children.iter() {
+                for (sum, value) in expectation.iter_mut().zip(reward.iter()) {
+                    *sum += value * *weight as f64 / total_weight as f64;
+                }
+            }
+            Some(expectation)
+        }
+        Actor::GameAction(_) => None,
+        // The outcomes kept are only a sample, so say nothing about the rest
+        Actor::SampledGameAction => None,
     };
 
-    let mut ucbs: Vec<(ActionType, f64)> = children
-                    .iter()
-                    .filter_map(|(action, child_node)| {
-                        let (visit_count, value_sum) = {
-                            let child_ref = child_node.clone();
-                            let child_node = child_ref.read().unwrap();
-                            if child_node.fully_explored() {
-                                log::trace!("Select short circuited - fully explored");
-                                return None;
-                            }
-                            let cached_ucb = child_node.cached_ucb(
-                                child_node.value_sum(), child_node.visit_count(), parent_visit_count);
-                            if let Some(ucb) = cached_ucb {
-                                return Some((action.clone(), ucb));
-                            }
-                            if game_action {
-                                (child_node.visit_count() as f64 / child_node.weight() as f64, 1.0)
-                            } else {
-                                (child_node.visit_count() as f64, child_node.value_sum())
-                            }
-                        };
-                        let parent_visits = parent_visit_count as f64;
-                        if visit_count == 0.0 {
-                            return Some((action.clone(), f64::INFINITY));
-                        }
-                        let q: f64 = value_sum / visit_count;
-                        let u: f64 = (parent_visits.ln() / visit_count).sqrt();
-                        // Random used to break ties
-                        // Todo: Cache the rng
-                        let r: f64 = rand::thread_rng().gen::<f64>() * RANDOM_FACTOR;
-                        let ucb: f64 = q + constant * u + r;
-                        trace!(
-                            "UCB action: {:?}, value_sum: {}, visit_count: {}, parent_visits: {}, q: {}, u: {}, c: {} ucb: {}",
-                            action,
-                            value_sum,
-                            visit_count,
-                            parent_visits,
-                            q,
-                            u,
-                            constant,
-                            ucb
-                        );
-                        Some((action.clone(), ucb))
-                    })
-                    .collect();
-
-    for (action, ucb) in ucbs.iter_mut() {
-        let node = children.get(action).unwrap();
-        let read_node = node.read().unwrap();
-        read_node.cache_ucb(
-            *ucb,
-            read_node.value_sum(),
-            read_node.visit_count(),
-            parent_visit_count,
-        );
+    match proven {
+        Some(reward) => {
+            trace!("Proven {:?}", reward);
+            node.set_proven(reward);
+            true
+        }
+        None => false,
     }
-    ucbs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-    trace!("UCBS action, ucb: {:?}", ucbs.iter().collect::<Vec<_>>());
-    ucbs
 }
 
 pub fn create_expanded_node<StateType>(
     state: StateType,
     weight: Option<u32>,
-) -> Node<StateType, StateType::ActionType>
+) -> NewNode<StateType, StateType::ActionType>
+where
+    StateType: State,
+{
+    create_expanded_node_with_priors(state, weight, None)
+}
+
+///
+/// Like [`create_expanded_node`], but a player node's children get their
+/// priors from `priors` - as does its estimate, if there is one.
+///
+pub fn create_expanded_node_with_priors<StateType>(
+    state: StateType,
+    weight: Option<u32>,
+    priors: Option<&dyn PriorProvider<StateType>>,
+) -> NewNode<StateType, StateType::ActionType>
 where
     StateType: State,
 {
@@ -376,45 +958,104 @@ where
     // (I think the Node::new_expanded should be able to work? But my rust brain
     // is still learning and couldn't figure out syntax that the type checker
     // was happy with)
-    let mut children: HashMap<
-        StateType::ActionType,
-        Arc<RwLock<Node<StateType, StateType::ActionType>>>,
-    > = HashMap::new();
+    let mut children: Vec<Edge<StateType::ActionType>> = vec![];
+    let mut estimate = None;
+    // Terminal states are proven straight away, and have nothing to explore
+    let proven = OnceLock::new();
+    if state.terminal() {
+        let _ = proven.set(state.reward());
+    }
     let game_action = match state.next_actor() {
+        _ if proven.get().is_some() => false,
         Actor::Player(_) => {
-            for action in state.permitted_actions() {
-                children.insert(
-                    action,
-                    Arc::new(RwLock::new(Node::Placeholder { weight: None })),
-                );
-            }
+            let actions = state.permitted_actions();
+            let prior = priors.map(|priors| priors.prior(&state, &actions));
+            children.extend(actions.into_iter().enumerate().map(|(i, action)| {
+                Edge {
+                    prior: prior
+                        .as_ref()
+                        .and_then(|prior| prior.policy.get(i).copied()),
+                    ..Edge::new(action, None)
+                }
+            }));
+            estimate = prior.and_then(|prior| prior.value);
             false
         }
         Actor::GameAction(actions) => {
-            for action in actions {
-                children.insert(
-                    action.0,
-                    Arc::new(RwLock::new(Node::Placeholder {
-                        weight: Some(action.1),
-                    })),
-                );
-            }
+            children.extend(
+                actions
+                    .iter()
+                    .map(|(action, weight)| Edge::new(action.clone(), Some(*weight))),
+            );
+            true
+        }
+        Actor::SampledGameAction => {
+            children.extend(
+                sample_outcomes(&state, SAMPLED_OUTCOMES)
+                    .into_iter()
+                    .map(|(action, weight)| Edge::new(action, Some(weight))),
+            );
             true
         }
     };
 
-    Node::Expanded {
-        state,
+    NewNode {
+        node: Node {
+            state,
+            children: Span::default(),
+            visit_count: AtomicU32::new(0),
+            value_sum: AtomicF64::new(0.0),
+            value_sq_sum: AtomicF64::new(0.0),
+            reward_sums: OnceLock::new(),
+            fully_explored: AtomicBool::new(false),
+            game_action,
+            weight,
+            proven,
+            in_flight: AtomicU32::new(0),
+            estimate: None,
+            action: PhantomData,
+        },
         children,
-        visit_count: 0,
-        value_sum: 0.0,
-        cached_ucb: RwLock::new(None),
-        cached_fully_explored: RwLock::new(None),
-        game_action,
-        weight,
+        estimate,
+        reward_sums: vec![],
     }
 }
 
+///
+/// Draw `count` outcomes from a state whose game actions are sampled, and
+/// return the distinct ones with weights. The weights are the probabilities
+/// the state reported (in millionths) if every draw had one, and otherwise
+/// how many times each outcome was drawn.
+///
+fn sample_outcomes<StateType: State>(
+    state: &StateType,
+    count: usize,
+) -> Vec<(StateType::ActionType, u32)> {
+    let mut outcomes: Vec<(StateType::ActionType, u32, Option<f64>)> = vec![];
+    let mut all_probabilities = true;
+    with_rng(|rng| {
+        for _ in 0..count {
+            let Some((action, probability)) = state.sample_game_action(rng) else {
+                continue;
+            };
+            all_probabilities &= probability.is_some();
+            match outcomes.iter_mut().find(|(drawn, _, _)| *drawn == action) {
+                Some((_, times, _)) => *times += 1,
+                None => outcomes.push((action, 1, probability)),
+            }
+        }
+    });
+    outcomes
+        .into_iter()
+        .map(|(action, times, probability)| match probability {
+            Some(probability) if all_probabilities => {
+                (action, ((probability * 1e6).round() as u32).max(1))
+            }
+            _ => (action, times),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -429,9 +1070,11 @@ mod tests {
             player_count: 1,
             next_actor: Actor::Player(0),
         };
-        let node = create_expanded_node(state, None);
-        assert_eq!(node.visit_count(), 0);
-        assert_eq!(node.value_sum(), 0.0);
+        let nodes = NodeArena::new();
+        let node = nodes.alloc(create_expanded_node(state, None));
+        assert_eq!(nodes[node].visit_count(), 0);
+        assert_eq!(nodes[node].value_sum(), 0.0);
+        assert_eq!(nodes.children(node).len(), 1);
     }
 
     #[test]
@@ -441,102 +1084,86 @@ mod tests {
         // Low effort test - create a node with weight 1 and weight 2, give them unexpanded children too,
         // check that the next pick is from the weight 2 node
 
-        let mut root_node = create_expanded_node(
+        let root_node = create_expanded_node(
             InjectableGameState {
                 injected_reward: vec![0.0f64],
                 injected_terminal: false,
                 injected_permitted_actions: vec![],
                 player_count: 1,
-                next_actor: Actor::GameAction(vec![
-                    (InjectableGameAction::WinInXTurns(1), 1),
-                    (InjectableGameAction::WinInXTurns(2), 2),
-                ]),
+                next_actor: Actor::GameAction(
+                    vec![
+                        (InjectableGameAction::WinInXTurns(1), 1),
+                        (InjectableGameAction::WinInXTurns(2), 2),
+                    ]
+                    .into(),
+                ),
             },
             None,
         );
 
-        let mut win_in_x_turns_1 = create_expanded_node(
+        let win_in_x_turns_1 = create_expanded_node(
             InjectableGameState {
                 injected_reward: vec![0.0f64],
                 injected_terminal: false,
-                injected_permitted_actions: vec![],
+                injected_permitted_actions: vec![
+                    InjectableGameAction::WinInXTurns(3),
+                    InjectableGameAction::WinInXTurns(4),
+                ],
                 player_count: 1,
                 next_actor: Actor::Player(0),
             },
             Some(1),
         );
 
-        let mut win_in_x_turns_2 = create_expanded_node(
+        let win_in_x_turns_2 = create_expanded_node(
             InjectableGameState {
                 injected_reward: vec![0.0f64],
                 injected_terminal: false,
-                injected_permitted_actions: vec![],
+                injected_permitted_actions: vec![
+                    InjectableGameAction::WinInXTurns(5),
+                    InjectableGameAction::WinInXTurns(6),
+                ],
                 player_count: 1,
                 next_actor: Actor::Player(0),
             },
             Some(2),
         );
 
-        root_node.visit(0.0f64);
-
-        let win_in_x_turns_1_child_3 = Node::Placeholder { weight: Some(3) };
-        let win_in_x_turns_1_child_4 = Node::Placeholder { weight: Some(4) };
-        let win_in_x_turns_2_child_5 = Node::Placeholder { weight: Some(5) };
-        let win_in_x_turns_2_child_6 = Node::Placeholder { weight: Some(6) };
-        win_in_x_turns_1.insert_child(
-            InjectableGameAction::WinInXTurns(3),
-            win_in_x_turns_1_child_3,
-        );
-        win_in_x_turns_1.insert_child(
-            InjectableGameAction::WinInXTurns(4),
-            win_in_x_turns_1_child_4,
-        );
-        win_in_x_turns_2.insert_child(
-            InjectableGameAction::WinInXTurns(5),
-            win_in_x_turns_2_child_5,
-        );
-        win_in_x_turns_2.insert_child(
-            InjectableGameAction::WinInXTurns(6),
-            win_in_x_turns_2_child_6,
-        );
-        root_node.insert_child(InjectableGameAction::WinInXTurns(1), win_in_x_turns_1);
-        root_node.insert_child(InjectableGameAction::WinInXTurns(2), win_in_x_turns_2);
-
-        let locked_node = RwLock::new(root_node);
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(root_node);
+        nodes.visit(root, 0.0f64, &[0.0]);
+        nodes.insert_child(root, InjectableGameAction::WinInXTurns(1), win_in_x_turns_1);
+        nodes.insert_child(root, InjectableGameAction::WinInXTurns(2), win_in_x_turns_2);
 
         // No visits, get the weight 2 node
         // TODO: do that. Currently, it visits the inf+ nodes in a random order.
         // {
-        //    let best_pick = best_pick(&locked_node, 2.0_f64.sqrt());
+        //    let best_pick = best_pick(&nodes, root, 2.0_f64.sqrt(), &Ucb1, 0, 0.0, None);
         //    assert_eq!(
         //        best_pick.first().unwrap().0,
         //        InjectableGameAction::WinInXTurns(2)
         //    );
         // }
 
-        {
-            let root_node_ref = locked_node.read().unwrap();
-            let child = root_node_ref.get_child(InjectableGameAction::WinInXTurns(2));
-            let mut child_write = child.write().unwrap();
-            child_write.visit(0.0f64);
-        }
+        nodes
+            .edge(root, &InjectableGameAction::WinInXTurns(2))
+            .unwrap()
+            .visit(0.0f64);
         // Weight 2 visited, weight 1 not, check that weight 1 is next
         {
-            let best_pick = best_pick(&locked_node, 2.0_f64.sqrt());
+            let best_pick = best_pick(&nodes, root, 2.0_f64.sqrt(), &Ucb1, 0, 0.0, None);
             assert_eq!(
                 best_pick.first().unwrap().0,
                 InjectableGameAction::WinInXTurns(1)
             );
         }
 
-        {
-            let root_node_ref = locked_node.read().unwrap();
-            let child = root_node_ref.get_child(InjectableGameAction::WinInXTurns(1));
-            let mut child_write = child.write().unwrap();
-            child_write.visit(0.0f64);
-        }
+        nodes
+            .edge(root, &InjectableGameAction::WinInXTurns(1))
+            .unwrap()
+            .visit(0.0f64);
 
-        let best_pick = best_pick(&locked_node, 2.0_f64.sqrt());
+        let best_pick = best_pick(&nodes, root, 2.0_f64.sqrt(), &Ucb1, 0, 0.0, None);
         // We're checking for 2 - because it's the first node from the root (and best-pick isn't
         // iterative down the tree, selection is)
         assert_eq!(
@@ -544,4 +1171,237 @@ mod tests {
             InjectableGameAction::WinInXTurns(2)
         );
     }
+
+    fn two_player_state(actor: Actor<InjectableGameAction>) -> InjectableGameState {
+        InjectableGameState {
+            injected_reward: vec![0.0, 0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![],
+            player_count: 2,
+            next_actor: actor,
+        }
+    }
+
+    fn terminal_node(
+        reward: Vec<f64>,
+        weight: Option<u32>,
+    ) -> NewNode<InjectableGameState, InjectableGameAction> {
+        create_expanded_node(
+            InjectableGameState {
+                injected_reward: reward,
+                injected_terminal: true,
+                ..two_player_state(Actor::Player(1))
+            },
+            weight,
+        )
+    }
+
+    #[test]
+    fn test_update_proven_player_node() {
+        let nodes = NodeArena::new();
+        let node = nodes.alloc(create_expanded_node(
+            InjectableGameState {
+                injected_permitted_actions: vec![
+                    InjectableGameAction::Lose,
+                    InjectableGameAction::Nothing,
+                    InjectableGameAction::Win,
+                ],
+                ..two_player_state(Actor::Player(0))
+            },
+            None,
+        ));
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Lose,
+            terminal_node(vec![-1.0, 1.0], None),
+        );
+        // A loss doesn't prove anything while there are other options
+        assert!(!update_proven(&nodes, node));
+        assert!(!nodes.fully_explored(node));
+
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Win,
+            terminal_node(vec![1.0, -1.0], None),
+        );
+        // But a win does
+        assert!(update_proven(&nodes, node));
+        assert_eq!(nodes[node].proven(), Some(&vec![1.0, -1.0]));
+        assert!(nodes.fully_explored(node));
+    }
+
+    #[test]
+    fn test_update_proven_needs_the_best_reward() {
+        let nodes = NodeArena::new();
+        let node = nodes.alloc(create_expanded_node(
+            InjectableGameState {
+                injected_permitted_actions: vec![
+                    InjectableGameAction::Nothing,
+                    InjectableGameAction::Win,
+                ],
+                ..two_player_state(Actor::Player(0))
+            },
+            None,
+        ));
+        // Ahead of the other player, but short of the most there is
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Nothing,
+            terminal_node(vec![0.5, -1.0], None),
+        );
+        assert!(!update_proven(&nodes, node));
+
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Win,
+            terminal_node(vec![1.0, -1.0], None),
+        );
+        assert!(update_proven(&nodes, node));
+        assert_eq!(nodes[node].proven(), Some(&vec![1.0, -1.0]));
+    }
+
+    #[test]
+    fn test_update_proven_chance_node() {
+        let nodes = NodeArena::new();
+        let node = nodes.alloc(create_expanded_node(
+            two_player_state(Actor::GameAction(
+                vec![
+                    (InjectableGameAction::Win, 3),
+                    (InjectableGameAction::Lose, 1),
+                ]
+                .into(),
+            )),
+            None,
+        ));
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Win,
+            terminal_node(vec![1.0, -1.0], Some(3)),
+        );
+        assert!(!update_proven(&nodes, node));
+
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Lose,
+            terminal_node(vec![-1.0, 1.0], Some(1)),
+        );
+        assert!(update_proven(&nodes, node));
+        assert_eq!(nodes[node].proven(), Some(&vec![0.5, -0.5]));
+    }
+
+    #[test]
+    fn test_sampled_chance_node() {
+        let nodes = NodeArena::new();
+        let node = nodes.alloc(create_expanded_node(
+            InjectableGameState {
+                injected_permitted_actions: vec![
+                    InjectableGameAction::Win,
+                    InjectableGameAction::Lose,
+                ],
+                ..two_player_state(Actor::SampledGameAction)
+            },
+            None,
+        ));
+        // Both outcomes are all but certain to be drawn, and are weighted by
+        // their probability
+        assert!(nodes[node].game_action());
+        let weights: Vec<u32> = nodes.children(node).map(Edge::weight).collect();
+        assert_eq!(weights, vec![500000, 500000]);
+
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Win,
+            terminal_node(vec![1.0, -1.0], Some(1)),
+        );
+        nodes.insert_child(
+            node,
+            InjectableGameAction::Lose,
+            terminal_node(vec![-1.0, 1.0], Some(1)),
+        );
+        // A sample can't prove anything
+        assert!(!update_proven(&nodes, node));
+    }
+
+    #[test]
+    fn test_best_pick_avoids_virtual_loss() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::WinInXTurns(2),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(create_expanded_node(state.clone(), None));
+        for action in [
+            InjectableGameAction::WinInXTurns(1),
+            InjectableGameAction::WinInXTurns(2),
+        ] {
+            let child = nodes.insert_child(
+                root,
+                action.clone(),
+                create_expanded_node(action.execute(&state), None),
+            );
+            nodes.visit(child, 1.0, &[1.0]);
+            nodes.visit(root, 1.0, &[1.0]);
+        }
+        nodes[nodes.get_child(root, InjectableGameAction::WinInXTurns(1))].add_in_flight();
+
+        // Without virtual loss, the iteration in flight makes no difference
+        let picks = best_pick(&nodes, root, 2.0_f64.sqrt(), &Ucb1, 0, -1.0, None);
+        assert_eq!(picks[0].1, picks[1].1);
+
+        let picks = best_pick(&nodes, root, 2.0_f64.sqrt(), &Ucb1, 1, -1.0, None);
+        assert_eq!(picks[0].0, InjectableGameAction::WinInXTurns(2));
+        assert!(picks[0].1 > picks[1].1);
+    }
+
+    #[test]
+    fn test_copy_subtree_keeps_statistics() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(2),
+                InjectableGameAction::WinInXTurns(3),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(create_expanded_node(state.clone(), None));
+        let kept_state = InjectableGameAction::WinInXTurns(2).execute(&state);
+        let kept = nodes.insert_child(
+            root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(kept_state.clone(), None),
+        );
+        nodes.insert_child(
+            root,
+            InjectableGameAction::WinInXTurns(3),
+            create_expanded_node(InjectableGameAction::WinInXTurns(3).execute(&state), None),
+        );
+        let grandchild = nodes.insert_child(
+            kept,
+            InjectableGameAction::WinInXTurns(1),
+            create_expanded_node(
+                InjectableGameAction::WinInXTurns(1).execute(&kept_state),
+                None,
+            ),
+        );
+        nodes.visit(kept, 0.5, &[0.5]);
+        nodes.visit(grandchild, 0.25, &[0.25]);
+
+        let (copy, copied) = nodes.copy_subtree(kept);
+        // The root and its other child are left behind
+        assert_eq!(copy.len(), 2);
+        assert_eq!(copy[copied[&kept]].value_sum(), 0.5);
+        let copied_grandchild = copy.get_child(copied[&kept], InjectableGameAction::WinInXTurns(1));
+        assert_eq!(copied_grandchild, copied[&grandchild]);
+        assert_eq!(copy[copied_grandchild].visit_count(), 1);
+        assert_eq!(copy.reward_sums(copied_grandchild), vec![0.25]);
+    }
 }
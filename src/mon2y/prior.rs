@@ -0,0 +1,174 @@
+use super::game::State;
+use super::network::{Network, NetworkPriors};
+use super::rng::with_rng;
+use super::selection::standard_normal;
+use super::Reward;
+use clap::ValueEnum;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+///
+/// What a [`PriorProvider`] thinks of a state
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prior {
+    /// The probability of each action being the one to take, in the same
+    /// order as the actions asked about
+    pub policy: Vec<f64>,
+    /// An estimate of every player's reward, on the same scale as
+    /// [`State::reward`] - used instead of playing out from the state
+    pub value: Option<Vec<Reward>>,
+}
+
+///
+/// Guesses how good a player's actions are before they're searched, for
+/// selection policies that use priors (see [`super::selection::Puct`]).
+///
+/// Providers are asked once for each player node as it's added to the tree.
+///
+pub trait PriorProvider<StateType: State>: Send + Sync {
+    fn prior(&self, state: &StateType, actions: &[StateType::ActionType]) -> Prior;
+}
+
+///
+/// Uses the game's own priors ([`State::action_priors`]), and a uniform one
+/// where the game doesn't have an opinion. Doesn't estimate values.
+///
+pub struct GamePriors;
+
+impl<StateType: State> PriorProvider<StateType> for GamePriors {
+    fn prior(&self, state: &StateType, actions: &[StateType::ActionType]) -> Prior {
+        let weights = state
+            .action_priors(actions)
+            .filter(|weights| weights.len() == actions.len())
+            .unwrap_or_else(|| vec![1.0; actions.len()]);
+        Prior {
+            policy: normalize(&weights),
+            value: None,
+        }
+    }
+}
+
+/// `weights` scaled to sum to 1 - or uniform, if they can't be
+pub fn normalize(weights: &[f64]) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    if total > 0.0 && total.is_finite() {
+        weights.iter().map(|weight| weight / total).collect()
+    } else {
+        vec![1.0 / weights.len() as f64; weights.len()]
+    }
+}
+
+///
+/// Mix Dirichlet(`alpha`) noise into `priors`, with the noise making up
+/// `weight` of the result - so a search tries moves its priors would
+/// otherwise rule out.
+///
+pub fn add_dirichlet_noise(priors: &[f64], alpha: f64, weight: f64) -> Vec<f64> {
+    let noise = normalize(&with_rng(|rng| {
+        priors
+            .iter()
+            .map(|_| sample_gamma(rng, alpha))
+            .collect::<Vec<_>>()
+    }));
+    priors
+        .iter()
+        .zip(noise)
+        .map(|(prior, noise)| (1.0 - weight) * prior + weight * noise)
+        .collect()
+}
+
+///
+/// Draw from Gamma(`shape`, 1) (Marsaglia and Tsang's method, boosted for
+/// shapes under 1)
+///
+fn sample_gamma(rng: &mut dyn RngCore, shape: f64) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        return sample_gamma(rng, shape + 1.0) * u.powf(1.0 / shape);
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = standard_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+/// The built in prior providers, for choosing from settings
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+pub enum Priors {
+    /// No priors - selection policies that need them treat every action alike
+    None,
+    Game,
+    /// A trained [`Network`], for its priors and values
+    Network,
+}
+
+impl Priors {
+    ///
+    /// Build the provider. `network` is the file [`Priors::Network`] loads
+    /// its network from - it panics without one, or if it can't be loaded.
+    ///
+    pub fn provider<StateType: State>(
+        &self,
+        network: Option<&Path>,
+    ) -> Option<Arc<dyn PriorProvider<StateType>>> {
+        match self {
+            Priors::None => None,
+            Priors::Game => Some(Arc::new(GamePriors)),
+            Priors::Network => {
+                let path = network.expect("Network priors need a network to load");
+                let network = Network::load(path).unwrap_or_else(|e| {
+                    panic!("Couldn't load network from {}: {}", path.display(), e)
+                });
+                Some(Arc::new(NetworkPriors::new(Arc::new(network))))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Priors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Priors::None => write!(f, "none"),
+            Priors::Game => write!(f, "game"),
+            Priors::Network => write!(f, "network"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::rng;
+
+    #[test]
+    fn test_dirichlet_noise_keeps_distribution() {
+        rng::seed_thread(3);
+        let priors = [0.7, 0.2, 0.1, 0.0];
+        let mut mean = [0.0; 4];
+        for _ in 0..2000 {
+            let noisy = add_dirichlet_noise(&priors, 0.3, 0.25);
+            assert!((noisy.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            for (sum, prior) in mean.iter_mut().zip(noisy) {
+                *sum += prior / 2000.0;
+            }
+        }
+        rng::unseed_thread();
+        // The noise averages out to uniform
+        for (mean, prior) in mean.iter().zip(priors) {
+            let expected = 0.75 * prior + 0.25 * 0.25;
+            assert!((mean - expected).abs() < 0.01, "{} vs {}", mean, expected);
+        }
+    }
+}
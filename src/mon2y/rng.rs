@@ -0,0 +1,64 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static SEEDED: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+///
+/// Seed this thread's random stream. Everything random done on the thread
+/// (playouts, selection tiebreaks, game shuffles, random players) draws from
+/// it until it's seeded again.
+///
+pub fn seed_thread(seed: u64) {
+    SEEDED.with(|rng| *rng.borrow_mut() = Some(StdRng::seed_from_u64(seed)));
+}
+
+/// Go back to drawing from [`rand::thread_rng`] on this thread, so tests
+/// sharing it don't carry a seed over
+#[cfg(test)]
+pub fn unseed_thread() {
+    SEEDED.with(|rng| *rng.borrow_mut() = None);
+}
+
+///
+/// Call `f` with this thread's random stream - the seeded one if there is
+/// one, otherwise [`rand::thread_rng`].
+///
+/// `f` mustn't call `with_rng` itself.
+///
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SEEDED.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut rand::thread_rng()),
+    })
+}
+
+///
+/// The seed for the `index`th stream derived from `seed` (a SplitMix64 step,
+/// so neighbouring indexes give unrelated streams)
+///
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_seeded_stream_repeats() {
+        seed_thread(42);
+        let first: Vec<u32> = (0..10).map(|_| with_rng(|rng| rng.gen())).collect();
+        seed_thread(42);
+        let second: Vec<u32> = (0..10).map(|_| with_rng(|rng| rng.gen())).collect();
+        unseed_thread();
+        assert_eq!(first, second);
+        assert_ne!(derive_seed(42, 0), derive_seed(42, 1));
+    }
+}
@@ -0,0 +1,218 @@
+use super::game::{Action, Actor, State};
+use super::rng::with_rng;
+use super::weighted_random::{sample_game_action, weighted_random};
+use super::Reward;
+use clap::ValueEnum;
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::Arc;
+
+///
+/// Picks the actions players take during a playout.
+///
+/// Game actions are always picked by their weights - this is only asked about
+/// player decisions.
+///
+pub trait RolloutPolicy<StateType: State>: Send + Sync {
+    fn choose_action(
+        &self,
+        state: &StateType,
+        actions: &[StateType::ActionType],
+    ) -> StateType::ActionType;
+}
+
+/// Picks uniformly at random from the permitted actions
+pub struct UniformRollout;
+
+impl<StateType: State> RolloutPolicy<StateType> for UniformRollout {
+    fn choose_action(
+        &self,
+        _state: &StateType,
+        actions: &[StateType::ActionType],
+    ) -> StateType::ActionType {
+        actions[with_rng(|rng| rng.gen_range(0..actions.len()))].clone()
+    }
+}
+
+///
+/// Picks the action with the highest [`State::playout_heuristic`], except for
+/// `epsilon` of the time when it picks uniformly at random.
+///
+/// Ties are broken randomly.
+///
+pub struct EpsilonGreedyRollout {
+    pub epsilon: f64,
+}
+
+impl<StateType: State> RolloutPolicy<StateType> for EpsilonGreedyRollout {
+    fn choose_action(
+        &self,
+        state: &StateType,
+        actions: &[StateType::ActionType],
+    ) -> StateType::ActionType {
+        if let Some(index) = with_rng(|rng| {
+            (rng.gen::<f64>() < self.epsilon).then(|| rng.gen_range(0..actions.len()))
+        }) {
+            return actions[index].clone();
+        }
+        let scores: Vec<f64> = actions
+            .iter()
+            .map(|action| state.playout_heuristic(action))
+            .collect();
+        let best_score = scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let best: Vec<&StateType::ActionType> = actions
+            .iter()
+            .zip(scores.iter())
+            .filter(|(_, score)| **score >= best_score)
+            .map(|(action, _)| action)
+            .collect();
+        best[with_rng(|rng| rng.gen_range(0..best.len()))].clone()
+    }
+}
+
+///
+/// Uses the game's own playout logic ([`State::heavy_playout_action`]), and
+/// picks uniformly at random where the game doesn't have an opinion.
+///
+pub struct HeavyRollout;
+
+impl<StateType: State> RolloutPolicy<StateType> for HeavyRollout {
+    fn choose_action(
+        &self,
+        state: &StateType,
+        actions: &[StateType::ActionType],
+    ) -> StateType::ActionType {
+        match state.heavy_playout_action(actions) {
+            Some(action) => action,
+            None => UniformRollout.choose_action(state, actions),
+        }
+    }
+}
+
+///
+/// Play `state` out to the end, with `policy` picking the players' actions,
+/// and return the rewards.
+///
+/// With `max_plies`, the playout stops after that many actions (counting the
+/// game's) and returns [`State::evaluate`] instead - or carries on to the end
+/// if the state can't be evaluated.
+///
+pub fn play_out<StateType: State>(
+    state: StateType,
+    policy: &dyn RolloutPolicy<StateType>,
+    max_plies: Option<usize>,
+) -> Vec<Reward> {
+    let mut cur_state = state;
+    let mut plies = 0;
+
+    while !cur_state.terminal() {
+        if Some(plies) == max_plies {
+            if let Some(reward) = cur_state.evaluate() {
+                log::trace!("Evaluated as {:?}", reward);
+                return reward;
+            }
+        }
+        match cur_state.next_actor() {
+            Actor::Player(_) => {
+                let permitted_actions = cur_state.permitted_actions();
+                let action = policy.choose_action(&cur_state, &permitted_actions);
+                cur_state = action.execute(&cur_state);
+            }
+            Actor::GameAction(actions) => {
+                let action = weighted_random(&actions);
+                cur_state = action.execute(&cur_state);
+            }
+            Actor::SampledGameAction => {
+                let Some(action) = sample_game_action(&cur_state) else {
+                    log::warn!("No game action could be sampled - stopping the playout");
+                    return cur_state.evaluate().unwrap_or_else(|| cur_state.reward());
+                };
+                cur_state = action.execute(&cur_state);
+            }
+        }
+        plies += 1;
+    }
+    log::trace!("Reward is {:?}", cur_state.reward());
+    cur_state.reward()
+}
+
+/// The built in rollout policies, for choosing from settings
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+pub enum Rollout {
+    Uniform,
+    EpsilonGreedy,
+    Heavy,
+}
+
+impl Rollout {
+    ///
+    /// Build the policy. `epsilon` is only used by [`Rollout::EpsilonGreedy`].
+    ///
+    pub fn policy<StateType: State>(&self, epsilon: f64) -> Arc<dyn RolloutPolicy<StateType>> {
+        match self {
+            Rollout::Uniform => Arc::new(UniformRollout),
+            Rollout::EpsilonGreedy => Arc::new(EpsilonGreedyRollout { epsilon }),
+            Rollout::Heavy => Arc::new(HeavyRollout),
+        }
+    }
+}
+
+impl std::fmt::Display for Rollout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Rollout::Uniform => write!(f, "uniform"),
+            Rollout::EpsilonGreedy => write!(f, "epsilon-greedy"),
+            Rollout::Heavy => write!(f, "heavy"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::game::Actor;
+    use crate::test::injectable_game::{InjectableGameAction, InjectableGameState};
+
+    #[test]
+    fn test_heavy_falls_back_to_uniform() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![InjectableGameAction::Win, InjectableGameAction::Lose],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let actions = state.permitted_actions();
+        let mut wins = 0;
+        for _ in 0..1000 {
+            if HeavyRollout.choose_action(&state, &actions) == InjectableGameAction::Win {
+                wins += 1;
+            }
+        }
+        assert!((400..600).contains(&wins), "Won {} of 1000", wins);
+    }
+
+    #[test]
+    fn test_cut_off_plays_on_without_evaluation() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![InjectableGameAction::WinInXTurns(3)],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        assert_eq!(play_out(state, &UniformRollout, Some(1)), vec![1.0]);
+    }
+
+    #[test]
+    fn test_stops_when_game_action_cant_be_sampled() {
+        let state = InjectableGameState {
+            injected_reward: vec![0.5],
+            injected_terminal: false,
+            injected_permitted_actions: vec![],
+            player_count: 1,
+            next_actor: Actor::SampledGameAction,
+        };
+        assert_eq!(play_out(state, &UniformRollout, None), vec![0.5]);
+    }
+}
@@ -0,0 +1,181 @@
+use super::Reward;
+use std::time::Duration;
+
+/// How many standard errors either side of the mean the bounds go (95%)
+const CONFIDENCE_Z: f64 = 1.96;
+
+///
+/// What a search found out about one of the root's actions, for the player
+/// choosing it
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionStats<ActionType> {
+    pub action: ActionType,
+    pub visits: u32,
+    /// Mean reward (0 if it was never visited)
+    pub mean: f64,
+    /// Mean less 1.96 standard errors, for a 95% interval (-infinity if unvisited)
+    pub lower_bound: f64,
+    /// Mean plus 1.96 standard errors (infinity if unvisited)
+    pub upper_bound: f64,
+    /// The reward for every player under perfect play, if it's known
+    pub proven: Option<Vec<Reward>>,
+    /// Every player's mean reward (empty if it was never visited)
+    pub rewards: Vec<Reward>,
+}
+
+impl<ActionType> ActionStats<ActionType> {
+    ///
+    /// `value_sum` and `value_sq_sum` are for the player choosing, and
+    /// `reward_sums` for every player
+    ///
+    pub fn new(
+        action: ActionType,
+        visits: u32,
+        value_sum: f64,
+        value_sq_sum: f64,
+        reward_sums: &[Reward],
+        proven: Option<Vec<Reward>>,
+    ) -> ActionStats<ActionType> {
+        if visits == 0 {
+            return ActionStats {
+                action,
+                visits,
+                mean: 0.0,
+                lower_bound: f64::NEG_INFINITY,
+                upper_bound: f64::INFINITY,
+                proven,
+                rewards: vec![],
+            };
+        }
+        let n = visits as f64;
+        let mean = value_sum / n;
+        // Rounding can take it just under 0
+        let variance = (value_sq_sum / n - mean * mean).max(0.0);
+        let margin = CONFIDENCE_Z * (variance / n).sqrt();
+        ActionStats {
+            action,
+            visits,
+            mean,
+            lower_bound: mean - margin,
+            upper_bound: mean + margin,
+            proven,
+            rewards: reward_sums.iter().map(|sum| sum / n).collect(),
+        }
+    }
+}
+
+///
+/// Everything a search found out, along with the action it chose
+///
+#[derive(Debug, Clone)]
+pub struct SearchResult<ActionType> {
+    pub action: ActionType,
+    /// Every action at the root, in the order the game lists them
+    pub actions: Vec<ActionStats<ActionType>>,
+    /// The most visited line of play from the root (which needn't start with
+    /// `action` - a proven win or [`super::BestTurnPolicy::Ucb0`] can pick
+    /// another)
+    pub principal_variation: Vec<ActionType>,
+    /// Iterations run by this search (not counting pondering, or earlier
+    /// turns of a kept tree)
+    pub iterations: usize,
+    pub elapsed: Duration,
+    /// Nodes in the tree (across every tree, for a root-parallel search)
+    pub node_count: usize,
+    /// Roughly how much memory those nodes take (see
+    /// [`super::node::NodeArena::used_bytes`]) - 0 for an information set
+    /// search, which doesn't keep count
+    pub used_bytes: usize,
+    /// How many actions deep the tree goes
+    pub max_depth: usize,
+    /// Whether there's nothing left in the tree to search
+    pub fully_explored: bool,
+    /// The root's reward for every player under perfect play, if it's known
+    pub proven: Option<Vec<Reward>>,
+    /// Every player's mean reward from the root, over the whole tree (empty
+    /// if nothing was searched)
+    pub rewards: Vec<Reward>,
+}
+
+impl<ActionType> SearchResult<ActionType> {
+    ///
+    /// The result when there's only one action, so nothing was searched
+    ///
+    pub fn forced(action: ActionType) -> SearchResult<ActionType> {
+        SearchResult {
+            action,
+            actions: vec![],
+            principal_variation: vec![],
+            iterations: 0,
+            elapsed: Duration::ZERO,
+            node_count: 0,
+            used_bytes: 0,
+            max_depth: 0,
+            fully_explored: false,
+            proven: None,
+            rewards: vec![],
+        }
+    }
+
+    /// The statistics of the chosen action, if it was searched
+    pub fn chosen_stats(&self) -> Option<&ActionStats<ActionType>>
+    where
+        ActionType: PartialEq,
+    {
+        self.actions
+            .iter()
+            .find(|stats| stats.action == self.action)
+    }
+}
+
+impl<ActionType: std::fmt::Debug + PartialEq> std::fmt::Display for SearchResult<ActionType> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} after {} iterations in {:.2?} ({} nodes, {} bytes, depth {})",
+            self.action,
+            self.iterations,
+            self.elapsed,
+            self.node_count,
+            self.used_bytes,
+            self.max_depth
+        )?;
+        if let Some(stats) = self.chosen_stats() {
+            write!(
+                f,
+                ", mean {:.3} [{:.3}, {:.3}] over {} visits",
+                stats.mean, stats.lower_bound, stats.upper_bound, stats.visits
+            )?;
+        }
+        if !self.rewards.is_empty() {
+            write!(f, ", rewards {:.3?}", self.rewards)?;
+        }
+        if let Some(proven) = &self.proven {
+            write!(f, ", proven {:?}", proven)?;
+        } else if self.fully_explored {
+            write!(f, ", fully explored")?;
+        }
+        write!(f, ", PV {:?}", self.principal_variation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_stats_bounds() {
+        let stats = ActionStats::new((), 4, 2.0, 4.0, &[2.0, -2.0], None);
+        assert_eq!(stats.mean, 0.5);
+        assert_eq!(stats.rewards, vec![0.5, -0.5]);
+        // Variance 0.75, so the standard error is sqrt(0.75 / 4)
+        let margin = CONFIDENCE_Z * (0.75_f64 / 4.0).sqrt();
+        assert!((stats.lower_bound - (0.5 - margin)).abs() < 1e-12);
+        assert!((stats.upper_bound - (0.5 + margin)).abs() < 1e-12);
+
+        let unvisited = ActionStats::new((), 0, 0.0, 0.0, &[], None);
+        assert_eq!(unvisited.lower_bound, f64::NEG_INFINITY);
+        assert_eq!(unvisited.upper_bound, f64::INFINITY);
+    }
+}
@@ -0,0 +1,221 @@
+use super::rng::with_rng;
+use clap::ValueEnum;
+use rand::Rng;
+use serde::Deserialize;
+use std::sync::Arc;
+
+///
+/// Statistics about a child, from the point of view of the player choosing it
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ChildStats {
+    pub visit_count: f64,
+    pub value_sum: f64,
+    /// Sum of the squares of the rewards
+    pub value_sq_sum: f64,
+    /// Prior probability of the child being picked, if known
+    pub prior: Option<f64>,
+}
+
+impl ChildStats {
+    pub fn mean(&self) -> f64 {
+        self.value_sum / self.visit_count
+    }
+
+    /// Sample variance of the rewards
+    pub fn variance(&self) -> f64 {
+        (self.value_sq_sum / self.visit_count - self.mean().powi(2)).max(0.0)
+    }
+}
+
+///
+/// Scores the children of a player node - the child with the highest score is
+/// explored next.
+///
+/// Chance nodes aren't scored by this - they're picked in proportion to their
+/// weights.
+///
+pub trait SelectionPolicy: Send + Sync {
+    ///
+    /// Score a child. `parent_visits` is at least 1, and `sibling_count`
+    /// includes the child itself.
+    ///
+    fn score(
+        &self,
+        child: &ChildStats,
+        parent_visits: f64,
+        sibling_count: usize,
+        constant: f64,
+    ) -> f64;
+}
+
+/// `q + c * sqrt(ln N / n)`
+pub struct Ucb1;
+
+impl SelectionPolicy for Ucb1 {
+    fn score(&self, child: &ChildStats, parent_visits: f64, _: usize, constant: f64) -> f64 {
+        if child.visit_count == 0.0 {
+            return f64::INFINITY;
+        }
+        let u = (parent_visits.ln() / child.visit_count).sqrt();
+        child.mean() + constant * u
+    }
+}
+
+///
+/// UCB1-Tuned - like UCB1, but explores children less when their rewards
+/// don't vary much.
+///
+/// `q + c * sqrt(ln N / n * min(1/4, V))`, where `V` is the variance plus
+/// `sqrt(2 ln N / n)`.
+///
+pub struct Ucb1Tuned;
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, child: &ChildStats, parent_visits: f64, _: usize, constant: f64) -> f64 {
+        if child.visit_count == 0.0 {
+            return f64::INFINITY;
+        }
+        let log_ratio = parent_visits.ln() / child.visit_count;
+        let v = child.variance() + (2.0 * log_ratio).sqrt();
+        child.mean() + constant * (log_ratio * v.min(0.25)).sqrt()
+    }
+}
+
+///
+/// PUCT - exploration is weighted by the child's prior.
+///
+/// `q + c * P * sqrt(N) / (1 + n)`. Children without a prior get a uniform
+/// one, and unvisited children are valued at 0.
+///
+pub struct Puct;
+
+impl SelectionPolicy for Puct {
+    fn score(
+        &self,
+        child: &ChildStats,
+        parent_visits: f64,
+        sibling_count: usize,
+        constant: f64,
+    ) -> f64 {
+        let prior = child.prior.unwrap_or(1.0 / sibling_count as f64);
+        let q = if child.visit_count == 0.0 {
+            0.0
+        } else {
+            child.mean()
+        };
+        q + constant * prior * parent_visits.sqrt() / (1.0 + child.visit_count)
+    }
+}
+
+///
+/// Thompson sampling - scores are drawn from a normal approximation of the
+/// posterior of the child's mean reward.
+///
+/// The constant scales the posterior's standard deviation.
+///
+pub struct Thompson;
+
+impl SelectionPolicy for Thompson {
+    fn score(&self, child: &ChildStats, _: f64, _: usize, constant: f64) -> f64 {
+        if child.visit_count == 0.0 {
+            return f64::INFINITY;
+        }
+        // Adding 1/n keeps rarely visited children (that happen to have had
+        // identical rewards so far) from being certain
+        let standard_error =
+            ((child.variance() + 1.0 / child.visit_count) / child.visit_count).sqrt();
+        child.mean() + constant * standard_error * with_rng(|rng| standard_normal(rng))
+    }
+}
+
+/// Box-Muller transform
+pub fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// The built in selection policies, for choosing from settings
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+pub enum TreePolicy {
+    Ucb1,
+    Ucb1Tuned,
+    Puct,
+    Thompson,
+}
+
+impl TreePolicy {
+    pub fn policy(&self) -> Arc<dyn SelectionPolicy> {
+        match self {
+            TreePolicy::Ucb1 => Arc::new(Ucb1),
+            TreePolicy::Ucb1Tuned => Arc::new(Ucb1Tuned),
+            TreePolicy::Puct => Arc::new(Puct),
+            TreePolicy::Thompson => Arc::new(Thompson),
+        }
+    }
+}
+
+impl std::fmt::Display for TreePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TreePolicy::Ucb1 => write!(f, "ucb1"),
+            TreePolicy::Ucb1Tuned => write!(f, "ucb1-tuned"),
+            TreePolicy::Puct => write!(f, "puct"),
+            TreePolicy::Thompson => write!(f, "thompson"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(visit_count: f64, rewards: &[f64]) -> ChildStats {
+        ChildStats {
+            visit_count,
+            value_sum: rewards.iter().sum(),
+            value_sq_sum: rewards.iter().map(|r| r * r).sum(),
+            prior: None,
+        }
+    }
+
+    #[test]
+    fn test_ucb1_tuned_explores_steady_children_less() {
+        let steady = stats(400.0, &[0.5; 400]);
+        let noisy = stats(400.0, &[[1.0, 0.0]; 200].concat());
+        let constant = 2.0_f64.sqrt();
+        assert_eq!(steady.mean(), noisy.mean());
+        assert!(
+            Ucb1Tuned.score(&steady, 1000.0, 2, constant)
+                < Ucb1Tuned.score(&noisy, 1000.0, 2, constant)
+        );
+        assert_eq!(
+            Ucb1.score(&steady, 1000.0, 2, constant),
+            Ucb1.score(&noisy, 1000.0, 2, constant)
+        );
+    }
+
+    #[test]
+    fn test_puct_follows_prior() {
+        let likely = ChildStats {
+            prior: Some(0.8),
+            ..stats(0.0, &[])
+        };
+        let unlikely = ChildStats {
+            prior: Some(0.2),
+            ..stats(0.0, &[])
+        };
+        assert!(Puct.score(&likely, 1.0, 2, 1.0) > Puct.score(&unlikely, 1.0, 2, 1.0));
+    }
+
+    #[test]
+    fn test_thompson_centres_on_mean() {
+        let child = stats(100.0, &[0.25; 100]);
+        let samples: Vec<f64> = (0..1000)
+            .map(|_| Thompson.score(&child, 200.0, 2, 1.0))
+            .collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!((mean - 0.25).abs() < 0.01, "Mean was {}", mean);
+    }
+}
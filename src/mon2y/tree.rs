@@ -1,10 +1,14 @@
 use super::game::{Action, Actor, State};
-use super::node::Node;
-use super::weighted_random::weighted_random;
-use super::Reward;
-use core::panic;
+use super::node::{
+    best_pick, create_expanded_node_with_priors, update_proven, Edge, NewNode, Node, NodeArena,
+    NodeId,
+};
+use super::prior::{add_dirichlet_noise, normalize, PriorProvider};
+use super::rollout::{play_out, RolloutPolicy, UniformRollout};
+use super::selection::{SelectionPolicy, Ucb1};
+use super::{Backup, Reward};
 use log::trace;
-use rand::Rng;
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 #[derive(Debug, PartialEq)]
@@ -13,9 +17,78 @@ pub enum Selection<ActionType: Action> {
     Selection(Vec<ActionType>),
 }
 
+///
+/// Nodes indexed by [`State::state_hash`] and how deep below the root they
+/// are, so that positions reached by different move orders share one node.
+/// Only valid for the [`NodeArena`] the nodes are in.
+///
+pub type TranspositionTable = RwLock<HashMap<(u64, usize), NodeId>>;
+
+///
+/// Limits on how large a tree can grow. Once one is reached, no more nodes
+/// are expanded, and selection only goes through children that already have
+/// been - iterations carry on, playing out from the nodes at the edge of the
+/// tree, so the statistics already there keep being refined.
+///
+/// Threads expanding at the same moment can each go a node over.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TreeBudget {
+    /// Most nodes the tree can hold
+    pub max_nodes: Option<usize>,
+    /// Most bytes the tree's nodes (and their states) can take (see
+    /// [`NodeArena::used_bytes`])
+    pub max_bytes: Option<usize>,
+}
+
+impl TreeBudget {
+    pub fn is_reached<StateType, ActionType>(
+        &self,
+        nodes: &NodeArena<StateType, ActionType>,
+    ) -> bool
+    where
+        StateType: State<ActionType = ActionType>,
+        ActionType: Action<StateType = StateType>,
+    {
+        self.max_nodes.is_some_and(|max| nodes.len() >= max)
+            || self.max_bytes.is_some_and(|max| nodes.used_bytes() >= max)
+    }
+
+    ///
+    /// An equal share of this budget, for each of `count` trees
+    ///
+    pub fn split(&self, count: usize) -> TreeBudget {
+        TreeBudget {
+            max_nodes: self.max_nodes.map(|max| max / count.max(1)),
+            max_bytes: self.max_bytes.map(|max| max / count.max(1)),
+        }
+    }
+}
+
 pub struct Tree<StateType: State, ActionType: Action<StateType = StateType>> {
-    pub root: Arc<RwLock<Node<StateType, ActionType>>>,
+    pub nodes: Arc<NodeArena<StateType, ActionType>>,
+    pub root: NodeId,
     pub constant: f64,
+    pub transpositions: Option<Arc<TranspositionTable>>,
+    pub rollout_policy: Arc<dyn RolloutPolicy<StateType>>,
+    /// Actions after which playouts are cut short and evaluated
+    pub playout_depth: Option<usize>,
+    pub selection_policy: Arc<dyn SelectionPolicy>,
+    /// Losing visits each iteration in flight adds to the nodes on its path
+    pub virtual_loss: u32,
+    /// What each of those visits is worth
+    pub virtual_loss_value: Reward,
+    pub budget: TreeBudget,
+    pub backup: Backup,
+    /// The player searching, for [`Tree::backup`]
+    pub perspective: u8,
+    /// Gives new player nodes' children their priors
+    pub prior_provider: Option<Arc<dyn PriorProvider<StateType>>>,
+    /// The root's children's priors, with noise mixed in
+    pub root_priors: Option<Vec<f64>>,
+    /// How much of a new leaf's reward is the prior provider's estimate,
+    /// rather than a playout
+    pub value_weight: f64,
 }
 
 impl<StateType: State<ActionType = ActionType>, ActionType: Action<StateType = StateType>>
@@ -24,58 +97,244 @@ where
     StateType: State<ActionType = ActionType>,
     ActionType: Action<StateType = StateType>,
 {
-    fn node_ref(root: Node<StateType, ActionType>) -> Arc<RwLock<Node<StateType, ActionType>>> {
-        // Only doing this to keep it a little tidier
-        Arc::new(RwLock::new(root))
+    pub fn new(root: NewNode<StateType, ActionType>) -> Tree<StateType, ActionType> {
+        Tree::new_with_constant(root, 2.0_f64.sqrt())
     }
 
-    pub fn new(root: Node<StateType, ActionType>) -> Tree<StateType, ActionType> {
-        Tree {
-            root: Tree::node_ref(root),
-            constant: 2.0_f64.sqrt(),
-        }
+    pub fn new_with_constant(
+        root: NewNode<StateType, ActionType>,
+        constant: f64,
+    ) -> Tree<StateType, ActionType> {
+        let nodes = NodeArena::new();
+        let root = nodes.alloc(root);
+        Tree::new_in(Arc::new(nodes), root, constant)
     }
 
-    pub fn new_with_constant(
-        root: Node<StateType, ActionType>,
+    ///
+    /// Create a tree around a node that's already in `nodes` (such as a
+    /// subtree kept from a previous search)
+    ///
+    pub fn new_in(
+        nodes: Arc<NodeArena<StateType, ActionType>>,
+        root: NodeId,
         constant: f64,
     ) -> Tree<StateType, ActionType> {
         Tree {
-            root: Tree::node_ref(root),
+            nodes,
+            root,
             constant,
+            transpositions: None,
+            rollout_policy: Arc::new(UniformRollout),
+            playout_depth: None,
+            selection_policy: Arc::new(Ucb1),
+            virtual_loss: 0,
+            virtual_loss_value: 0.0,
+            budget: TreeBudget::default(),
+            backup: Backup::MaxN,
+            perspective: 0,
+            prior_provider: None,
+            root_priors: None,
+            value_weight: 1.0,
         }
     }
 
     ///
-    /// Returns a path to the current selection
+    /// Share nodes between transpositions, using (and adding to) `table`.
     ///
-    pub fn selection(&self) -> Selection<ActionType> {
-        return Tree::select_from(self.root.clone(), self.constant);
+    /// Only children of player nodes are shared - a chance node's children
+    /// carry their own weights.
+    ///
+    /// A shared node's own statistics count its visits through every parent.
+    /// Each parent selects on the visits through its own edge to it (see
+    /// [`super::node::Edge`]), so it isn't put off a child that's been
+    /// searched through another parent.
+    ///
+    /// A node is only shared between paths of the same length from the root.
+    /// Every edge then leads one level deeper, so shared nodes can't form a
+    /// cycle, even in a game where a position can come round again - and
+    /// nothing has to search the tree to check.
+    ///
+    pub fn with_transpositions(
+        mut self,
+        table: Arc<TranspositionTable>,
+    ) -> Tree<StateType, ActionType> {
+        self.transpositions = Some(table);
+        self
     }
 
-    fn select_from(
-        node: Arc<RwLock<Node<StateType, ActionType>>>,
-        constant: f64,
-    ) -> Selection<ActionType> {
-        let best_pick: Vec<_> = super::node::best_pick(&node, constant)
-            .iter()
-            .map(|x| x.0.clone())
+    ///
+    /// Use `policy` to pick player actions in playouts
+    ///
+    pub fn with_rollout_policy(
+        mut self,
+        policy: Arc<dyn RolloutPolicy<StateType>>,
+    ) -> Tree<StateType, ActionType> {
+        self.rollout_policy = policy;
+        self
+    }
+
+    ///
+    /// Stop playouts after `depth` actions and use the state's evaluation
+    /// instead, if it has one (see [`State::evaluate`])
+    ///
+    pub fn with_playout_depth(mut self, depth: usize) -> Tree<StateType, ActionType> {
+        self.playout_depth = Some(depth);
+        self
+    }
+
+    ///
+    /// Use `policy` to pick which child of a player node to explore
+    ///
+    pub fn with_selection_policy(
+        mut self,
+        policy: Arc<dyn SelectionPolicy>,
+    ) -> Tree<StateType, ActionType> {
+        self.selection_policy = policy;
+        self
+    }
+
+    ///
+    /// Count each iteration that's in flight through a node as `virtual_loss`
+    /// losing visits worth `value` each, until its reward is propagated - so
+    /// threads searching the tree at the same time spread out rather than all
+    /// following the same path.
+    ///
+    /// `value` should be the worst a player can do - such as -1 in Connect 4,
+    /// but 0 in Can't Stop (see [`super::SearchConfig::virtual_loss_value_for`]).
+    ///
+    pub fn with_virtual_loss(
+        mut self,
+        virtual_loss: u32,
+        value: Reward,
+    ) -> Tree<StateType, ActionType> {
+        self.virtual_loss = virtual_loss;
+        self.virtual_loss_value = value;
+        self
+    }
+
+    ///
+    /// Stop expanding nodes once the tree reaches `budget`
+    ///
+    pub fn with_budget(mut self, budget: TreeBudget) -> Tree<StateType, ActionType> {
+        self.budget = budget;
+        self
+    }
+
+    ///
+    /// Value rewards with `backup`, with `perspective` as the player
+    /// searching (see [`Backup`])
+    ///
+    pub fn with_backup(mut self, backup: Backup, perspective: u8) -> Tree<StateType, ActionType> {
+        self.backup = backup;
+        self.perspective = perspective;
+        self
+    }
+
+    ///
+    /// Give the children of player nodes added from now on priors from
+    /// `provider`, and use its value estimates rather than playing out (or as
+    /// well as, see [`Tree::with_value_weight`])
+    ///
+    pub fn with_prior_provider(
+        mut self,
+        provider: Arc<dyn PriorProvider<StateType>>,
+    ) -> Tree<StateType, ActionType> {
+        self.prior_provider = Some(provider);
+        self
+    }
+
+    ///
+    /// Make `weight` (0 to 1) of a new leaf's reward the prior provider's
+    /// estimate, and the rest a playout - rather than only the estimate. An
+    /// estimate that's no better than a guess can still help, without
+    /// drowning out what playouts find.
+    ///
+    pub fn with_value_weight(mut self, weight: f64) -> Tree<StateType, ActionType> {
+        self.value_weight = weight;
+        self
+    }
+
+    ///
+    /// Mix Dirichlet(`alpha`) noise into the priors of the root's children,
+    /// making up `weight` of them, so the search tries moves the priors would
+    /// otherwise rule out (see [`add_dirichlet_noise`]). Children without
+    /// priors start from uniform ones.
+    ///
+    pub fn with_root_noise(mut self, alpha: f64, weight: f64) -> Tree<StateType, ActionType> {
+        let child_count = self.nodes.children(self.root).len();
+        if self.root_node().game_action() || child_count == 0 {
+            return self;
+        }
+        let priors: Option<Vec<f64>> = self
+            .nodes
+            .children(self.root)
+            .map(|edge| edge.prior)
             .collect();
+        let priors = priors.unwrap_or_else(|| normalize(&vec![1.0; child_count]));
+        self.root_priors = Some(add_dirichlet_noise(&priors, alpha, weight));
+        self
+    }
+
+    pub fn root_node(&self) -> &Node<StateType, ActionType> {
+        &self.nodes[self.root]
+    }
+
+    ///
+    /// Returns a path to the current selection.
+    ///
+    /// With virtual loss, each node on the path is marked as in flight as
+    /// it's picked, so other threads selecting at the same time see it. The
+    /// leaf is marked when it's expanded, and they're all unmarked when the
+    /// reward is propagated.
+    ///
+    pub fn selection(&self) -> Selection<ActionType> {
+        if self.root_node().proven().is_some() {
+            return Selection::FullyExplored;
+        }
+        self.add_in_flight(self.root);
+        let selection = self.select_from(self.root);
+        if selection == Selection::FullyExplored {
+            self.remove_in_flight(self.root);
+        }
+        selection
+    }
+
+    fn add_in_flight(&self, node: NodeId) {
+        if self.virtual_loss > 0 {
+            self.nodes[node].add_in_flight();
+        }
+    }
+
+    fn remove_in_flight(&self, node: NodeId) {
+        if self.virtual_loss > 0 {
+            self.nodes[node].remove_in_flight();
+        }
+    }
+
+    fn select_from(&self, node: NodeId) -> Selection<ActionType> {
+        let best_pick: Vec<_> = best_pick(
+            &self.nodes,
+            node,
+            self.constant,
+            self.selection_policy.as_ref(),
+            self.virtual_loss,
+            self.virtual_loss_value,
+            self.root_priors.as_deref().filter(|_| node == self.root),
+        )
+        .into_iter()
+        .map(|x| x.0)
+        .collect();
         if best_pick.is_empty() {
             return Selection::FullyExplored;
         }
+        // Once the tree's full, unexpanded children can't be expanded - and
+        // having no visits, they'd otherwise be picked every time
+        let full = self.budget.is_reached(&self.nodes);
+        let mut unexpandable = false;
         for action in best_pick.iter() {
-            let child = { node.read().unwrap().get_child(action.clone()) };
-            let is_expanded = {
-                let node = child.read().unwrap();
-                if let Node::Expanded { .. } = &*node {
-                    true
-                } else {
-                    false
-                }
-            };
-            if is_expanded {
-                let selection = Tree::select_from(child, constant);
+            if let Some(child) = self.nodes.child(node, action) {
+                self.add_in_flight(child);
+                let selection = self.select_from(child);
                 match selection {
                     // FullyExplored shouldn't normally happen here (because
                     // best_pick will handle it) - but with multithreading, it's
@@ -84,9 +343,18 @@ where
                     // It's annoying.
                     Selection::FullyExplored => {
                         trace!("FullyExplored hit in selection");
+                        self.remove_in_flight(child);
+                        // Every child is done with - which can prove the
+                        // node, if it was reached by another path
+                        update_proven(&self.nodes, child);
                         continue;
                     }
                     Selection::Selection(selection) => {
+                        // The child is the leaf - it's marked as in flight
+                        // when it's expanded, as leaves are
+                        if selection.is_empty() {
+                            self.remove_in_flight(child);
+                        }
                         // TBD if this would be faster with .insert or
                         // preallocation
                         let mut result_selection = vec![action.clone()];
@@ -94,115 +362,148 @@ where
                         return Selection::Selection(result_selection);
                     }
                 }
+            } else if full {
+                unexpandable = true;
             } else {
                 return Selection::Selection(vec![action.clone()]);
             }
         }
+        // Only unexpanded children are left, so it's played out from here
+        if unexpandable {
+            return Selection::Selection(vec![]);
+        }
         Selection::FullyExplored
     }
 
-    pub fn expansion(
-        &self,
-        selection: &Selection<ActionType>,
-    ) -> Vec<Arc<RwLock<Node<StateType, ActionType>>>> {
+    pub fn expansion(&self, selection: &Selection<ActionType>) -> Vec<NodeId> {
         trace!("Expansion: Selection: {:#?}", selection);
-        let mut cur_node = self.root.clone();
+        let mut cur_node = self.root;
         // This root is needed as part of the output to ensure that propagate can work
         // It was either here or selection. Could fit in either place.
         // Could also be in iterate, but that was going to result in more memory allocations.
-        let mut result: Vec<Arc<RwLock<Node<StateType, ActionType>>>> = vec![self.root.clone()];
+        let mut result: Vec<NodeId> = vec![self.root];
 
         if let Selection::Selection(selection) = selection {
-            for action in selection.iter() {
-                {
-                    let child_node = {
-                        let node = cur_node.read().unwrap();
-                        if let Node::Expanded { .. } = &*node {
-                            node.get_child(action.clone()).clone()
-                        } else {
-                            continue;
-                        }
-                    };
-
-                    {
-                        let cur_state = {
-                            let node = cur_node.read().unwrap();
-                            node.state().clone()
-                        };
-
-                        let expanded_child = {
-                            let read_node = child_node.read().unwrap();
-                            if let Node::Placeholder { .. } = &*read_node {
-                                Some(read_node.expansion(action.clone(), &cur_state))
-                            } else {
-                                None
-                            }
-                        };
-
-                        if let Some(expanded_child) = expanded_child {
-                            cur_node
-                                .write()
-                                .unwrap()
-                                .insert_child(action.clone(), expanded_child);
-                        }
-                    }
-
-                    result.push(cur_node);
-                    cur_node = child_node;
+            for (i, action) in selection.iter().enumerate() {
+                let Some(edge) = self.nodes.edge(cur_node, action) else {
+                    continue;
+                };
+                // Another thread may have expanded it since selection - if
+                // so, its child is used
+                let child_node = match edge.child() {
+                    Some(child) => child,
+                    // Out of room - play out from here instead
+                    None if self.budget.is_reached(&self.nodes) => break,
+                    None => self.expand_child(cur_node, edge, i + 1),
+                };
+                // Selection stopped at the leaf, so it's not in flight yet
+                if i == selection.len() - 1 {
+                    self.add_in_flight(child_node);
                 }
+                result.push(cur_node);
+                cur_node = child_node;
             }
+            // The newly expanded leaf (or where the budget stopped expansion),
+            // so it's visited and played out from
+            result.push(cur_node);
         }
         result
     }
 
-    pub fn play_out(&self, state: StateType) -> Vec<Reward> {
-        let mut rng = rand::thread_rng();
-
-        let mut cur_state = Box::new(state.clone());
-
-        while !cur_state.terminal() {
-            match cur_state.next_actor() {
-                Actor::Player(_) => {
-                    let permitted_actions = cur_state.permitted_actions();
-
-                    let action: ActionType =
-                        permitted_actions[rng.gen_range(0..permitted_actions.len())].clone();
-                    cur_state = Box::new(action.execute(&cur_state));
-                }
-                Actor::GameAction(actions) => {
-                    let action = weighted_random(actions);
-                    cur_state = Box::new(action.execute(&cur_state));
-                }
+    ///
+    /// The child of `parent` on `edge`, `depth` below the root - built, or
+    /// found in the transposition table if the position has been reached
+    /// another way. If another thread expanded the edge first, its child is
+    /// used.
+    ///
+    /// A node is only shared at the same depth, so the nodes can't form a
+    /// cycle, and if it isn't already one of `parent`'s children (see
+    /// [`Tree::with_transpositions`]).
+    ///
+    fn expand_child(&self, parent: NodeId, edge: &Edge<ActionType>, depth: usize) -> NodeId {
+        let parent_node = &self.nodes[parent];
+        let create = |state| {
+            self.nodes.alloc(create_expanded_node_with_priors(
+                state,
+                edge.weight,
+                self.prior_provider.as_deref(),
+            ))
+        };
+        let table = match &self.transpositions {
+            Some(table) if !parent_node.game_action() => table,
+            _ => {
+                return self
+                    .nodes
+                    .expand(edge, || create(edge.action.execute(parent_node.state())))
+            }
+        };
+        let state = edge.action.execute(parent_node.state());
+        let Some(hash) = state.state_hash() else {
+            return self.nodes.expand(edge, || create(state));
+        };
+        let key = (hash, depth);
+        let existing = table.read().unwrap().get(&key).copied();
+        match existing {
+            Some(existing) => {
+                // Held until the edge is set, so two of `parent`'s edges
+                // can't both be linked to the node at once
+                let _linking = table.write().unwrap();
+                self.nodes.expand(edge, || {
+                    if self
+                        .nodes
+                        .children(parent)
+                        .any(|other| other.child() == Some(existing))
+                    {
+                        return create(state);
+                    }
+                    trace!("Transposition hit for {:?}", edge.action);
+                    existing
+                })
+            }
+            None => {
+                let node = self.nodes.expand(edge, || create(state));
+                table.write().unwrap().entry(key).or_insert(node);
+                node
             }
         }
-        trace!("Reward is {:?}", cur_state.reward());
-        cur_state.reward()
     }
 
-    pub fn propagate_reward(
-        &self,
-        nodes: Vec<Arc<RwLock<Node<StateType, ActionType>>>>,
-        reward: Vec<Reward>,
-    ) {
-        let mut previous_node = nodes[0].clone();
-        for node in nodes[1..].iter() {
-            {
-                let actor = {
-                    let read_previous = previous_node.read().unwrap();
-                    if let Node::Expanded { .. } = &*read_previous {
-                        read_previous.state().next_actor()
-                    } else {
-                        panic!("Attempting to propagate to a placeholder node");
-                    }
-                };
+    pub fn play_out(&self, state: StateType) -> Vec<Reward> {
+        play_out(state, self.rollout_policy.as_ref(), self.playout_depth)
+    }
 
-                let mut cur_node = node.write().unwrap();
-                cur_node.visit(match actor {
-                    Actor::Player(player_id) => *reward.get(player_id as usize).unwrap_or(&0.0),
-                    _ => 0.0,
-                })
+    ///
+    /// Add `reward` to every node on the path. Each node is valued for the
+    /// player whose choice it was - for the outcomes of a chance node, that's
+    /// whoever chose to go to the chance node.
+    ///
+    /// The edge each node was reached by gets the visit too. A node shared
+    /// between transpositions gets the reward whichever path it came
+    /// through, but only the edge from that path's parent does (see
+    /// [`Tree::with_transpositions`]).
+    ///
+    pub fn propagate_reward(&self, nodes: Vec<NodeId>, reward: Vec<Reward>) {
+        let mut chooser = None;
+        let mut previous = nodes[0];
+        for id in nodes[1..].iter() {
+            if let Actor::Player(player) = self.nodes[previous].state().next_actor() {
+                chooser = Some(player);
+            }
+            self.remove_in_flight(*id);
+            let value = chooser.map_or(0.0, |player| {
+                self.backup.value(&reward, player, self.perspective)
+            });
+            self.nodes.visit(*id, value, &reward);
+            // A node is never the child of two of one parent's edges, so it
+            // picks out the edge (the root, first, has none)
+            if let Some(edge) = self
+                .nodes
+                .children(previous)
+                .find(|edge| edge.child() == Some(*id))
+            {
+                edge.visit(value);
             }
-            previous_node = node.clone();
+            previous = *id;
         }
     }
 
@@ -214,27 +515,44 @@ where
         };
         let expanded_nodes = self.expansion(&selection);
         if let Selection::Selection(..) = selection {
-            let reward = {
-                self.play_out(
-                    expanded_nodes
-                        .last()
-                        .unwrap()
-                        .read()
-                        .unwrap()
-                        .state()
-                        .clone(),
-                )
+            let leaf_id = *expanded_nodes.last().unwrap();
+            let leaf = &self.nodes[leaf_id];
+            let reward = match self.nodes.estimate(leaf_id) {
+                Some(estimate) if self.value_weight >= 1.0 => estimate,
+                Some(estimate) if self.value_weight > 0.0 => estimate
+                    .iter()
+                    .zip(self.play_out(leaf.state().clone()))
+                    .map(|(estimate, played)| {
+                        self.value_weight * estimate + (1.0 - self.value_weight) * played
+                    })
+                    .collect(),
+                _ => self.play_out(leaf.state().clone()),
             };
+            self.prove(&expanded_nodes);
             self.propagate_reward(expanded_nodes, reward);
         }
         selection
     }
+
+    ///
+    /// Back proven values up from the leaf of `nodes`, stopping at the first
+    /// node that can't be proven yet.
+    ///
+    fn prove(&self, nodes: &[NodeId]) {
+        for node in nodes.iter().rev() {
+            if !update_proven(&self.nodes, *node) {
+                break;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::mon2y::node::create_expanded_node;
+    use crate::mon2y::prior::Prior;
+    use crate::mon2y::selection::Puct;
     use crate::test::injectable_game::{InjectableGameAction, InjectableGameState};
     use std::vec;
 
@@ -255,18 +573,19 @@ mod tests {
         };
 
         let explored_state = InjectableGameAction::WinInXTurns(2).execute(&root_state);
-        let mut root = create_expanded_node(root_state, None);
-
-        let mut explored_node = create_expanded_node(explored_state, None);
-        explored_node.visit(0.0f64);
+        let tree = Tree::new(create_expanded_node(root_state, None));
 
-        root.insert_child(InjectableGameAction::WinInXTurns(2), explored_node);
-        root.insert_child(
-            InjectableGameAction::WinInXTurns(3),
-            Node::Placeholder { weight: None },
+        let explored_node = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(explored_state, None),
         );
-        root.visit(0.0f64);
-        let tree = Tree::new(root);
+        tree.nodes.visit(explored_node, 0.0f64, &[0.0]);
+        tree.nodes
+            .edge(tree.root, &InjectableGameAction::WinInXTurns(2))
+            .unwrap()
+            .visit(0.0f64);
+        tree.nodes.visit(tree.root, 0.0f64, &[0.0]);
 
         assert_eq!(
             tree.selection(),
@@ -293,25 +612,35 @@ mod tests {
         let mut explored_state_1 = InjectableGameAction::WinInXTurns(2).execute(&root_state);
         explored_state_1.injected_permitted_actions = vec![InjectableGameAction::WinInXTurns(1)];
         let explored_state_2 = InjectableGameAction::WinInXTurns(3).execute(&root_state);
-        let mut root = create_expanded_node(root_state, None);
+        let tree = Tree::new(create_expanded_node(root_state, None));
 
-        let mut explored_node_1 = create_expanded_node(explored_state_1, None);
-        explored_node_1.visit(0.0f64);
-        explored_node_1.insert_child(
-            InjectableGameAction::WinInXTurns(1),
-            Node::Placeholder { weight: None },
+        let explored_node_1 = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(explored_state_1, None),
         );
+        tree.nodes.visit(explored_node_1, 0.0f64, &[0.0]);
 
-        let mut explored_node_2 = create_expanded_node(explored_state_2, None);
-        explored_node_2.visit(-1.0f64);
-        explored_node_2.visit(0.0f64);
-
-        root.insert_child(InjectableGameAction::WinInXTurns(2), explored_node_1);
-        root.insert_child(InjectableGameAction::WinInXTurns(3), explored_node_2);
-        root.visit(0.0f64);
-        root.visit(0.0f64);
-        root.visit(0.0f64);
-        let tree = Tree::new(root);
+        let explored_node_2 = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(3),
+            create_expanded_node(explored_state_2, None),
+        );
+        tree.nodes.visit(explored_node_2, -1.0f64, &[-1.0]);
+        tree.nodes.visit(explored_node_2, 0.0f64, &[0.0]);
+        tree.nodes
+            .edge(tree.root, &InjectableGameAction::WinInXTurns(2))
+            .unwrap()
+            .visit(0.0f64);
+        let edge_2 = tree
+            .nodes
+            .edge(tree.root, &InjectableGameAction::WinInXTurns(3))
+            .unwrap();
+        edge_2.visit(-1.0f64);
+        edge_2.visit(0.0f64);
+        tree.nodes.visit(tree.root, 0.0f64, &[0.0]);
+        tree.nodes.visit(tree.root, 0.0f64, &[0.0]);
+        tree.nodes.visit(tree.root, 0.0f64, &[0.0]);
 
         assert_eq!(
             tree.selection(),
@@ -339,21 +668,22 @@ mod tests {
             vec![InjectableGameAction::NextTurnInjectActionCount(5)];
 
         let explored_state_2 = InjectableGameAction::WinInXTurns(3).execute(&root_state);
-        let mut root = create_expanded_node(root_state, None);
+        let tree = Tree::new(create_expanded_node(root_state, None));
 
-        let mut explored_node_1 = create_expanded_node(explored_state_1, None);
-        explored_node_1.visit(0.0f64);
-        explored_node_1.insert_child(
-            InjectableGameAction::NextTurnInjectActionCount(5),
-            Node::Placeholder { weight: None },
+        let explored_node_1 = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(explored_state_1, None),
         );
+        tree.nodes.visit(explored_node_1, 0.0f64, &[0.0]);
 
-        let mut explored_node_2 = create_expanded_node(explored_state_2, None);
-        explored_node_2.visit(-1.0f64);
-        explored_node_2.visit(0.0f64);
-
-        root.insert_child(InjectableGameAction::WinInXTurns(2), explored_node_1);
-        root.insert_child(InjectableGameAction::WinInXTurns(3), explored_node_2);
+        let explored_node_2 = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(3),
+            create_expanded_node(explored_state_2, None),
+        );
+        tree.nodes.visit(explored_node_2, -1.0f64, &[-1.0]);
+        tree.nodes.visit(explored_node_2, 0.0f64, &[0.0]);
 
         let selection_path = vec![
             InjectableGameAction::WinInXTurns(2),
@@ -361,16 +691,236 @@ mod tests {
         ];
         let selection = Selection::Selection(selection_path.clone());
 
-        let tree = Tree::new(root);
         tree.expansion(&selection);
-        let node_path = tree.root.clone();
-        let node_ref = node_path.read().unwrap().get_node_by_path(selection_path);
-        let node = node_ref.read().unwrap();
-        if let Node::Expanded { children, .. } = &*node {
-            assert_eq!(children.len(), 5);
-        } else {
-            self::panic!("Node is not expanded");
+        let node = tree.nodes.get_node_by_path(tree.root, selection_path);
+        assert_eq!(tree.nodes.children(node).len(), 5);
+    }
+
+    ///
+    /// Test that a position is only shared between paths of the same length,
+    /// so shared nodes can't make a cycle
+    ///
+    #[test]
+    fn test_transpositions_shared_at_same_depth() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::Nothing,
+                InjectableGameAction::WinInXTurns(0),
+                InjectableGameAction::WinInXTurns(1),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None))
+            .with_transpositions(Arc::new(RwLock::new(HashMap::new())));
+        let paths = [
+            vec![
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::WinInXTurns(0),
+            ],
+            vec![InjectableGameAction::WinInXTurns(0)],
+            vec![
+                InjectableGameAction::Nothing,
+                InjectableGameAction::WinInXTurns(0),
+            ],
+            // Back to where it started - the same position as the root's
+            // Nothing child, but deeper
+            vec![InjectableGameAction::Nothing, InjectableGameAction::Nothing],
+        ];
+        let nodes: Vec<NodeId> = paths
+            .iter()
+            .map(|path| {
+                tree.expansion(&Selection::Selection(path.clone()));
+                tree.nodes.get_node_by_path(tree.root, path.clone())
+            })
+            .collect();
+        // The first three all reach the state a turn from winning, but only
+        // the two the same depth down share it
+        assert_eq!(nodes[0], nodes[2]);
+        assert_ne!(nodes[0], nodes[1]);
+        let nothing = tree
+            .nodes
+            .get_node_by_path(tree.root, vec![InjectableGameAction::Nothing]);
+        assert_ne!(nodes[3], nothing);
+        assert_ne!(nodes[3], tree.root);
+    }
+
+    ///
+    /// Test that a node shared between two parents counts every visit, and
+    /// each parent's edge to it only counts that parent's
+    ///
+    #[test]
+    fn test_shared_node_visits_counted_per_edge() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::Nothing,
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::WinInXTurns(2),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None))
+            .with_transpositions(Arc::new(RwLock::new(HashMap::new())));
+        for _ in 0..40 {
+            tree.iterate();
         }
+        // Both two actions down from the root, a turn from winning
+        let edge_below = |action| {
+            let parent = tree.nodes.get_node_by_path(tree.root, vec![action]);
+            tree.nodes
+                .edge(parent, &InjectableGameAction::WinInXTurns(1))
+                .unwrap()
+        };
+        let direct = edge_below(InjectableGameAction::WinInXTurns(2));
+        let indirect = edge_below(InjectableGameAction::Nothing);
+        let shared = direct.child().unwrap();
+        assert_eq!(indirect.child(), Some(shared));
+        assert!(direct.visit_count() > 0 && indirect.visit_count() > 0);
+        assert_eq!(
+            direct.visit_count() + indirect.visit_count(),
+            tree.nodes[shared].visit_count()
+        );
+    }
+
+    /// Always plays the last of the permitted actions
+    struct LastActionRollout;
+
+    impl RolloutPolicy<InjectableGameState> for LastActionRollout {
+        fn choose_action(
+            &self,
+            _state: &InjectableGameState,
+            actions: &[InjectableGameAction],
+        ) -> InjectableGameAction {
+            actions.last().unwrap().clone()
+        }
+    }
+
+    ///
+    /// Test that an iteration visits the leaf it expands, and plays out from
+    /// it rather than from its parent
+    ///
+    #[test]
+    fn test_iteration_plays_out_from_new_leaf() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(0),
+                InjectableGameAction::Lose,
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None))
+            .with_rollout_policy(Arc::new(LastActionRollout));
+        assert_eq!(
+            tree.iterate(),
+            Selection::Selection(vec![InjectableGameAction::WinInXTurns(0)])
+        );
+        // Played out from the root, the last action would have lost
+        let leaf = tree
+            .nodes
+            .get_child(tree.root, InjectableGameAction::WinInXTurns(0));
+        assert_eq!(
+            (tree.nodes[leaf].visit_count(), tree.nodes[leaf].value_sum()),
+            (1, 1.0)
+        );
+        assert_eq!(tree.root_node().visit_count(), 1);
+
+        // The path to back up ends with the new leaf
+        let path = tree.expansion(&Selection::Selection(vec![
+            InjectableGameAction::WinInXTurns(0),
+            InjectableGameAction::Win,
+        ]));
+        let new_leaf = tree.nodes.get_child(leaf, InjectableGameAction::Win);
+        assert_eq!(path, vec![tree.root, tree.root, leaf, new_leaf]);
+    }
+
+    ///
+    /// Test that iterations carry on visiting the tree, without growing it,
+    /// once the node budget is reached
+    ///
+    #[test]
+    fn test_expansion_stops_at_budget() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(2),
+                InjectableGameAction::WinInXTurns(3),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None)).with_budget(TreeBudget {
+            max_nodes: Some(3),
+            max_bytes: None,
+        });
+
+        for _ in 0..10 {
+            tree.iterate();
+        }
+        assert_eq!(tree.nodes.len(), 3);
+        assert_eq!(tree.root_node().visit_count(), 10);
+        let selection = Selection::Selection(vec![
+            InjectableGameAction::WinInXTurns(2),
+            InjectableGameAction::WinInXTurns(1),
+        ]);
+        let expanded = tree.expansion(&selection);
+        assert_eq!(tree.nodes.len(), 3);
+        let child = tree
+            .nodes
+            .get_node_by_path(tree.root, vec![InjectableGameAction::WinInXTurns(2)]);
+        assert_eq!(expanded.last(), Some(&child));
+    }
+
+    ///
+    /// Test that once the budget is reached, a node's unexpanded children
+    /// aren't selected over its expanded ones
+    ///
+    #[test]
+    fn test_budget_selects_expanded_children() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::NextTurnInjectActionCount(3),
+                InjectableGameAction::NextTurnInjectActionCount(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None))
+            .with_budget(TreeBudget {
+                max_nodes: Some(2),
+                max_bytes: None,
+            })
+            .with_virtual_loss(1, -1.0);
+
+        for _ in 0..10 {
+            tree.iterate();
+        }
+        assert_eq!(tree.nodes.len(), 2);
+        let root = tree.root_node();
+        let (expanded, unexpanded): (Vec<_>, Vec<_>) = tree
+            .nodes
+            .children(tree.root)
+            .partition(|edge| edge.child().is_some());
+        assert_eq!((expanded.len(), unexpanded.len()), (1, 1));
+        // Every iteration after the first went through the expanded child,
+        // and played out from it
+        assert_eq!(expanded[0].visit_count(), 10);
+        let child = &tree.nodes[expanded[0].child().unwrap()];
+        assert_eq!(child.visit_count(), 10);
+        assert_eq!(root.visit_count(), 10);
+        // Nothing's left in flight
+        assert_eq!(child.effective_visit_count(1), child.visit_count());
+        assert_eq!(root.effective_visit_count(1), root.visit_count());
     }
 
     #[test]
@@ -405,57 +955,32 @@ mod tests {
         };
 
         let explored_state = InjectableGameAction::WinInXTurns(2).execute(&root_state);
-        let mut root = create_expanded_node(root_state, None);
-
-        let mut explored_node = create_expanded_node(explored_state, None);
+        let child_state = InjectableGameAction::WinInXTurns(1).execute(&explored_state);
+        let grandchild_state = InjectableGameAction::WinInXTurns(0).execute(&child_state);
+        let tree = Tree::new(create_expanded_node(root_state, None));
 
-        let mut child_node = create_expanded_node(
-            InjectableGameAction::WinInXTurns(1).execute(&explored_node.state()),
-            None,
+        let explored_node = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(explored_state, None),
+        );
+        let child_node = tree.nodes.insert_child(
+            explored_node,
+            InjectableGameAction::WinInXTurns(1),
+            create_expanded_node(child_state, None),
+        );
+        let grandchild_node = tree.nodes.insert_child(
+            child_node,
+            InjectableGameAction::WinInXTurns(0),
+            create_expanded_node(grandchild_state, None),
         );
-
-        let grandchild_state = InjectableGameAction::Win.execute(&child_node.state());
-        let grandchild_node = create_expanded_node(grandchild_state, None);
-
-        child_node.insert_child(InjectableGameAction::Win, grandchild_node);
-        explored_node.insert_child(InjectableGameAction::WinInXTurns(1), child_node);
-        root.insert_child(InjectableGameAction::WinInXTurns(2), explored_node);
-        let tree = Tree::new(root);
 
         let path = vec![
             InjectableGameAction::WinInXTurns(2),
             InjectableGameAction::WinInXTurns(1),
-            InjectableGameAction::Win,
-        ];
-        let owned_root = tree.root.clone();
-        // Todo: Think about ways to tidy this.
-        let nodes = vec![
-            tree.root.clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(1))
-                .clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(1))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::Win)
-                .clone(),
+            InjectableGameAction::WinInXTurns(0),
         ];
+        let nodes = vec![tree.root, explored_node, child_node, grandchild_node];
 
         let check_path = path.clone();
         const REWARD: f64 = 0.8;
@@ -463,8 +988,7 @@ mod tests {
 
         for path_i in 1..=check_path.len() {
             let semi_path = check_path[0..path_i].to_vec();
-            let node_ref = tree.root.read().unwrap().get_node_by_path(semi_path);
-            let node = node_ref.read().unwrap();
+            let node = &tree.nodes[tree.nodes.get_node_by_path(tree.root, semi_path)];
             assert_eq!(node.value_sum(), REWARD);
             assert_eq!(node.visit_count(), 1);
         }
@@ -484,57 +1008,32 @@ mod tests {
         };
 
         let explored_state = InjectableGameAction::WinInXTurns(2).execute(&root_state);
-        let mut root = create_expanded_node(root_state, None);
-
-        let mut explored_node = create_expanded_node(explored_state, None);
+        let child_state = InjectableGameAction::WinInXTurns(1).execute(&explored_state);
+        let grandchild_state = InjectableGameAction::WinInXTurns(0).execute(&child_state);
+        let tree = Tree::new(create_expanded_node(root_state, None));
 
-        let mut child_node = create_expanded_node(
-            InjectableGameAction::WinInXTurns(1).execute(&explored_node.state()),
-            None,
+        let explored_node = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::WinInXTurns(2),
+            create_expanded_node(explored_state, None),
+        );
+        let child_node = tree.nodes.insert_child(
+            explored_node,
+            InjectableGameAction::WinInXTurns(1),
+            create_expanded_node(child_state, None),
+        );
+        let grandchild_node = tree.nodes.insert_child(
+            child_node,
+            InjectableGameAction::WinInXTurns(0),
+            create_expanded_node(grandchild_state, None),
         );
-
-        let grandchild_state = InjectableGameAction::Win.execute(&child_node.state());
-        let grandchild_node = create_expanded_node(grandchild_state, None);
-
-        child_node.insert_child(InjectableGameAction::Win, grandchild_node);
-        explored_node.insert_child(InjectableGameAction::WinInXTurns(1), child_node);
-        root.insert_child(InjectableGameAction::WinInXTurns(2), explored_node);
-        let tree = Tree::new(root);
 
         let path = vec![
             InjectableGameAction::WinInXTurns(2),
             InjectableGameAction::WinInXTurns(1),
-            InjectableGameAction::Win,
-        ];
-        let owned_root = tree.root.clone();
-        // Not super pleased with this here either
-        let nodes = vec![
-            tree.root.clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(1))
-                .clone(),
-            owned_root
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(2))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::WinInXTurns(1))
-                .read()
-                .unwrap()
-                .get_child(InjectableGameAction::Win)
-                .clone(),
+            InjectableGameAction::WinInXTurns(0),
         ];
+        let nodes = vec![tree.root, explored_node, child_node, grandchild_node];
 
         let check_path = path.clone();
         // Using slightly unusual rewards to just make more certain that it was actually this reward
@@ -547,8 +1046,7 @@ mod tests {
             // table.
             let semi_path = check_path[0..path_i].to_vec();
             let player_id = (path_i + 1) % 2;
-            let node_ref = tree.root.read().unwrap().get_node_by_path(semi_path);
-            let node = node_ref.read().unwrap();
+            let node = &tree.nodes[tree.nodes.get_node_by_path(tree.root, semi_path)];
             if player_id == 0 {
                 assert_eq!(node.value_sum(), REWARD);
                 assert_eq!(node.visit_count(), 1);
@@ -559,6 +1057,79 @@ mod tests {
         }
     }
 
+    ///
+    /// Test that the outcomes of a chance node are valued for the player who
+    /// chose it, and that every node keeps every player's rewards
+    ///
+    #[test]
+    fn test_propagate_chance_outcomes() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0, 0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![InjectableGameAction::Nothing],
+            player_count: 2,
+            next_actor: Actor::Player(0),
+        };
+        let chance_state = InjectableGameState {
+            next_actor: Actor::GameAction(vec![(InjectableGameAction::Nothing, 1)].into()),
+            ..root_state.clone()
+        };
+        let outcome_state = InjectableGameAction::Nothing.execute(&chance_state);
+        let tree = Tree::new(create_expanded_node(root_state, None));
+        let chance = tree.nodes.insert_child(
+            tree.root,
+            InjectableGameAction::Nothing,
+            create_expanded_node(chance_state, None),
+        );
+        let outcome = tree.nodes.insert_child(
+            chance,
+            InjectableGameAction::Nothing,
+            create_expanded_node(outcome_state, Some(1)),
+        );
+
+        tree.propagate_reward(vec![tree.root, tree.root, chance, outcome], vec![0.8, -0.6]);
+        for node in [tree.root, chance, outcome] {
+            assert_eq!(tree.nodes[node].value_sum(), 0.8);
+            assert_eq!(tree.nodes.mean_rewards(node), vec![0.8, -0.6]);
+        }
+    }
+
+    #[test]
+    fn test_propagate_backups() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0, 0.0, 0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![InjectableGameAction::Nothing],
+            player_count: 3,
+            next_actor: Actor::Player(0),
+        };
+        let reward = vec![0.2, 0.5, -0.7];
+        // The value of the node player 2 chose, for each backup
+        for (backup, expected) in [
+            (Backup::MaxN, -0.7),
+            (Backup::Paranoid, -0.2),
+            (Backup::BestReply, 0.5),
+        ] {
+            let tree =
+                Tree::new(create_expanded_node(root_state.clone(), None)).with_backup(backup, 0);
+            let mut path = vec![tree.root, tree.root];
+            let mut state = root_state.clone();
+            for _ in 0..3 {
+                state = InjectableGameAction::Nothing.execute(&state);
+                path.push(tree.nodes.insert_child(
+                    *path.last().unwrap(),
+                    InjectableGameAction::Nothing,
+                    create_expanded_node(state.clone(), None),
+                ));
+            }
+            tree.propagate_reward(path.clone(), reward.clone());
+            // Player 0's own choice is valued the same by all of them
+            assert_eq!(tree.nodes[path[2]].value_sum(), 0.2);
+            assert_eq!(tree.nodes[path[4]].value_sum(), expected, "{}", backup);
+            assert_eq!(tree.nodes.reward_sums(path[4]), reward);
+        }
+    }
+
     #[test]
     fn test_weighted_game_action_play_out() {
         let root_state = InjectableGameState {
@@ -566,10 +1137,13 @@ mod tests {
             injected_terminal: false,
             injected_permitted_actions: vec![],
             player_count: 1,
-            next_actor: Actor::GameAction(vec![
-                (InjectableGameAction::Lose, 1),
-                (InjectableGameAction::Win, 2),
-            ]),
+            next_actor: Actor::GameAction(
+                vec![
+                    (InjectableGameAction::Lose, 1),
+                    (InjectableGameAction::Win, 2),
+                ]
+                .into(),
+            ),
         };
 
         let root = create_expanded_node(root_state.clone(), None);
@@ -596,4 +1170,166 @@ mod tests {
             tolerance
         );
     }
+
+    #[test]
+    fn test_virtual_loss_reverted() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(2),
+                InjectableGameAction::WinInXTurns(3),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None)).with_virtual_loss(3, -1.0);
+        for _ in 0..20 {
+            tree.iterate();
+        }
+        let root = tree.root_node();
+        assert_eq!(root.effective_visit_count(3), root.visit_count());
+        for action in [
+            InjectableGameAction::WinInXTurns(2),
+            InjectableGameAction::WinInXTurns(3),
+        ] {
+            let child = &tree.nodes[tree.nodes.get_child(tree.root, action)];
+            assert_eq!(child.effective_visit_count(3), child.visit_count());
+            assert_eq!(child.effective_value_sum(3, -1.0), child.value_sum());
+        }
+    }
+
+    ///
+    /// Test that a path being selected is in flight before it's expanded, so
+    /// selections made at the same time go different ways
+    ///
+    #[test]
+    fn test_virtual_loss_seen_by_concurrent_selection() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(2),
+                InjectableGameAction::WinInXTurns(3),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree =
+            Tree::new(create_expanded_node(root_state.clone(), None)).with_virtual_loss(1, -1.0);
+        for action in [
+            InjectableGameAction::WinInXTurns(2),
+            InjectableGameAction::WinInXTurns(3),
+        ] {
+            let child = tree.nodes.insert_child(
+                tree.root,
+                action.clone(),
+                create_expanded_node(action.execute(&root_state), None),
+            );
+            tree.nodes.visit(child, 0.0, &[0.0]);
+            tree.nodes.visit(tree.root, 0.0, &[0.0]);
+        }
+
+        let (Selection::Selection(first), Selection::Selection(second)) =
+            (tree.selection(), tree.selection())
+        else {
+            panic!("Nothing selected");
+        };
+        assert_ne!(first[0], second[0]);
+
+        for selection in [first.clone(), second.clone()] {
+            let path = tree.expansion(&Selection::Selection(selection));
+            tree.propagate_reward(path, vec![0.0]);
+        }
+        // Every node on the way is out of flight again
+        let root = tree.root_node();
+        assert_eq!(root.effective_visit_count(1), root.visit_count());
+        for selection in [first, second] {
+            for depth in 1..=selection.len() {
+                let node = &tree.nodes[tree
+                    .nodes
+                    .get_node_by_path(tree.root, selection[..depth].to_vec())];
+                assert_eq!(node.effective_visit_count(1), node.visit_count());
+            }
+        }
+    }
+
+    /// Prefers the first action, and thinks every position is worth 0.25
+    struct FixedPrior;
+
+    impl PriorProvider<InjectableGameState> for FixedPrior {
+        fn prior(&self, _state: &InjectableGameState, actions: &[InjectableGameAction]) -> Prior {
+            let mut policy = vec![0.1 / actions.len() as f64; actions.len()];
+            policy[0] += 0.9;
+            Prior {
+                policy,
+                value: Some(vec![0.25]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_prior_provider_guides_and_estimates() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(3),
+                InjectableGameAction::WinInXTurns(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node_with_priors(
+            root_state,
+            None,
+            Some(&FixedPrior),
+        ))
+        .with_prior_provider(Arc::new(FixedPrior))
+        .with_selection_policy(Arc::new(Puct));
+        assert!(tree.nodes.children(tree.root).next().unwrap().prior > Some(0.9));
+
+        tree.iterate();
+        let favoured = tree
+            .nodes
+            .get_child(tree.root, InjectableGameAction::WinInXTurns(3));
+        // Estimated, rather than played out to the win
+        assert_eq!(tree.nodes[favoured].value_sum(), 0.25);
+
+        // The other action's prior is too low for it to be tried yet
+        for _ in 0..2 {
+            tree.iterate();
+        }
+        assert_eq!(tree.nodes[favoured].visit_count(), 3);
+        assert_eq!(tree.nodes.children(tree.root).nth(1).unwrap().child(), None);
+    }
+
+    #[test]
+    fn test_value_weight_mixes_estimate_with_playout() {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(3),
+                InjectableGameAction::WinInXTurns(4),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node_with_priors(
+            root_state,
+            None,
+            Some(&FixedPrior),
+        ))
+        .with_prior_provider(Arc::new(FixedPrior))
+        .with_selection_policy(Arc::new(Puct))
+        .with_value_weight(0.5);
+
+        tree.iterate();
+        let favoured = tree
+            .nodes
+            .get_child(tree.root, InjectableGameAction::WinInXTurns(3));
+        // Half the estimate, and half the win it plays out to
+        assert_eq!(tree.nodes[favoured].value_sum(), 0.625);
+    }
 }
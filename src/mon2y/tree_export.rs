@@ -0,0 +1,430 @@
+use super::game::{Action, Actor, State};
+use super::node::{NodeArena, NodeId};
+use super::selection::ChildStats;
+use super::tree::Tree;
+use super::Reward;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+///
+/// Which parts of a search tree to export. With the defaults, that's all of
+/// it - including children that haven't been expanded.
+///
+#[derive(Debug, Clone, Default, PartialEq, clap::Args)]
+#[command(about = None, long_about = None, next_help_heading = "Tree export")]
+pub struct ExportOptions {
+    /// Levels of the tree to export below the root
+    #[arg(long("tree-max-depth"))]
+    pub max_depth: Option<usize>,
+    /// Leave out children visited fewer times than this (1 or more leaves out
+    /// the unexpanded ones)
+    #[arg(long("tree-min-visits"), default_value_t = 0)]
+    pub min_visits: u32,
+    /// Only export each node's most visited children
+    #[arg(long("tree-top-k"))]
+    pub top_k: Option<usize>,
+}
+
+impl ExportOptions {
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_min_visits(mut self, min_visits: u32) -> Self {
+        self.min_visits = min_visits;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+}
+
+///
+/// A node of an exported tree, with what the search knew about it
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportedNode {
+    /// The action leading here, as `{:?}` shows it (`None` at the root)
+    pub action: Option<String>,
+    /// The player to move here - `None` where the game acts, at the end of
+    /// the game, or if it's not expanded
+    pub player: Option<u8>,
+    /// Whether the game acts here (a chance node)
+    pub chance: bool,
+    /// Visits through the edge from the parent (all of the root's) - a node
+    /// shared between transpositions has visits through other parents too
+    pub visits: u32,
+    /// Mean value through the edge from the parent, for the player who chose
+    /// this node (`None` if unvisited)
+    pub value: Option<f64>,
+    /// Every player's mean reward (empty if unvisited)
+    pub rewards: Vec<Reward>,
+    /// The selection policy's score for this node, as its parent would pick
+    /// it - only for the children of player nodes
+    pub ucb: Option<f64>,
+    /// How likely the game is to pick this node, for the children of chance
+    /// nodes
+    pub weight: Option<u32>,
+    /// The action's prior, if it has one
+    pub prior: Option<f64>,
+    /// The reward for every player under perfect play, if it's known
+    pub proven: Option<Vec<Reward>>,
+    /// Not expanded yet - the tree only has the action that leads here
+    pub placeholder: bool,
+    pub children: Vec<ExportedNode>,
+    /// Children left out by the [`ExportOptions`] (or that lead back up the
+    /// tree, through transpositions)
+    pub omitted: usize,
+}
+
+///
+/// Export `tree` from its root, as `options` say. Scores come from the tree's
+/// selection policy and exploration constant.
+///
+pub fn export_tree<StateType, ActionType>(
+    tree: &Tree<StateType, ActionType>,
+    options: &ExportOptions,
+) -> ExportedNode
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let mut root = exported(&tree.nodes, tree.root);
+    let mut path = vec![tree.root];
+    export_children(tree, tree.root, options, &mut path, &mut root);
+    root
+}
+
+/// Everything about the node itself, but not how its parent sees it
+fn exported<StateType, ActionType>(
+    nodes: &NodeArena<StateType, ActionType>,
+    id: NodeId,
+) -> ExportedNode
+where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let node = &nodes[id];
+    let visits = node.visit_count();
+    ExportedNode {
+        action: None,
+        player: match node.state().next_actor() {
+            Actor::Player(player) if node.proven().is_none() => Some(player),
+            _ => None,
+        },
+        chance: node.game_action(),
+        visits,
+        value: (visits > 0).then(|| node.value_sum() / visits as f64),
+        rewards: nodes.mean_rewards(id),
+        ucb: None,
+        weight: None,
+        prior: None,
+        proven: node.proven().cloned(),
+        placeholder: false,
+        children: vec![],
+        omitted: 0,
+    }
+}
+
+fn export_children<StateType, ActionType>(
+    tree: &Tree<StateType, ActionType>,
+    id: NodeId,
+    options: &ExportOptions,
+    path: &mut Vec<NodeId>,
+    exported_node: &mut ExportedNode,
+) where
+    StateType: State<ActionType = ActionType>,
+    ActionType: Action<StateType = StateType>,
+{
+    let nodes = &tree.nodes;
+    let node = &nodes[id];
+    let edges = nodes.children(id);
+    let sibling_count = edges.len();
+    if options
+        .max_depth
+        .is_some_and(|max_depth| path.len() > max_depth)
+    {
+        exported_node.omitted = sibling_count;
+        return;
+    }
+    let parent_visits = node.visit_count().max(1) as f64;
+    let priors = tree.root_priors.as_deref().filter(|_| id == tree.root);
+    let mut children: Vec<(ExportedNode, Option<NodeId>)> = edges
+        .enumerate()
+        .map(|(i, edge)| {
+            let child = edge.child();
+            let mut exported_child = match child {
+                Some(child) => exported(nodes, child),
+                None => ExportedNode {
+                    action: None,
+                    player: None,
+                    chance: false,
+                    visits: 0,
+                    value: None,
+                    rewards: vec![],
+                    ucb: None,
+                    weight: None,
+                    prior: None,
+                    proven: None,
+                    placeholder: true,
+                    children: vec![],
+                    omitted: 0,
+                },
+            };
+            exported_child.action = Some(format!("{:?}", edge.action));
+            let visits = edge.visit_count();
+            exported_child.visits = visits;
+            exported_child.value = (visits > 0).then(|| edge.value_sum() / visits as f64);
+            if node.game_action() {
+                exported_child.weight = Some(edge.weight());
+            } else {
+                exported_child.prior = priors.map_or(edge.prior, |priors| priors.get(i).copied());
+                // As selection scores it, from the visits through this edge
+                let stats = ChildStats {
+                    visit_count: edge.visit_count() as f64,
+                    value_sum: edge.value_sum(),
+                    value_sq_sum: edge.value_sq_sum(),
+                    prior: exported_child.prior,
+                };
+                exported_child.ucb = Some(tree.selection_policy.score(
+                    &stats,
+                    parent_visits,
+                    sibling_count,
+                    tree.constant,
+                ));
+            }
+            (exported_child, child)
+        })
+        .filter(|(exported_child, _)| exported_child.visits >= options.min_visits)
+        .collect();
+    if let Some(top_k) = options.top_k {
+        // Stable, so ties stay in the game's order
+        children.sort_by_key(|(exported_child, _)| std::cmp::Reverse(exported_child.visits));
+        children.truncate(top_k);
+    }
+    exported_node.omitted = sibling_count - children.len();
+    for (mut exported_child, child) in children {
+        if let Some(child) = child {
+            // Can only happen with transpositions
+            if path.contains(&child) {
+                exported_node.omitted += 1;
+                continue;
+            }
+            path.push(child);
+            export_children(tree, child, options, path, &mut exported_child);
+            path.pop();
+        }
+        exported_node.children.push(exported_child);
+    }
+}
+
+impl ExportedNode {
+    ///
+    /// The tree as a Graphviz digraph called `name`. Player nodes are boxes
+    /// and chance nodes ellipses, proven nodes are bold, and unexpanded ones
+    /// are dashed.
+    ///
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut dot = format!("digraph \"{}\" {{\n", escape(name));
+        dot.push_str("    node [fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        self.write_dot(&mut dot, &mut next_id);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Add this node and everything under it, and return its id in the graph
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        let mut label = format!("n={}", self.visits);
+        if let Some(value) = self.value {
+            let _ = write!(label, " q={:.3}", value);
+        }
+        if let Some(ucb) = self.ucb {
+            let _ = write!(label, "\\nucb={:.3}", ucb);
+        }
+        if let Some(prior) = self.prior {
+            let _ = write!(label, " p={:.3}", prior);
+        }
+        if let Some(proven) = &self.proven {
+            let _ = write!(label, "\\nproven {:?}", proven);
+        }
+        if self.omitted > 0 {
+            let _ = write!(label, "\\n+{} more", self.omitted);
+        }
+        let mut style = vec![];
+        if self.placeholder {
+            style.push("dashed");
+        }
+        if self.proven.is_some() {
+            style.push("bold");
+        }
+        let _ = writeln!(
+            dot,
+            "    {} [label=\"{}\", shape={}, style=\"{}\"];",
+            id,
+            label,
+            if self.chance { "ellipse" } else { "box" },
+            style.join(",")
+        );
+        for child in self.children.iter() {
+            let child_id = child.write_dot(dot, next_id);
+            let mut edge_label = child.action.clone().unwrap_or_default();
+            if let Some(weight) = child.weight {
+                let _ = write!(edge_label, " ({})", weight);
+            }
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [label=\"{}\"];",
+                id,
+                child_id,
+                escape(&edge_label)
+            );
+        }
+        id
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+///
+/// How to write exported trees, from the file's extension: `.dot` or `.gv`
+/// for Graphviz, anything else for JSON
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeFormat {
+    Dot,
+    Json,
+}
+
+impl TreeFormat {
+    pub fn from_path(path: &Path) -> TreeFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dot") | Some("gv") => TreeFormat::Dot,
+            _ => TreeFormat::Json,
+        }
+    }
+}
+
+///
+/// Write `tree` to `out`, called `name`. Each call adds a graph of its own
+/// to a DOT file, or a line with `name` and the tree to a JSON one - so one
+/// file can hold every search of a game.
+///
+pub fn write_tree(
+    out: &mut impl Write,
+    tree: &ExportedNode,
+    name: &str,
+    format: TreeFormat,
+) -> std::io::Result<()> {
+    match format {
+        TreeFormat::Dot => out.write_all(tree.to_dot(name).as_bytes())?,
+        TreeFormat::Json => {
+            serde_json::to_writer(
+                &mut *out,
+                &serde_json::json!({ "name": name, "tree": tree }),
+            )?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mon2y::node::create_expanded_node;
+    use crate::test::injectable_game::{InjectableGameAction, InjectableGameState};
+
+    fn searched_tree() -> Tree<InjectableGameState, InjectableGameAction> {
+        let root_state = InjectableGameState {
+            injected_reward: vec![0.0],
+            injected_terminal: false,
+            injected_permitted_actions: vec![
+                InjectableGameAction::WinInXTurns(1),
+                InjectableGameAction::WinInXTurns(2),
+                InjectableGameAction::WinInXTurns(3),
+            ],
+            player_count: 1,
+            next_actor: Actor::Player(0),
+        };
+        let tree = Tree::new(create_expanded_node(root_state, None));
+        for _ in 0..2 {
+            tree.iterate();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_export_includes_placeholders_and_scores() {
+        let tree = searched_tree();
+        let exported = export_tree(&tree, &ExportOptions::default());
+        assert_eq!(exported.visits, 2);
+        assert_eq!(exported.player, Some(0));
+        assert_eq!(exported.children.len(), 3);
+        assert_eq!(exported.omitted, 0);
+        let expanded: Vec<_> = exported
+            .children
+            .iter()
+            .filter(|child| !child.placeholder)
+            .collect();
+        assert_eq!(expanded.len(), 2);
+        assert!(expanded.iter().all(|child| child.visits == 1));
+        assert!(exported.children.iter().all(|child| child.ucb.is_some()));
+        let placeholder = exported
+            .children
+            .iter()
+            .find(|child| child.placeholder)
+            .unwrap();
+        assert_eq!(placeholder.ucb, Some(f64::INFINITY));
+
+        let dot = exported.to_dot("test");
+        assert!(dot.starts_with("digraph \"test\" {"));
+        // Every node but the root has an edge to it
+        assert_eq!(
+            dot.matches(" -> ").count(),
+            dot.matches("shape=").count() - 1
+        );
+        assert!(dot.contains("label=\"WinInXTurns(1)\""));
+        assert!(dot.contains("style=\"dashed\""));
+    }
+
+    #[test]
+    fn test_export_takes_visits_from_edges() {
+        let tree = searched_tree();
+        let child = tree
+            .nodes
+            .get_child(tree.root, InjectableGameAction::WinInXTurns(1));
+        // As if shared, and also visited through another parent
+        tree.nodes.visit(child, -1.0, &[-1.0]);
+        let exported = export_tree(&tree, &ExportOptions::default().with_top_k(1));
+        let exported_child = &exported.children[0];
+        assert_eq!(exported_child.action.as_deref(), Some("WinInXTurns(1)"));
+        assert_eq!(exported_child.visits, 1);
+        assert_eq!(exported_child.value, Some(1.0));
+    }
+
+    #[test]
+    fn test_export_options_prune() {
+        let tree = searched_tree();
+        let visited = export_tree(&tree, &ExportOptions::default().with_min_visits(1));
+        assert_eq!(visited.children.len(), 2);
+        assert_eq!(visited.omitted, 1);
+
+        let top = export_tree(&tree, &ExportOptions::default().with_top_k(1));
+        assert_eq!(top.children.len(), 1);
+        assert_eq!(top.children[0].visits, 1);
+
+        let shallow = export_tree(&tree, &ExportOptions::default().with_max_depth(0));
+        assert!(shallow.children.is_empty());
+        assert_eq!(shallow.omitted, 3);
+    }
+}
@@ -1,14 +1,38 @@
+use super::game::{Actor, State};
+use super::rng::with_rng;
 use rand::Rng;
 
-pub fn weighted_random<T>(items: Vec<(T, u32)>) -> T {
+pub fn weighted_random<T: Clone>(items: &[(T, u32)]) -> T {
     let total_weight: u32 = items.iter().map(|(_, weight)| weight).sum();
-    let random = rand::thread_rng().gen_range(0..total_weight);
+    let random = with_rng(|rng| rng.gen_range(0..total_weight));
     let mut current_weight = 0;
     for (item, weight) in items {
         current_weight += weight;
         if current_weight > random {
-            return item;
+            return item.clone();
         }
     }
     unreachable!()
 }
+
+///
+/// An outcome drawn by a state whose game actions are sampled rather than
+/// listed (see [`State::sample_game_action`]). `None` if the state can't
+/// draw one.
+///
+pub fn sample_game_action<StateType: State>(state: &StateType) -> Option<StateType::ActionType> {
+    with_rng(|rng| state.sample_game_action(rng)).map(|(action, _)| action)
+}
+
+///
+/// The game's action from `state`, for either kind of chance - picked by
+/// weight from the outcomes listed, or sampled by the state. `None` if it's
+/// a player's turn, or the state can't sample one.
+///
+pub fn random_game_action<StateType: State>(state: &StateType) -> Option<StateType::ActionType> {
+    match state.next_actor() {
+        Actor::Player(_) => None,
+        Actor::GameAction(actions) => Some(weighted_random(&actions)),
+        Actor::SampledGameAction => sample_game_action(state),
+    }
+}
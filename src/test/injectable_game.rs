@@ -1,4 +1,7 @@
 use crate::mon2y::game::{Action, Actor, State};
+use rand::{Rng, RngCore};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 ///
 /// A generic test game that can have injected reward, terminal state, and permitted actions
@@ -28,6 +31,39 @@ impl State for InjectableGameState {
     fn terminal(&self) -> bool {
         return self.injected_terminal;
     }
+
+    /// What [`InjectableGameAction::Lose`] and [`InjectableGameAction::Win`] give
+    fn reward_range(&self) -> Option<(f64, f64)> {
+        Some((-1.0, 1.0))
+    }
+
+    /// Everything but the reward, so tests can share transpositions
+    fn state_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.injected_terminal.hash(&mut hasher);
+        self.injected_permitted_actions.hash(&mut hasher);
+        self.player_count.hash(&mut hasher);
+        match &self.next_actor {
+            Actor::Player(player) => Some(*player).hash(&mut hasher),
+            _ => None::<u8>.hash(&mut hasher),
+        }
+        Some(hasher.finish())
+    }
+
+    /// Sampled game actions are drawn evenly from the permitted actions
+    fn sample_game_action(
+        &self,
+        rng: &mut dyn RngCore,
+    ) -> Option<(InjectableGameAction, Option<f64>)> {
+        let actions = &self.injected_permitted_actions;
+        if actions.is_empty() {
+            return None;
+        }
+        Some((
+            actions[rng.gen_range(0..actions.len())].clone(),
+            Some(1.0 / actions.len() as f64),
+        ))
+    }
 }
 
 #[derive(Hash, Clone, Eq, PartialEq, Debug)]
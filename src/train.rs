@@ -0,0 +1,277 @@
+//! Trains a network on self-play, each generation searching with the last
+//! generation's network for priors and values
+use mon2y_rs::{game, games, mon2y};
+
+use clap::Parser;
+use game::Game;
+use games::Games;
+use games::{C4, CS, EBR, NT};
+use mon2y::game::{final_reward, Action, Actor, State};
+use mon2y::network::{Network, NetworkPriors, Sample};
+use mon2y::rng;
+use mon2y::selection::TreePolicy;
+use mon2y::weighted_random::random_game_action;
+use mon2y::{BestTurnPolicy, Search, SearchConfig};
+use rand::seq::SliceRandom;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+// Self-play searches less than a game turn, and PUCT follows the priors
+#[command(mut_arg("iterations", |arg| arg.default_value("200")))]
+#[command(mut_arg("threads", |arg| arg.default_value("1")))]
+#[command(mut_arg("selection", |arg| arg.default_value("puct")))]
+struct Args {
+    #[arg()]
+    game: Games,
+    #[command(flatten)]
+    verbose: clap_verbosity_flag::Verbosity,
+    #[command(flatten)]
+    search: SearchConfig,
+    #[arg(short, long, default_value_t = 3)]
+    player_count: u8,
+    /// Rounds of self-play then training
+    #[arg(long, default_value_t = 10)]
+    generations: usize,
+    /// Self-play games each generation
+    #[arg(long, default_value_t = 50)]
+    games: usize,
+    /// Moves at the start of each game that are sampled in proportion to
+    /// their visits, rather than the most visited played
+    #[arg(long, default_value_t = 8)]
+    sampled_moves: usize,
+    /// Sizes of the network's hidden layers
+    #[arg(long, value_delimiter = ',', default_value = "32,32")]
+    hidden: Vec<usize>,
+    /// Passes over the samples each generation. The positions of a game
+    /// share its outcome, so more passes soon learn the games rather than
+    /// the positions.
+    #[arg(long, default_value_t = 2)]
+    epochs: usize,
+    #[arg(long, default_value_t = 32)]
+    batch_size: usize,
+    #[arg(long, default_value_t = 0.02)]
+    learning_rate: f64,
+    #[arg(long, default_value_t = 1e-3)]
+    weight_decay: f64,
+    /// Generations of samples trained on (the latest, and those before it)
+    #[arg(long, default_value_t = 4)]
+    replay: usize,
+    /// Network to start from, rather than an untrained one
+    #[arg(long("from"))]
+    from: Option<PathBuf>,
+    /// Where the network is saved after each generation
+    #[arg(short, long, default_value = "network.json")]
+    out: PathBuf,
+    /// Seed for self-play and the network's starting weights
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+///
+/// Play one game against itself, searching with `network` if there is one,
+/// and return a sample for each player decision
+///
+fn self_play<G: Game>(
+    game: &G,
+    args: &Args,
+    network: Option<&Arc<Network>>,
+    seed: u64,
+) -> Vec<Sample> {
+    let mut state = game.init_game();
+    let search = Search::new(args.search.clone()).with_seed(seed);
+    let mut search = match network {
+        Some(network) => search.with_prior_provider(Arc::new(NetworkPriors::new(network.clone()))),
+        None => search,
+    };
+    // Samples still waiting for the reward, with the player they were for
+    let mut pending: Vec<(u8, Vec<f64>, Vec<f64>)> = vec![];
+    let mut moves = 0;
+    while !state.terminal() {
+        let action = match state.next_actor() {
+            Actor::Player(player) => {
+                let result = search
+                    .calculate_best_turn(state.clone())
+                    .unwrap_or_else(|e| panic!("Invalid search config: {}", e));
+                let mut policy = vec![0.0; state.action_slots()];
+                for stats in result.actions.iter() {
+                    if let Some(slot) = state.action_index(&stats.action) {
+                        policy[slot] = stats.visits as f64;
+                    }
+                }
+                let total: f64 = policy.iter().sum();
+                if total > 0.0 {
+                    pending.push((
+                        player,
+                        state.features().unwrap(),
+                        policy.iter().map(|visits| visits / total).collect(),
+                    ));
+                }
+                moves += 1;
+                if moves <= args.sampled_moves {
+                    let children: Vec<_> = result
+                        .actions
+                        .iter()
+                        .map(|stats| (stats.action.clone(), stats.visits, 0.0))
+                        .collect();
+                    BestTurnPolicy::Temperature.choose(&children, 0.0, 1.0)
+                } else {
+                    result.action
+                }
+            }
+            Actor::GameAction(_) | Actor::SampledGameAction => {
+                let Some(action) = random_game_action(&state) else {
+                    log::warn!("No game action could be sampled - ending the game");
+                    break;
+                };
+                action
+            }
+        };
+        state = action.execute(&state);
+        search.advance(&action);
+    }
+    let reward = final_reward(&state);
+    let players = reward.len();
+    pending
+        .into_iter()
+        .map(|(player, features, policy)| Sample {
+            features,
+            policy,
+            // Turned so the player to move comes first
+            value: (0..players)
+                .map(|seat| reward[(player as usize + seat) % players])
+                .collect(),
+        })
+        .collect()
+}
+
+///
+/// The value loss of always guessing each seat's mean reward over
+/// `samples` - a network's value head is only any use if it does better
+///
+fn mean_guess_loss(samples: &[Sample]) -> f64 {
+    let Some(first) = samples.first() else {
+        return 0.0;
+    };
+    let count = samples.len() as f64;
+    (0..first.value.len())
+        .map(|seat| {
+            let mean = samples.iter().map(|sample| sample.value[seat]).sum::<f64>() / count;
+            samples
+                .iter()
+                .map(|sample| (sample.value[seat] - mean).powi(2))
+                .sum::<f64>()
+                / count
+        })
+        .sum()
+}
+
+fn train<G: Game>(game: G, args: &Args) {
+    let first_state = game.init_game();
+    let Some(features) = first_state.features() else {
+        eprintln!("This game has no features to train a network on");
+        std::process::exit(1);
+    };
+    let first_seed = args.seed.unwrap_or_else(rand::random);
+    rng::seed_thread(first_seed);
+
+    let mut network = args
+        .from
+        .as_ref()
+        .map(|path| Network::load(path).expect("Failed to load network"));
+    // An untrained network's guesses are no use to search with, so the first
+    // generation searches without one
+    let mut searching_with = network.clone().map(Arc::new);
+    let mut replay: Vec<Vec<Sample>> = vec![];
+
+    for generation in 0..args.generations {
+        let started = std::time::Instant::now();
+        let samples: Vec<Sample> = (0..args.games)
+            .flat_map(|episode| {
+                let seed = rng::derive_seed(first_seed, (generation * args.games + episode) as u64);
+                self_play(&game, args, searching_with.as_ref(), seed)
+            })
+            .collect();
+        let sample_count = samples.len();
+        // The last generation's network hasn't seen these games, so this is
+        // how well it generalises - compared with always guessing the mean
+        let held_out = network.as_ref().map(|network| network.loss(&samples));
+        let mean_guess = mean_guess_loss(&samples);
+        // Sized by the rewards, which have every player in them
+        let network = network.get_or_insert_with(|| {
+            Network::new(
+                features.len(),
+                &args.hidden,
+                first_state.action_slots(),
+                samples.first().map_or(0, |sample| sample.value.len()),
+            )
+        });
+        replay.push(samples);
+        if replay.len() > args.replay {
+            replay.remove(0);
+        }
+
+        let mut training: Vec<&Sample> = replay.iter().flatten().collect();
+        for _ in 0..args.epochs {
+            rng::with_rng(|rng| training.shuffle(rng));
+            for batch in training.chunks(args.batch_size.max(1)) {
+                let batch: Vec<Sample> = batch.iter().map(|sample| (*sample).clone()).collect();
+                network.train(&batch, args.learning_rate, args.weight_decay);
+            }
+        }
+        network.save(&args.out).expect("Failed to save network");
+        let training: Vec<Sample> = training.into_iter().cloned().collect();
+        let (policy_loss, value_loss) = network.loss(&training);
+        println!(
+            "Generation {}: {} samples ({} trained on), {:.1} seconds",
+            generation,
+            sample_count,
+            training.len(),
+            started.elapsed().as_secs_f64()
+        );
+        if let Some((policy_loss, value_loss)) = held_out {
+            println!(
+                "  New games: policy loss {:.4}, value loss {:.4} (guessing the mean {:.4})",
+                policy_loss, value_loss, mean_guess
+            );
+        }
+        println!(
+            "  Trained on: policy loss {:.4}, value loss {:.4}",
+            policy_loss, value_loss
+        );
+        searching_with = Some(Arc::new(network.clone()));
+    }
+    println!("Saved to {}", args.out.display());
+}
+
+fn main() {
+    let args = Args::parse();
+    env_logger::Builder::new()
+        .filter_level(args.verbose.log_level_filter())
+        .init();
+    if args.search.selection != TreePolicy::Puct {
+        log::warn!("Only PUCT selection makes use of the network's priors");
+    }
+    match args.game {
+        Games::C4 => train(C4, &args),
+        Games::NT => train(
+            NT {
+                player_count: args.player_count,
+            },
+            &args,
+        ),
+        Games::CS => train(
+            CS {
+                player_count: args.player_count,
+            },
+            &args,
+        ),
+        Games::EBR => train(
+            EBR {
+                player_count: args.player_count,
+            },
+            &args,
+        ),
+    }
+}
@@ -2,14 +2,22 @@ use mon2y_rs::game::Game;
 use mon2y_rs::games::c4;
 use mon2y_rs::games::c4::C4;
 use mon2y_rs::mon2y::game::{Action, State};
+use mon2y_rs::mon2y::network::{Network, NetworkPriors};
 use mon2y_rs::mon2y::node::create_expanded_node;
-use mon2y_rs::mon2y::tree::Tree;
-use mon2y_rs::mon2y::{calculate_best_turn, BestTurnPolicy};
+use mon2y_rs::mon2y::prior::{PriorProvider, Priors};
+use mon2y_rs::mon2y::rollout::{
+    play_out, EpsilonGreedyRollout, HeavyRollout, RolloutPolicy, UniformRollout,
+};
+use mon2y_rs::mon2y::selection::TreePolicy;
+use mon2y_rs::mon2y::tree::{Selection, Tree};
+use mon2y_rs::mon2y::{calculate_best_turn, Parallelism, Search, SearchConfig};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 #[test]
 fn test_c4_one_action_blocks_win() {
     let mut c4_state = C4.init_game();
-    for action in vec![
+    for action in [
         c4::C4Action::Drop(0),
         c4::C4Action::Drop(1),
         c4::C4Action::Drop(0),
@@ -19,21 +27,18 @@ fn test_c4_one_action_blocks_win() {
         c4_state = action.execute(&c4_state);
     }
     let action = calculate_best_turn(
-        100,
-        None,
-        1,
         c4_state,
-        BestTurnPolicy::MostVisits,
-        2.0_f64.sqrt(),
-        false,
-    );
+        &SearchConfig::default().with_iterations(100).with_threads(1),
+    )
+    .unwrap()
+    .action;
     assert_eq!(action, c4::C4Action::Drop(0));
 }
 
 #[test]
 fn test_c4_one_action_gets_win() {
     let mut c4_state = C4.init_game();
-    for action in vec![
+    for action in [
         c4::C4Action::Drop(3),
         c4::C4Action::Drop(1),
         c4::C4Action::Drop(3),
@@ -44,14 +49,11 @@ fn test_c4_one_action_gets_win() {
         c4_state = action.execute(&c4_state);
     }
     let action = calculate_best_turn(
-        100,
-        None,
-        1,
         c4_state,
-        BestTurnPolicy::MostVisits,
-        2.0_f64.sqrt(),
-        false,
-    );
+        &SearchConfig::default().with_iterations(100).with_threads(1),
+    )
+    .unwrap()
+    .action;
     assert_eq!(action, c4::C4Action::Drop(3));
 }
 
@@ -59,7 +61,7 @@ fn test_c4_one_action_gets_win() {
 fn test_c4_play_out_repeated() {
     env_logger::init();
     let mut c4_state = C4.init_game();
-    for action in vec![
+    for action in [
         c4::C4Action::Drop(3),
         c4::C4Action::Drop(1),
         c4::C4Action::Drop(3),
@@ -74,9 +76,7 @@ fn test_c4_play_out_repeated() {
     let mut p0_wins = 0.0;
     let mut p1_wins = 0.0;
     for _ in 0..1000 {
-        let root_ref = tree.root.clone();
-        let root = root_ref.read().unwrap();
-        let result = tree.play_out(root.state().clone());
+        let result = tree.play_out(tree.root_node().state().clone());
         if result[0] > 0.0 {
             p0_wins += 1.0;
         };
@@ -93,14 +93,11 @@ fn test_c4_plays_through_without_crash() {
     while !c4_state.terminal() {
         if let mon2y_rs::mon2y::game::Actor::Player(_) = c4_state.next_actor() {
             let action = calculate_best_turn(
-                100,
-                None,
-                1,
                 c4_state.clone(),
-                BestTurnPolicy::MostVisits,
-                2.0_f64.sqrt(),
-                false,
-            );
+                &SearchConfig::default().with_iterations(100).with_threads(1),
+            )
+            .unwrap()
+            .action;
             c4_state = action.execute(&c4_state);
         }
     }
@@ -111,14 +108,11 @@ fn test_c4_plays_through_multiple_threads_without_crash() {
     while !c4_state.terminal() {
         if let mon2y_rs::mon2y::game::Actor::Player(_) = c4_state.next_actor() {
             let action = calculate_best_turn(
-                100,
-                None,
-                4,
                 c4_state.clone(),
-                BestTurnPolicy::MostVisits,
-                2.0_f64.sqrt(),
-                false,
-            );
+                &SearchConfig::default().with_iterations(100).with_threads(4),
+            )
+            .unwrap()
+            .action;
             c4_state = action.execute(&c4_state);
         }
     }
@@ -129,7 +123,7 @@ fn test_c4_full_exploration() {
     // This is more of a test that it doesn't freeze when getting fully explored
     // is very likely.
     let mut c4_state = C4.init_game();
-    for action in vec![
+    for action in [
         c4::C4Action::Drop(3),
         c4::C4Action::Drop(3),
         c4::C4Action::Drop(3),
@@ -157,12 +151,240 @@ fn test_c4_full_exploration() {
     }
 
     calculate_best_turn(
-        100000,
-        None,
-        8,
         c4_state,
-        BestTurnPolicy::MostVisits,
-        2.0_f64.sqrt(),
-        false,
+        &SearchConfig::default()
+            .with_iterations(100000)
+            .with_threads(8),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_c4_plays_through_reusing_tree_without_crash() {
+    let mut c4_state = C4.init_game();
+    let mut search = Search::new(SearchConfig::default().with_iterations(100).with_threads(2));
+    while !c4_state.terminal() {
+        let action = search.calculate_best_turn(c4_state.clone()).unwrap().action;
+        c4_state = action.execute(&c4_state);
+        search.advance(&action);
+    }
+}
+
+#[test]
+fn test_c4_transpositions_share_nodes() {
+    let c4_state = C4.init_game();
+    let tree = Tree::new(create_expanded_node(c4_state, None))
+        .with_transpositions(Arc::new(RwLock::new(HashMap::new())));
+    let path_a = vec![
+        c4::C4Action::Drop(0),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(2),
+    ];
+    let path_b = vec![
+        c4::C4Action::Drop(2),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(0),
+    ];
+    tree.expansion(&Selection::Selection(path_a.clone()));
+    tree.expansion(&Selection::Selection(path_b.clone()));
+
+    let node_a = tree.nodes.get_node_by_path(tree.root, path_a);
+    let node_b = tree.nodes.get_node_by_path(tree.root, path_b);
+    assert_eq!(node_a, node_b);
+}
+
+#[test]
+fn test_c4_plays_through_with_transpositions_without_crash() {
+    let mut c4_state = C4.init_game();
+    let mut search = Search::new(
+        SearchConfig::default()
+            .with_iterations(200)
+            .with_threads(2)
+            .with_transpositions(),
+    );
+    while !c4_state.terminal() {
+        let action = search.calculate_best_turn(c4_state.clone()).unwrap().action;
+        c4_state = action.execute(&c4_state);
+        search.advance(&action);
+    }
+}
+
+#[test]
+fn test_c4_heavy_playout_takes_win_and_blocks_loss() {
+    let mut c4_state = C4.init_game();
+    for action in [
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(3),
+    ] {
+        c4_state = action.execute(&c4_state);
+    }
+    // Player 1 has to block
+    let actions = c4_state.permitted_actions();
+    assert_eq!(
+        HeavyRollout.choose_action(&c4_state, &actions),
+        c4::C4Action::Drop(3)
+    );
+
+    c4_state = c4::C4Action::Drop(5).execute(&c4_state);
+    // Player 0 can take the win
+    let actions = c4_state.permitted_actions();
+    assert_eq!(
+        HeavyRollout.choose_action(&c4_state, &actions),
+        c4::C4Action::Drop(3)
+    );
+}
+
+#[test]
+fn test_c4_epsilon_greedy_prefers_centre() {
+    let c4_state = C4.init_game();
+    let actions = c4_state.permitted_actions();
+    let rollout = EpsilonGreedyRollout { epsilon: 0.0 };
+    assert_eq!(
+        rollout.choose_action(&c4_state, &actions),
+        c4::C4Action::Drop(3)
     );
 }
+
+#[test]
+fn test_c4_solver_proves_double_threat() {
+    let mut c4_state = C4.init_game();
+    for action in [
+        c4::C4Action::Drop(2),
+        c4::C4Action::Drop(6),
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(6),
+    ] {
+        c4_state = action.execute(&c4_state);
+    }
+    // Dropping in 1 or 4 leaves player 0 with an open three, which player 1
+    // can only block one end of
+    let tree = Tree::new(create_expanded_node(c4_state.clone(), None));
+    for _ in 0..100000 {
+        if tree.iterate() == Selection::FullyExplored {
+            break;
+        }
+    }
+    assert_eq!(tree.root_node().proven(), Some(&vec![1.0, -1.0]));
+
+    let action = calculate_best_turn(
+        c4_state,
+        &SearchConfig::default()
+            .with_iterations(100000)
+            .with_threads(1),
+    )
+    .unwrap()
+    .action;
+    assert!(
+        [c4::C4Action::Drop(1), c4::C4Action::Drop(4)].contains(&action),
+        "Played {:?}",
+        action
+    );
+}
+
+#[test]
+fn test_c4_root_parallel_gets_win() {
+    let mut c4_state = C4.init_game();
+    for action in [
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(3),
+        c4::C4Action::Drop(1),
+    ] {
+        c4_state = action.execute(&c4_state);
+    }
+    let action = Search::new(
+        SearchConfig::default()
+            .with_iterations(400)
+            .with_threads(4)
+            .with_parallelism(Parallelism::RootParallel),
+    )
+    .calculate_best_turn(c4_state)
+    .unwrap()
+    .action;
+    assert_eq!(action, c4::C4Action::Drop(3));
+}
+
+#[test]
+fn test_c4_evaluates_open_threes() {
+    let mut c4_state = C4.init_game();
+    for action in [
+        c4::C4Action::Drop(0),
+        c4::C4Action::Drop(6),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(6),
+        c4::C4Action::Drop(2),
+    ] {
+        c4_state = action.execute(&c4_state);
+    }
+    let evaluation = c4_state.evaluate().unwrap();
+    assert!(evaluation[0] > 0.0 && evaluation[0] < 1.0);
+    assert_eq!(evaluation[1], -evaluation[0]);
+    assert_eq!(play_out(c4_state, &UniformRollout, Some(0)), evaluation);
+}
+
+#[test]
+fn test_c4_puct_with_priors_blocks_edge_win() {
+    // The block is in the column the priors like least
+    let mut c4_state = C4.init_game();
+    for action in [
+        c4::C4Action::Drop(0),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(0),
+        c4::C4Action::Drop(1),
+        c4::C4Action::Drop(0),
+    ] {
+        c4_state = action.execute(&c4_state);
+    }
+    let action = calculate_best_turn(
+        c4_state,
+        &SearchConfig::default()
+            .with_iterations(400)
+            .with_threads(1)
+            .with_selection(TreePolicy::Puct)
+            .with_priors(Priors::Game)
+            .with_dirichlet_noise(0.3, 0.25),
+    )
+    .unwrap()
+    .action;
+    assert_eq!(action, c4::C4Action::Drop(0));
+}
+
+#[test]
+fn test_c4_network_priors_follow_the_mover() {
+    let c4_state = c4::C4Action::Drop(3).execute(&C4.init_game());
+    let features = c4_state.features().unwrap();
+    assert_eq!(features.len(), 2 * c4::BOARD_WIDTH * c4::BOARD_HEIGHT);
+    // The piece just dropped is the opponent's, from the mover's view
+    assert_eq!(features.iter().sum::<f64>(), 1.0);
+    assert_eq!(
+        features[c4::BOARD_WIDTH * c4::BOARD_HEIGHT..]
+            .iter()
+            .sum::<f64>(),
+        1.0
+    );
+
+    let network = Network::new(features.len(), &[16], c4_state.action_slots(), 2);
+    let (policy, value) = network.predict(&features);
+    let actions = c4_state.permitted_actions();
+    let prior = NetworkPriors::new(Arc::new(network)).prior(&c4_state, &actions);
+    assert!((prior.policy.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert!((prior.policy[3] - policy[3]).abs() < 1e-9);
+    // Player 1 is to move, so comes first in the network's values
+    assert_eq!(prior.value, Some(vec![value[1], value[0]]));
+}
+
+#[test]
+fn test_c4_trained_network_fits_the_game() {
+    // The network c4_network_arena.json plays with
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("models/c4_network.json");
+    let network = Network::load(&path).unwrap();
+    let c4_state = C4.init_game();
+    assert_eq!(network.input_size(), c4_state.features().unwrap().len());
+    assert_eq!(network.policy_size(), c4_state.action_slots());
+    assert_eq!(network.players(), 2);
+}